@@ -1,7 +1,12 @@
+pub mod categories;
 pub mod csv2db;
 pub mod http;
 pub mod handlers;
+pub mod manual_tags;
+pub mod reconciliation;
+pub mod suggestions;
 pub mod tagging;
+pub mod watch;
 
 pub mod utils {
     use std::collections::HashMap;
@@ -21,7 +26,7 @@ pub mod utils {
         for e in input.iter() {
             grouped
                 .entry(key_fn(e))
-                .or_insert_with(Vec::new)
+                .or_default()
                 .push(value_fn(e));
         }
         grouped
@@ -0,0 +1,175 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use itertools::Itertools;
+
+use crate::db::{ArcMutDB, DBActions};
+use crate::errors::RequestError;
+use crate::models::DateRange;
+use crate::models::tagging::{MonthAmount, PATH_SEPARATOR, Tag, TagDefinition, TagNode, TaggedAmount};
+use crate::money::Money;
+
+/**
+ * The tags with the amounts of their activities rolled up to their parents: the tag of the path (ie. "LOGEMENT/ENERGIE")
+ * and its sub tags, or all the tags from the ones without parent
+ */
+pub fn tag_tree<T: DBActions>(arc_db: ArcMutDB<T>, path: Option<&str>, account_id: Option<u32>, range: &DateRange) -> anyhow::Result<Vec<TagNode>> {
+    let (tags, tagged_amounts) = {
+        let db = arc_db.lock().unwrap();
+        (db.get_tags()?, db.get_tagged_amounts(account_id, range)?)
+    };
+    build_tree(&tags, &tagged_amounts, path)
+}
+
+/**
+ * Create a tag, under its parent if any
+ */
+pub fn add_tag<T: DBActions>(arc_db: ArcMutDB<T>, definition: &TagDefinition) -> anyhow::Result<Tag> {
+    let db = arc_db.lock().unwrap();
+    let id = db.create_tag(&definition.tag, definition.parent_id)?;
+    find_tag(&*db, id)
+}
+
+/**
+ * Rename a tag or move it under another parent (none to make it a root). The activities keep it:
+ * they are tagged by the rules and by hand with its id.
+ */
+pub fn edit_tag<T: DBActions>(arc_db: ArcMutDB<T>, id: u32, definition: &TagDefinition) -> anyhow::Result<Tag> {
+    let db = arc_db.lock().unwrap();
+    db.update_tag(id, &definition.tag, definition.parent_id)?;
+    find_tag(&*db, id)
+}
+
+/**
+ * Remove a tag given by no rule and without sub tags, returns it
+ */
+pub fn remove_tag<T: DBActions>(arc_db: ArcMutDB<T>, id: u32) -> anyhow::Result<Tag> {
+    let mut db = arc_db.lock().unwrap();
+    let tag = find_tag(&*db, id)?;
+    db.delete_tag(id)?;
+    Ok(tag)
+}
+
+fn find_tag<T: DBActions>(db: &T, id: u32) -> anyhow::Result<Tag> {
+    db.get_tags()?.into_iter().find(|t| t.id == id).ok_or_else(|| RequestError::NotFound(format!("Unknown tag {}", id)).into())
+}
+
+/**
+ * Activity id to its amount and month
+ */
+type ActivityAmounts = BTreeMap<u32, (Money, u32)>;
+
+struct Tree<'a> {
+    children: HashMap<Option<u32>, Vec<&'a Tag>>,
+    amounts: HashMap<u32, Vec<&'a TaggedAmount>>,
+}
+
+impl<'a> Tree<'a> {
+    fn children_of(&self, parent_id: Option<u32>) -> &[&'a Tag] {
+        self.children.get(&parent_id).map(|c| c.as_slice()).unwrap_or_default()
+    }
+
+    fn node(&self, tag: &Tag, parent_path: Option<&str>) -> (TagNode, ActivityAmounts) {
+        let path = match parent_path {
+            Some(parent_path) => format!("{}{}{}", parent_path, PATH_SEPARATOR, tag.tag),
+            None => tag.tag.clone(),
+        };
+
+        let mut activities: ActivityAmounts = BTreeMap::new();
+        for tagged in self.amounts.get(&tag.id).into_iter().flatten() {
+            activities.insert(tagged.activity_id, (tagged.amount, tagged.month_year));
+        }
+        let mut children: Vec<TagNode> = Vec::new();
+        for child in self.children_of(Some(tag.id)) {
+            let (node, child_activities) = self.node(child, Some(&path));
+            activities.extend(child_activities);
+            children.push(node);
+        }
+
+        let months: Vec<MonthAmount> = activities
+            .values()
+            .map(|(amount, month_year)| (*month_year, *amount))
+            .into_group_map()
+            .into_iter()
+            .map(|(month_year, amounts)| MonthAmount { month_year, amount: amounts.into_iter().sum() })
+            .sorted_by_key(|m| m.month_year)
+            .collect();
+
+        let node = TagNode {
+            id: tag.id,
+            tag: tag.tag.clone(),
+            path,
+            amount: months.iter().map(|m| m.amount).sum(),
+            months,
+            children,
+        };
+        (node, activities)
+    }
+}
+
+pub fn build_tree(tags: &[Tag], tagged_amounts: &[TaggedAmount], path: Option<&str>) -> anyhow::Result<Vec<TagNode>> {
+    // A tag whose parent is unknown is a root
+    let ids: HashSet<u32> = tags.iter().map(|t| t.id).collect();
+    let tree = Tree {
+        children: tags
+            .iter()
+            .sorted_by(|a, b| a.tag.cmp(&b.tag))
+            .map(|t| (t.parent_id.filter(|p| ids.contains(p)), t))
+            .into_group_map(),
+        amounts: tagged_amounts.iter().map(|t| (t.tag_id, t)).into_group_map(),
+    };
+
+    let path = match path.map(str::trim).filter(|p| !p.is_empty()) {
+        Some(path) => path,
+        None => return Ok(tree.children_of(None).iter().map(|t| tree.node(t, None).0).collect()),
+    };
+
+    let mut parent: Option<&Tag> = None;
+    let mut parent_path: Option<String> = None;
+    for name in path.split(PATH_SEPARATOR).map(str::trim) {
+        let tag = tree
+            .children_of(parent.map(|p| p.id))
+            .iter()
+            .find(|t| t.tag.to_lowercase() == name.to_lowercase())
+            .ok_or_else(|| RequestError::NotFound(format!("Unknown tag path '{}'", path)))?;
+        if let Some(parent) = parent {
+            parent_path = Some(match parent_path {
+                Some(parent_path) => format!("{}{}{}", parent_path, PATH_SEPARATOR, parent.tag),
+                None => parent.tag.clone(),
+            });
+        }
+        parent = Some(tag);
+    }
+
+    Ok(parent.map(|tag| tree.node(tag, parent_path.as_deref()).0).into_iter().collect())
+}
+
+#[test]
+fn test_tag_tree() -> anyhow::Result<()> {
+    use crate::actions::manual_tags::set_manual_tags;
+    use crate::db::sqlite::tests::tagged_test_db;
+    use crate::models::tagging::TagOverride;
+
+    let arc_db = tagged_test_db()?;
+
+    // An activity tagged twice in the tree counts once
+    let edf = arc_db.lock().unwrap().get_activities(None)?.into_iter().find(|a| a.statement.contains("EDF")).unwrap();
+    set_manual_tags(arc_db.clone(), edf.row_id.unwrap(), &[TagOverride { tag: "LOYER".to_string(), assigned: true }])?;
+    let edf_month: u32 = edf.date.format("%Y%m").to_string().parse()?;
+
+    let roots = tag_tree(arc_db.clone(), None, None, &DateRange::default())?;
+    let housing = roots.iter().find(|n| n.tag == "LOGEMENT").expect("LOGEMENT should be a root");
+    assert!(!roots.iter().any(|n| n.tag == "EDF"), "A sub tag should not be a root");
+    assert_eq!(housing.amount, "-674.00".parse()?, "The amounts of the sub tags should be rolled up once");
+    assert_eq!(housing.children.iter().map(|n| (n.tag.as_str(), n.amount.to_string())).collect_vec(), vec![("ENERGIE", "-74.00".to_string()), ("LOYER", format!("{}", Money::from_cents(-60000) + edf.amount))]);
+    assert_eq!(housing.months.iter().map(|m| m.amount).sum::<Money>(), housing.amount, "The months should add up to the total");
+
+    let energy = tag_tree(arc_db.clone(), Some("logement/Energie"), None, &DateRange::default())?;
+    assert_eq!((energy[0].path.as_str(), energy[0].children[0].path.as_str()), ("LOGEMENT/ENERGIE", "LOGEMENT/ENERGIE/EDF"), "Wrong paths");
+    assert_eq!(energy[0].months.iter().find(|m| m.month_year == edf_month).map(|m| m.amount), Some(edf.amount), "Wrong amount of the month");
+    assert!(tag_tree(arc_db.clone(), Some("ENERGIE"), None, &DateRange::default()).is_err(), "A path should start from a root");
+
+    let stats = arc_db.lock().unwrap().get_stats_tag_per_month(&["LOGEMENT".to_string()], None)?;
+    assert_eq!(stats.iter().map(|s| s.amount).sum::<Money>(), "674.00".parse()?, "The stats of a tag should include its sub tags");
+
+    Ok(())
+}
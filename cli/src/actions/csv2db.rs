@@ -1,50 +1,531 @@
-use std::path::Path;
+use std::path::{Path, PathBuf};
+
+use chrono::Local;
+use sha2::{Digest, Sha256};
 
 use crate::db::DBActions;
+use crate::errors::RequestError;
+use crate::csv::archive::{is_archive_file, read_archive_content};
 use crate::csv::dir::list_files;
 use crate::csv::parsing::*;
+use crate::ofx::parsing::parse_ofx;
+use crate::qif::parsing::parse_qif;
+use crate::camt::parsing::parse_camt;
+use crate::pdf::parsing::parse_pdf;
+use crate::label::parsing::fill_details;
 use crate::models::{AccountActivity, AccountBalance, BankingStatement};
+use crate::models::tagging::TagOverride;
+use crate::models::import::{FailedFile, FileReport, ImportMode, ImportOptions, ImportReport, ImportedFile, RejectedRow};
 
 use crate::db::ArcMutDB;
 
-pub fn csv2db<T: DBActions, P: AsRef<Path>>(dir_path: P, arc_db : ArcMutDB<T>) -> anyhow::Result<()> {
-    //-----  Get all csv statements ------------
-    let statement_files = list_files(
-        dir_path.as_ref(), 
-        Some("csv")
-    )?;
+/**
+ * Extensions of the statement files that can be imported
+ */
+const STATEMENT_EXTENSIONS: [&str; 6] = ["csv", "ofx", "qfx", "qif", "xml", "pdf"];
+
+pub fn is_statement_file<P: AsRef<Path>>(file_path: P) -> bool {
+    match file_path.as_ref().extension().and_then(|ex| ex.to_str()) {
+        Some(ex) => STATEMENT_EXTENSIONS.contains(&ex.to_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/**
+ * A statement to import: a file, or a member of the archive `path`
+ */
+struct StatementSource {
+    path: String,
+    member: Option<String>,
+    bytes: Vec<u8>,
+}
+
+impl StatementSource {
+    /**
+     * Path choosing the parser and the import profile: an archive is seen as a folder
+     */
+    fn file_path(&self) -> PathBuf {
+        match &self.member {
+            Some(member) => Path::new(&self.path).join(member),
+            None => PathBuf::from(&self.path),
+        }
+    }
+
+    fn name(&self) -> String {
+        source_name(&self.path, self.member.as_deref())
+    }
+}
 
-    //-----  Parse csvs ------------
-    let mut banking_statements: Vec<BankingStatement> = vec!();
+/**
+ * Name of a statement in the reports, ie. "./data/2021.zip:2021/janvier.csv" for an archive member
+ */
+fn source_name(path: &str, member: Option<&str>) -> String {
+    match member {
+        Some(member) => format!("{}:{}", path, member),
+        None => path.to_string(),
+    }
+}
 
-    for csv_path in statement_files {
-        let banking_statement = parse_csv(csv_path)?;
-        banking_statements.push(banking_statement);
+/**
+ * The statement `path`, or the statements of the archive `path`
+ */
+fn read_sources(path: String, bytes: Vec<u8>) -> anyhow::Result<Vec<StatementSource>> {
+    if is_archive_file(&path) {
+        Ok(read_archive_content(&path, &bytes)?
+            .into_iter()
+            .filter(|m| is_statement_file(&m.name))
+            .map(|m| StatementSource { path: path.clone(), member: Some(m.name), bytes: m.bytes })
+            .collect())
+    } else {
+        Ok(vec![StatementSource { path, member: None, bytes }])
     }
-    
-    //------ Insert to DB ------------    
+}
+
+/**
+ * Choose the parser from the file extension
+ */
+fn parse_statement(source: &StatementSource, options: &ImportOptions) -> anyhow::Result<Vec<BankingStatement>> {
+    let file_path = source.file_path();
+    let extension = file_path.extension()
+        .and_then(|ex| ex.to_str())
+        .map(|ex| ex.to_lowercase());
+
+    match extension.as_deref() {
+        Some("csv") => Ok(vec!(parse_csv_with_profiles(&file_path, &source.bytes, &options.csv_profiles)?)),
+        Some("ofx") | Some("qfx") => parse_ofx(&source.bytes),
+        Some("qif") => parse_qif(&file_path, &source.bytes, &options.qif_date_order),
+        Some("xml") => parse_camt(&source.bytes),
+        Some("pdf") => parse_pdf(&source.bytes),
+        _ => Err(anyhow::anyhow!("Unsupported statement file {}", file_path.display()))
+    }
+}
+
+/**
+ * Import all statements (csv, ofx, qif, camt, pdf) found in `dir_path` and in the folders of the import profiles,
+ * their sub folders and their .zip / .tar.gz archives.
+ * Files already recorded in the import ledger (same content hash) are skipped,
+ * so running it again only ingests the new statements.
+ */
+pub fn csv2db<T: DBActions, P: AsRef<Path>>(dir_path: P, arc_db : ArcMutDB<T>, options: &ImportOptions) -> anyhow::Result<ImportReport> {
+    //-----  Get all statements ------------
+    let mut dirs: Vec<&Path> = vec!(dir_path.as_ref());
+    for folder in options.csv_profiles.iter().filter_map(|p| p.folder.as_ref()) {
+        if ! dirs.contains(&Path::new(folder)) {
+            dirs.push(Path::new(folder));
+        }
+    }
+
+    let mut statement_files: Vec<PathBuf> = Vec::new();
+    for dir in dirs {
+        statement_files.extend(
+            list_files(dir, &options.include, &options.exclude)?
+                .into_iter()
+                .filter(|f| is_statement_file(f) || is_archive_file(f))
+        );
+    }
+    // A profile folder can be a sub folder of dir_path
+    statement_files.sort();
+    statement_files.dedup();
+
+    csv2db_files(statement_files, arc_db, options)
+}
+
+/**
+ * Import the given statements and archives of statements, skipping the ones already recorded in the import ledger.
+ * In strict mode, the first invalid row or file fails the import; in lenient mode, they are skipped and reported.
+ */
+pub fn csv2db_files<T: DBActions>(statement_files: Vec<PathBuf>, arc_db : ArcMutDB<T>, options: &ImportOptions) -> anyhow::Result<ImportReport> {
+    let files = statement_files
+        .into_iter()
+        .map(|f| (f.to_string_lossy().to_string(), std::fs::read(&f).map_err(anyhow::Error::from)))
+        .collect();
+    import_files(files, arc_db, options)
+}
+
+/**
+ * Same as `csv2db_files` for statements (or archives) sent over HTTP, given by their file name and content
+ */
+pub fn csv2db_uploads<T: DBActions>(uploads: Vec<(String, Vec<u8>)>, arc_db : ArcMutDB<T>, options: &ImportOptions) -> anyhow::Result<ImportReport> {
+    let files = uploads
+        .into_iter()
+        .map(|(name, bytes)| (name, Ok(bytes)))
+        .collect();
+    import_files(files, arc_db, options)
+}
+
+/**
+ * A file can not be imported: report it in lenient mode, fail in strict mode
+ */
+fn fail_file(report: &mut ImportReport, options: &ImportOptions, path: String, err: anyhow::Error) -> anyhow::Result<()> {
+    match options.mode {
+        ImportMode::Lenient => {
+            report.failed_files.push(FailedFile { path, reason: err.to_string() });
+            Ok(())
+        }
+        ImportMode::Strict => Err(RequestError::Invalid(format!("{} : {}", path, err)).into()),
+    }
+}
+
+fn import_files<T: DBActions>(files: Vec<(String, anyhow::Result<Vec<u8>>)>, arc_db : ArcMutDB<T>, options: &ImportOptions) -> anyhow::Result<ImportReport> {
     let mut db = arc_db.lock().unwrap();
-    db.create_table()?;
-
-    let mut latest_balance : Option<AccountBalance> = None;
-
-    for statement in banking_statements {
-        let activities: Vec<AccountActivity> = statement.activities.into_iter().collect::<Vec<AccountActivity>>();
-        
-        // Keep the latest balance
-        latest_balance = match latest_balance {
-            Some(b) if b.date.lt(&statement.balance.date) => Some(statement.balance),
-            None => Some(statement.balance),
-            _ => latest_balance
+    if ! db.is_initialized()? {
+        db.create_table()?;
+    }
+
+    let mut report = ImportReport::default();
+
+    //-----  Read statements ------------
+    let mut sources: Vec<StatementSource> = Vec::new();
+    for (path, bytes) in files {
+        match bytes.and_then(|bytes| read_sources(path.clone(), bytes)) {
+            Ok(file_sources) => sources.extend(file_sources),
+            Err(err) => fail_file(&mut report, options, path, err)?,
+        }
+    }
+
+    for source in sources {
+        let name = source.name();
+        // The content hash is the key of the import ledger: a statement is not imported again once archived
+        let hash = format!("{:x}", Sha256::digest(&source.bytes));
+
+        //Already imported: nothing to do
+        if db.find_import(&hash)?.is_some() {
+            report.skipped_files.push(name);
+            continue;
+        }
+
+        //-----  Parse statement ------------
+        let statements = match parse_statement(&source, options) {
+            Ok(statements) => statements,
+            Err(err) => {
+                fail_file(&mut report, options, name, err)?;
+                continue;
+            }
+        };
+        let rejected: Vec<RejectedRow> = statements.iter().flat_map(|s| s.rejected.iter().cloned()).collect();
+        if let (ImportMode::Strict, Some(row)) = (&options.mode, rejected.first()) {
+            return Err(RequestError::Invalid(format!("{} line {} : {} ({})", name, row.line, row.reason, row.content)).into());
+        }
+
+        // Statements without balance (ie. QIF) are dated by their latest activity
+        let statement_date = statements
+            .iter()
+            .filter_map(|s| s.balance.as_ref().map(|b| b.date).or_else(|| s.activities.iter().map(|a| a.date).max()))
+            .max();
+
+        //------ Insert to DB ------------
+        // A file is imported completely, with its ledger row, or not at all
+        let (imported_file, inserted_ids) = db.in_transaction(|db| {
+            let mut inserted_ids: Vec<u32> = Vec::new();
+            let mut rows: usize = 0;
+            for statement in statements {
+                let account_id = db.insert_account(&statement.account)?;
+
+                // Every balance is kept for the history of the account
+                for balance in statement.opening_balance.into_iter().chain(statement.balance) {
+                    db.insert_balance(AccountBalance { account_id: Some(account_id), ..balance })?;
+                }
+
+                let activities: Vec<AccountActivity> = statement.activities
+                    .into_iter()
+                    .map(|mut activity| {
+                        fill_details(&activity.statement, activity.date, &mut activity.details);
+                        AccountActivity { account_id: Some(account_id), ..activity }
+                    })
+                    .collect::<Vec<AccountActivity>>();
+                rows += activities.len();
+                let activity_ids = db.insert_activities(&activities)?;
+
+                if options.qif_seed_tags {
+                    seed_tags(db, &activity_ids)?;
+                }
+                inserted_ids.extend(activity_ids);
+            }
+
+            let imported_file = ImportedFile {
+                path: source.path,
+                member: source.member,
+                hash,
+                statement_date,
+                rows_inserted: inserted_ids.len(),
+                rows_skipped: rows - inserted_ids.len(),
+                imported_at: Local::now().naive_local(),
+            };
+            db.insert_import(&imported_file)?;
+            Ok((imported_file, inserted_ids))
+        })?;
+
+        report.activity_ids.extend(inserted_ids);
+        report.files.push(FileReport { file: imported_file, rejected });
+    }
+
+    Ok(report)
+}
+
+/**
+ * Print the outcome of an import, and write it as JSON to `json_path` if given
+ */
+pub fn print_report(report: &ImportReport, json_path: Option<&str>) -> anyhow::Result<()> {
+    for file in report.files.iter() {
+        let imported = &file.file;
+        println!(
+            "Imported {} : {} accepted, {} duplicates skipped, {} rejected", 
+            source_name(&imported.path, imported.member.as_deref()), imported.rows_inserted, imported.rows_skipped, file.rejected.len()
+        );
+        for row in file.rejected.iter() {
+            println!("    line {} : {} ({})", row.line, row.reason, row.content);
+        }
+    }
+    for file in report.skipped_files.iter() {
+        println!("Already imported {}", file);
+    }
+    for file in report.failed_files.iter() {
+        println!("Failed {} : {}", file.path, file.reason);
+    }
+
+    if let Some(json_path) = json_path {
+        std::fs::write(json_path, serde_json::to_string_pretty(report)?)?;
+        println!("Report written to {}", json_path);
+    }
+    Ok(())
+}
+
+/**
+ * Keep the categorisation coming with the statement (QIF categories): each category becomes a tag,
+ * given by hand to the activities inserted with it. No rule is created, the other activities are left to the rules.
+ */
+fn seed_tags<T: DBActions>(db: &mut T, activity_ids: &[u32]) -> anyhow::Result<()> {
+    for activity in db.get_activities_by_ids(activity_ids)? {
+        let category = match activity.details.category.as_deref().map(str::trim) {
+            Some(category) if !category.is_empty() => category.to_string(),
+            _ => continue,
         };
+        let activity_id = activity.row_id.ok_or_else(|| anyhow::anyhow!("Activity '{}' read without its id", activity.statement))?;
+        db.insert_tag(&category)?;
+        db.set_tag_overrides(activity_id, &[TagOverride { tag: category, assigned: true }])?;
+    }
+    Ok(())
+}
+
+
+#[test]
+fn test_incremental_import() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+
+    let sqlite_db =
+        SqliteDB::from_config(DBConfig::Memory)
+        .with_init_db_script("./data/init-db-test.toml".to_string());
+    let arc_db = Arc::new(Mutex::new(sqlite_db));
+
+    let first_run = csv2db("./data/", arc_db.clone(), &ImportOptions::default())?;
+    assert!(first_run.failed_files.is_empty(), "No statement should fail");
+    assert_eq!(first_run.files.len(), 3, "All statements should be imported on the first run");
+    assert!(!first_run.activity_ids.is_empty(), "Activities should be inserted on the first run");
+
+    let history = arc_db.lock().unwrap().get_balance_history(None, &crate::models::DateRange::default())?;
+    assert_eq!(history.len(), 3, "The balance of every statement should be kept");
+
+    let second_run = csv2db("./data/", arc_db.clone(), &ImportOptions::default())?;
+    assert!(second_run.files.is_empty(), "Statements already in the ledger should not be imported again");
+    assert_eq!(second_run.skipped_files.len(), 3, "Wrong count of skipped statements");
+    assert!(second_run.activity_ids.is_empty(), "No activity should be inserted on the second run");
+
+    Ok(())
+}
+
+#[test]
+fn test_import_rolled_back() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+
+    let sqlite_db =
+        SqliteDB::from_config(DBConfig::Memory)
+        .with_init_db_script("./data/init-db-test.toml".to_string());
+    sqlite_db.create_table()?;
+    // The ledger row, written last, can not be inserted
+    sqlite_db.connection().execute_batch("CREATE TRIGGER refuse_import BEFORE INSERT ON imports BEGIN SELECT RAISE(ABORT, 'import refused'); END")?;
+    let arc_db = Arc::new(Mutex::new(sqlite_db));
+
+    let result = csv2db_files(vec!["./data/input01.csv".into()], arc_db.clone(), &ImportOptions::default());
+    assert!(result.is_err(), "The import should fail without its ledger row");
+
+    let db = arc_db.lock().unwrap();
+    assert!(db.get_activities(None)?.is_empty(), "The activities of a failed file should be rolled back");
+    assert!(db.get_balances(None)?.is_empty(), "The balances of a failed file should be rolled back");
+    assert!(db.connection().is_autocommit(), "The transaction of a failed file should be ended");
+
+    Ok(())
+}
+
+#[test]
+fn test_is_statement_file() {
+    assert!(is_statement_file("releve.csv"), "csv statement not detected");
+    assert!(is_statement_file("releve.OFX"), "ofx statement not detected");
+    assert!(is_statement_file("releve.qfx"), "qfx statement not detected");
+    assert!(is_statement_file("camt053.xml"), "camt statement not detected");
+    assert!(!is_statement_file("releve.csv.part"), "partial download should be ignored");
+    assert!(!is_statement_file("notes.txt"), "non statement file should be ignored");
+}
+
+#[test]
+fn test_qif_seed_tags() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+    use crate::models::import::QifDateOrder;
+
+    let dir_path = std::env::temp_dir().join(format!("lpr-qif-{}", std::process::id()));
+    let records = "D02/04/2021\nT-650.00\nPAGENCE IMMO\nLLoyer\n^\nD03/04/2021\nT-20.00\nPBOULANGERIE\n^\n";
+    std::fs::create_dir_all(&dir_path)?;
+    std::fs::write(dir_path.join("money.qif"), format!("!Type:Bank\n{}", records))?;
+
+    let sqlite_db =
+        SqliteDB::from_config(DBConfig::Memory)
+        .with_init_db_script("./data/init-db-test.toml".to_string());
+    sqlite_db.create_table()?;
+    let patterns = sqlite_db.get_tag_patterns()?.len();
+    let arc_db = Arc::new(Mutex::new(sqlite_db));
+
+    let options = ImportOptions { qif_date_order: QifDateOrder::Dmy, qif_seed_tags: true, ..ImportOptions::default() };
+    let summary = csv2db(&dir_path, arc_db.clone(), &options);
+    // The same activities in a later export of the account, with a new one
+    let reimport = std::fs::write(
+        dir_path.join("money.qif"), 
+        format!("!Type:Bank\n{}D05/04/2021\nT-30.00\nPAGENCE IMMO\nLFrais\n^\n", records)
+    ).map_err(anyhow::Error::from).and_then(|_| csv2db(&dir_path, arc_db.clone(), &options));
+    std::fs::remove_dir_all(&dir_path)?;
 
-        //Insert all account activities
-        db.insert_activities(&activities)?;
+    assert_eq!(summary?.activity_ids.len(), 2, "Wrong count of QIF activities imported");
+    assert_eq!(reimport?.activity_ids.len(), 1, "The activities imported before should be skipped");
+
+    let db = arc_db.lock().unwrap();
+    assert_eq!(db.get_tag_patterns()?.len(), patterns, "No rule should be created from the QIF categories");
+    assert!(!db.get_tags()?.iter().any(|t| t.tag == "Loyer"), "A category should reuse the tag of another case");
+    let mut seeded: Vec<(String, Vec<TagOverride>)> = db
+        .get_activities(None)?
+        .into_iter()
+        .map(|a| (a.statement, a.tag_overrides))
+        .collect();
+    seeded.sort_by_key(|(statement, tags)| (statement.clone(), tags.iter().map(|t| t.tag.clone()).collect::<Vec<String>>()));
+    let given = |tag: &str| vec![TagOverride { tag: tag.to_string(), assigned: true }];
+    assert_eq!(
+        seeded,
+        vec![
+            ("AGENCE IMMO".to_string(), given("Frais")),
+            ("AGENCE IMMO".to_string(), given("LOYER")),
+            ("BOULANGERIE".to_string(), vec![]),
+        ],
+        "Each activity should only get its own QIF category"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_import_mode() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+
+    let dir_path = std::env::temp_dir().join(format!("lpr-mode-{}", std::process::id()));
+    std::fs::create_dir_all(&dir_path)?;
+    std::fs::write(
+        dir_path.join("invalid.csv"),
+        "Numéro Compte;123456789\nDate;27/03/2021\nSolde (EUROS);187,77\nDate;Libellé;Montant(EUROS)\n26/03/2021;BUY;-20,00\n26/13/2021;BAD DATE;-10,00\n"
+    )?;
+    std::fs::write(dir_path.join("broken.ofx"), "<OFX></OFX>")?;
+
+    let new_db = || {
+        let sqlite_db =
+            SqliteDB::from_config(DBConfig::Memory)
+            .with_init_db_script("./data/init-db-test.toml".to_string());
+        Arc::new(Mutex::new(sqlite_db))
+    };
+
+    let strict = csv2db(&dir_path, new_db(), &ImportOptions { mode: ImportMode::Strict, ..ImportOptions::default() });
+    let lenient = csv2db(&dir_path, new_db(), &ImportOptions { mode: ImportMode::Lenient, ..ImportOptions::default() });
+    std::fs::remove_dir_all(&dir_path)?;
+
+    assert!(strict.is_err(), "Strict mode should fail on the invalid file");
+
+    let lenient = lenient?;
+    assert_eq!(lenient.failed_files.len(), 1, "The broken file should be reported");
+    assert_eq!(lenient.files.len(), 1, "The file with an invalid row should be imported");
+    assert_eq!(lenient.files[0].file.rows_inserted, 1, "The valid row should be accepted");
+    assert_eq!(lenient.files[0].rejected.len(), 1, "The invalid row should be rejected");
+    assert_eq!(lenient.files[0].rejected[0].line, 6, "Wrong line of the rejected row");
+
+    Ok(())
+}
+
+#[test]
+fn test_sub_folders_and_archives() -> anyhow::Result<()> {
+    use std::io::Write;
+    use std::sync::{Arc, Mutex};
+    use glob::Pattern;
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+
+    let dir_path = std::env::temp_dir().join(format!("lpr-archives-{}", std::process::id()));
+    std::fs::create_dir_all(dir_path.join("2021/drafts"))?;
+    std::fs::copy("./data/input01.csv", dir_path.join("2021/input01.csv"))?;
+    std::fs::copy("./data/input03.csv", dir_path.join("2021/drafts/input03.csv"))?;
+    {
+        // input01.csv again (already imported from the folder) and input02.csv
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(dir_path.join("2021/releves.zip"))?);
+        for name in ["input01.csv", "input02.csv"] {
+            zip.start_file(format!("releves/{}", name), Default::default())?;
+            zip.write_all(&std::fs::read(format!("./data/{}", name))?)?;
+        }
+        zip.finish()?;
     }
 
-    //Insert the latest / newest account balance
-    db.insert_balance(latest_balance.expect("We should get a latest balance"))?;
- 
+    let sqlite_db =
+        SqliteDB::from_config(DBConfig::Memory)
+        .with_init_db_script("./data/init-db-test.toml".to_string());
+    let arc_db = Arc::new(Mutex::new(sqlite_db));
+    let options = ImportOptions { exclude: vec![Pattern::new("**/drafts/**")?], ..ImportOptions::default() };
+
+    let report = csv2db(&dir_path, arc_db, &options);
+    std::fs::remove_dir_all(&dir_path)?;
+    let report = report?;
+
+    assert_eq!(report.files.len(), 2, "The statements of the sub folder and of the archive should be imported");
+    assert_eq!(report.skipped_files.len(), 1, "The same statement in an archive should be imported once");
+    assert!(report.skipped_files[0].ends_with("releves.zip:releves/input01.csv"), "Wrong name of the archive member");
+
+    let member = report.files.iter().find(|f| f.file.member.is_some()).expect("Archive member not imported");
+    assert_eq!(member.file.member.as_deref(), Some("releves/input02.csv"), "Wrong archive member recorded");
+    assert!(member.file.path.ends_with("2021/releves.zip"), "The archive path should be recorded");
+
+    Ok(())
+}
+
+#[test]
+fn test_uploads() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+
+    let sqlite_db =
+        SqliteDB::from_config(DBConfig::Memory)
+        .with_init_db_script("./data/init-db-test.toml".to_string());
+    let arc_db = Arc::new(Mutex::new(sqlite_db));
+    let uploads = vec![
+        ("input01.csv".to_string(), std::fs::read("./data/input01.csv")?),
+        ("copy of input01.csv".to_string(), std::fs::read("./data/input01.csv")?),
+        ("notes.txt".to_string(), b"not a statement".to_vec()),
+    ];
+
+    let report = csv2db_uploads(uploads, arc_db, &ImportOptions::default())?;
+
+    assert_eq!(report.files.len(), 1, "The uploaded statement should be imported");
+    assert_eq!(report.files[0].file.rows_inserted, 9, "Wrong count of activities inserted");
+    assert_eq!(report.skipped_files, vec!["copy of input01.csv".to_string()], "The same statement should be imported once");
+    assert_eq!(report.failed_files.len(), 1, "An unsupported file should be reported");
 
     Ok(())
-}
\ No newline at end of file
+}
@@ -1,21 +1,47 @@
+use crate::actions::categories::{add_tag, edit_tag, remove_tag, tag_tree};
+use crate::actions::csv2db::csv2db_uploads;
+use crate::actions::manual_tags::{clear_manual_tags, set_manual_tags};
+use crate::actions::reconciliation::reconcile;
+use crate::actions::suggestions::{accept_suggestion, suggestions, MIN_CONFIDENCE};
+use crate::actions::tagging::{add_rule, conflicts, delete_rule, set_rule_tags, tagging_activities, update_rule};
 use crate::actions::utils::group_by;
 use crate::db::{ArcMutDB, DBActions};
 use crate::errors::Errors;
-use crate::models::tagging::TagsPattern;
+use crate::money::Money;
+use crate::models::import::{ImportOptions, ImportReport};
+use crate::models::tagging::{Condition, MatchKind, RuleDefinition, RuleOptions, SuggestionAcceptance, TagDefinition, TagOverride, TagsPattern};
 use crate::models::{
-    AccountActivity, AccountBalance, StatsAmountPerMonthByTag,
+    Account, AccountActivity, AccountBalance, DateRange, StatsAmountPerMonthByTag,
 };
 
 use chrono::{Datelike, NaiveDate};
+use futures::TryStreamExt;
 use itertools::Itertools;
-use ordered_float::OrderedFloat;
 use serde::Serialize;
+use std::collections::HashMap;
+use std::path::Path;
+use warp::hyper::body::Buf;
+use warp::multipart::{FormData, Part};
 
 
 #[derive(Serialize)]
 struct BalanceWWW<'a> {
     date: &'a NaiveDate,
-    amount: &'a OrderedFloat<f32>,
+    amount: &'a Money,
+}
+
+#[derive(Serialize)]
+struct BalanceHistoryWWW {
+    date: NaiveDate,
+    amount: Money,
+}
+
+#[derive(Serialize)]
+struct AccountWWW<'a> {
+    id: Option<u32>,
+    number: &'a str,
+    account_type: &'a str,
+    balance: Option<BalanceWWW<'a>>,
 }
 
 #[derive(Serialize)]
@@ -27,8 +53,8 @@ struct AccountActivityWWW {
 
 #[derive(Serialize)]
 struct AmountStatsWWW {
-    amount_plus: OrderedFloat<f32>,
-    amount_minus: OrderedFloat<f32>,
+    amount_plus: Money,
+    amount_minus: Money,
 }
 
 #[derive(Serialize)]
@@ -37,47 +63,55 @@ pub struct StatsAmountPerMonthByTagWWW<'a> {
     pub data: &'a Vec<StatsAmountPerMonthByTag>,
 }
 
+#[allow(unused)]
 #[derive(Serialize)]
 pub struct StatsDetailedAmountPerMonthByTagWWW<'a> {
     pub labels: &'a Vec<u32>,
     pub data: &'a Vec<StatsDetailedWWW<'a>>,
 }
 
+#[allow(unused)]
 #[derive(Serialize)]
 pub struct StatsDetailedWWW<'a> {
     pub label: &'a str,
-    pub data: Vec<OrderedFloat<f32>>,
+    pub data: Vec<Money>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct TagsPatternWWW<'a> {
+    pub id: u32,
     pub pattern: &'a str,
+    pub match_kind: MatchKind,
+    pub rule: Option<&'a Condition>,
+    #[serde(flatten)]
+    pub options: &'a RuleOptions,
     pub tags: Vec<&'a str>,
 }
 
 impl AmountStatsWWW {
     fn new() -> Self {
         Self {
-            amount_plus: OrderedFloat(0.00),
-            amount_minus: OrderedFloat(0.00),
+            amount_plus: Money::ZERO,
+            amount_minus: Money::ZERO,
         }
     }
-    fn add_to_amount_plus(&mut self, value: OrderedFloat<f32>) {
-        self.amount_plus = self.amount_plus + value;
+    fn add_to_amount_plus(&mut self, value: Money) {
+        self.amount_plus += value;
     }
-    fn add_to_amount_minus(&mut self, value: OrderedFloat<f32>) {
-        self.amount_minus = self.amount_minus + value;
+    fn add_to_amount_minus(&mut self, value: Money) {
+        self.amount_minus += value;
     }
 }
 
 pub async fn get_activities<T: DBActions>(
     db: ArcMutDB<T>,
+    account_id: Option<u32>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let activities: Vec<AccountActivity> = db
         .lock()
         .unwrap()
-        .get_activities()
-        .map_err(|err| Errors::DBError(err))?;
+        .get_activities(account_id)
+        .map_err(Errors::DBError)?;
 
     //Group all activities per month
     let activities_by_month = activities.into_iter().group_by(|a| a.date.month());
@@ -90,7 +124,7 @@ pub async fn get_activities<T: DBActions>(
             let mut account_activities: Vec<AccountActivity> = Vec::new();
 
             group.into_iter().for_each(|e| {
-                if e.amount.ge(&OrderedFloat(0.0)) {
+                if !e.amount.is_negative() {
                     amounts.add_to_amount_plus(e.amount);
                 } else {
                     amounts.add_to_amount_minus(e.amount);
@@ -108,23 +142,161 @@ pub async fn get_activities<T: DBActions>(
     Ok(warp::reply::json(&result))
 }
 
+/**
+ * Get the latest balance of an account or, without account, the total of the latest balance of all accounts
+ */
 pub async fn get_balance<T: DBActions>(
     db: ArcMutDB<T>,
+    account_id: Option<u32>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     // https://github.com/dtolnay/anyhow/issues/81#issuecomment-609171265. for lock().unwrap()
-    let account_balance: AccountBalance = db
+    let account_balances: Vec<AccountBalance> = db
         .lock()
         .unwrap()
-        .get_balance()
-        .map_err(|err| Errors::DBError(err))?;
+        .get_balances(account_id)
+        .map_err(Errors::DBError)?;
+
+    let date = account_balances
+        .iter()
+        .map(|b| b.date)
+        .max()
+        .ok_or_else(|| Errors::DBError(anyhow::anyhow!("No balance found")))?;
+    let amount: Money = account_balances
+        .iter()
+        .map(|b| b.balance_euro)
+        .sum();
+
     let result = BalanceWWW {
-        date: &account_balance.date,
-        amount: &account_balance.balance_euro,
+        date: &date,
+        amount: &amount,
     };
 
     Ok(warp::reply::json(&result))
 }
 
+/**
+ * Get the balance over time of an account or, without account, the total of all accounts.
+ * The total at a date sums the latest balance known at that date of each account.
+ */
+pub async fn get_balance_history<T: DBActions>(
+    db: ArcMutDB<T>,
+    account_id: Option<u32>,
+    range: DateRange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    // The balances before the range are needed for the total at its start
+    let until = DateRange { from: None, to: range.to };
+    let balances: Vec<AccountBalance> = db
+        .lock()
+        .unwrap()
+        .get_balance_history(account_id, &until)
+        .map_err(Errors::DBError)?;
+
+    let mut latest_per_account: HashMap<Option<u32>, Money> = HashMap::new();
+    let mut history: Vec<BalanceHistoryWWW> = Vec::new();
+    for (date, group) in &balances.iter().group_by(|b| b.date) {
+        for balance in group {
+            latest_per_account.insert(balance.account_id, balance.balance_euro);
+        }
+        if range.from.map(|from| date >= from).unwrap_or(true) {
+            history.push(BalanceHistoryWWW { date, amount: latest_per_account.values().copied().sum() });
+        }
+    }
+
+    Ok(warp::reply::json(&history))
+}
+
+/**
+ * Check that the balance delta between consecutive statements is the sum of their activities
+ */
+pub async fn get_reconciliation<T: DBActions>(
+    db: ArcMutDB<T>,
+    account_id: Option<u32>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let intervals = reconcile(db, account_id).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&intervals))
+}
+
+/**
+ * Import the statements (or archives of statements) sent as the files of a multipart form,
+ * tag the new activities and return the import report
+ */
+pub async fn post_import<T: DBActions + Send + 'static>(
+    db: ArcMutDB<T>,
+    options: ImportOptions,
+    form: FormData,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let uploads = read_uploads(form).await.map_err(Errors::UploadError)?;
+    // Parsing (ie. PDF, archives) and waiting for the database must not block the runtime
+    let report = tokio::task::spawn_blocking(move || -> anyhow::Result<ImportReport> {
+        let report = csv2db_uploads(uploads, db.clone(), &options)?;
+        tagging_activities(db, &report.activity_ids)?;
+        Ok(report)
+    })
+    .await
+    .map_err(|err| Errors::ImportError(err.into()))?
+    .map_err(Errors::ImportError)?;
+    Ok(warp::reply::json(&report))
+}
+
+/**
+ * Name and content of the files of the form, the other fields are ignored
+ */
+async fn read_uploads(form: FormData) -> anyhow::Result<Vec<(String, Vec<u8>)>> {
+    let parts: Vec<Part> = form.try_collect().await?;
+    let mut uploads: Vec<(String, Vec<u8>)> = Vec::new();
+    for part in parts {
+        // Some browsers send the full path of the file
+        let file_name = match part.filename().and_then(|f| Path::new(f).file_name()) {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let bytes = part
+            .stream()
+            .try_fold(Vec::new(), |mut bytes, buf| async move {
+                bytes.extend_from_slice(buf.chunk());
+                Ok(bytes)
+            })
+            .await?;
+        uploads.push((file_name, bytes));
+    }
+    Ok(uploads)
+}
+
+/**
+ * Get all accounts with their latest balance
+ */
+pub async fn get_accounts<T: DBActions>(
+    db: ArcMutDB<T>,
+    account_id: Option<u32>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let (accounts, balances): (Vec<Account>, Vec<AccountBalance>) = {
+        let db = db.lock().unwrap();
+        (
+            db.get_accounts().map_err(Errors::DBError)?,
+            db.get_balances(account_id).map_err(Errors::DBError)?,
+        )
+    };
+
+    let result: Vec<AccountWWW> = accounts
+        .iter()
+        .filter(|a| account_id.is_none() || a.row_id == account_id)
+        .map(|a| AccountWWW {
+            id: a.row_id,
+            number: &a.number,
+            account_type: &a.account_type,
+            balance: balances
+                .iter()
+                .find(|b| b.account_id == a.row_id)
+                .map(|b| BalanceWWW {
+                    date: &b.date,
+                    amount: &b.balance_euro,
+                }),
+        })
+        .collect();
+
+    Ok(warp::reply::json(&result))
+}
+
 /**
  * Get the tag pattern ids and their associated tags text/pattern
  */
@@ -133,7 +305,7 @@ pub async fn get_tags<T: DBActions>(db: ArcMutDB<T>) -> Result<impl warp::Reply,
         .lock()
         .unwrap()
         .get_tag_patterns()
-        .map_err(|err| Errors::DBError(err))?;
+        .map_err(Errors::DBError)?;
 
 
     let tags_pattern_grouped = group_by(
@@ -145,18 +317,61 @@ pub async fn get_tags<T: DBActions>(db: ArcMutDB<T>) -> Result<impl warp::Reply,
     Ok(warp::reply::json(&tags_pattern_grouped))
 }
 
+/**
+ * Get all the tags with their parent
+ */
+pub async fn get_tag_list<T: DBActions>(db: ArcMutDB<T>) -> Result<impl warp::Reply, warp::Rejection> {
+    let tags = db.lock().unwrap().get_tags().map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&tags))
+}
+
+/**
+ * Create a tag, returns it
+ */
+pub async fn post_tag<T: DBActions>(
+    db: ArcMutDB<T>,
+    definition: TagDefinition,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tag = add_tag(db, &definition).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&tag))
+}
+
+/**
+ * Rename a tag or change its parent, returns it
+ */
+pub async fn put_tag<T: DBActions>(
+    id: u32,
+    db: ArcMutDB<T>,
+    definition: TagDefinition,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tag = edit_tag(db, id, &definition).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&tag))
+}
+
+/**
+ * Remove a tag given by no rule and without sub tags, returns it
+ */
+pub async fn delete_tag<T: DBActions>(
+    id: u32,
+    db: ArcMutDB<T>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tag = remove_tag(db, id).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&tag))
+}
+
 /**
  * Get stats by tag text
  */
 pub async fn get_stats_tag_per_month<T: DBActions>(
     db: ArcMutDB<T>,
     tags: Vec<String>,
+    account_id: Option<u32>,
 ) -> Result<impl warp::Reply, warp::Rejection> {
     let stats = db
         .lock()
         .unwrap()
-        .get_stats_tag_per_month(&tags)
-        .map_err(|err| Errors::DBError(err))?;
+        .get_stats_tag_per_month(&tags, account_id)
+        .map_err(Errors::DBError)?;
 
     let result = StatsAmountPerMonthByTagWWW {
         tags: &tags,
@@ -166,6 +381,47 @@ pub async fn get_stats_tag_per_month<T: DBActions>(
     Ok(warp::reply::json(&result))
 }
 
+/**
+ * Get the tag of the path (ie. LOGEMENT/ENERGIE) or, without path, all the tags without parent,
+ * with the amounts of their activities and of their sub tags per month
+ */
+pub async fn get_stats_tag_tree<T: DBActions>(
+    db: ArcMutDB<T>,
+    path: Option<String>,
+    account_id: Option<u32>,
+    range: DateRange,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tree = tag_tree(db, path.as_deref(), account_id, &range).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&tree))
+}
+
+/**
+ * Get the amount of the activities per month and primary category, the activities without category under a null category
+ */
+pub async fn get_stats_category_per_month<T: DBActions>(
+    db: ArcMutDB<T>,
+    account_id: Option<u32>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let stats = db
+        .lock()
+        .unwrap()
+        .get_stats_category_per_month(account_id)
+        .map_err(Errors::DBError)?;
+
+    Ok(warp::reply::json(&stats))
+}
+
+/**
+ * Get the activities matched by rules giving different categories
+ */
+pub async fn get_tag_conflicts<T: DBActions>(
+    db: ArcMutDB<T>,
+    account_id: Option<u32>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let conflicts = conflicts(db, account_id).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&conflicts))
+}
+
 /**
  * Get the list of all tag pattern and their associated tag text
  */
@@ -176,22 +432,120 @@ pub async fn get_tags_pattern<T: DBActions>(
         .lock()
         .unwrap()
         .get_tag_patterns()
-        .map_err(|err| Errors::DBError(err))?;
+        .map_err(Errors::DBError)?;
 
+    // The same pattern text may be looked for with different match kinds
     let grouped = group_by(
         &tags_pattern,
-        |e: &TagsPattern| e.pattern.as_str(),
-        |e: &TagsPattern| e.tag.as_str(),
+        |e: &TagsPattern| &e.id,
+        |e: &TagsPattern| e,
     );
 
     let tags_pattern_grouped: Vec<TagsPatternWWW> = grouped
         .into_iter()
-        .map(|e| TagsPatternWWW {
-            pattern: e.0,
-            tags: e.1,
+        .map(|(id, patterns)| TagsPatternWWW {
+            id: *id,
+            pattern: patterns[0].pattern.as_str(),
+            match_kind: patterns[0].match_kind,
+            rule: patterns[0].rule.as_ref(),
+            options: &patterns[0].options,
+            tags: patterns.iter().map(|p| p.tag.as_str()).collect(),
         })
+        .sorted_by_key(|p| p.id)
         .collect();
 
     Ok(warp::reply::json(&tags_pattern_grouped))
 }
 
+/**
+ * Add a rule and tag the activities it matches, returns what was retagged
+ */
+pub async fn post_tags_pattern<T: DBActions>(
+    db: ArcMutDB<T>,
+    definition: RuleDefinition,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let report = add_rule(db, &definition).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&report))
+}
+
+/**
+ * Replace a rule and retag only the activities it matched or now matches
+ */
+pub async fn put_tags_pattern<T: DBActions>(
+    id: u32,
+    db: ArcMutDB<T>,
+    definition: RuleDefinition,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let report = update_rule(db, id, &definition).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&report))
+}
+
+/**
+ * Remove a rule and retag the activities it matched
+ */
+pub async fn delete_tags_pattern<T: DBActions>(
+    id: u32,
+    db: ArcMutDB<T>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let report = delete_rule(db, id).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&report))
+}
+
+/**
+ * Replace the tags given by a rule (by their ids), returns them
+ */
+pub async fn put_tags_pattern_tags<T: DBActions>(
+    id: u32,
+    db: ArcMutDB<T>,
+    tag_ids: Vec<u32>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let tags = set_rule_tags(db, id, &tag_ids).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&tags))
+}
+
+/**
+ * Replace the tags given or removed by hand on an activity, returns them
+ */
+pub async fn put_activity_tags<T: DBActions>(
+    id: u32,
+    db: ArcMutDB<T>,
+    tag_overrides: Vec<TagOverride>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let saved = set_manual_tags(db, id, &tag_overrides).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&saved))
+}
+
+/**
+ * Give back to the rules the tags set by hand on an activity (only the given tag if any), returns how many were removed
+ */
+pub async fn delete_activity_tags<T: DBActions>(
+    id: u32,
+    db: ArcMutDB<T>,
+    tag: Option<String>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let deleted = clear_manual_tags(db, id, tag.as_deref()).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&deleted))
+}
+
+/**
+ * Get the tags suggested for the activities without tag, with at least the given confidence (0.5 by default)
+ */
+pub async fn get_suggestions<T: DBActions>(
+    db: ArcMutDB<T>,
+    account_id: Option<u32>,
+    min_confidence: Option<f64>,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let suggestions = suggestions(db, account_id, min_confidence.unwrap_or(MIN_CONFIDENCE)).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&suggestions))
+}
+
+/**
+ * Accept a suggestion as a manual tag of the activity or as a new rule
+ */
+pub async fn post_suggestion_acceptance<T: DBActions>(
+    db: ArcMutDB<T>,
+    acceptance: SuggestionAcceptance,
+) -> Result<impl warp::Reply, warp::Rejection> {
+    let accepted = accept_suggestion(db, &acceptance).map_err(Errors::DBError)?;
+    Ok(warp::reply::json(&accepted))
+}
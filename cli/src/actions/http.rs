@@ -1,12 +1,22 @@
-use self::filters::{QueryParam, filter_generic};
+use self::filters::{AccountParam, HistoryParam, QueryParam, SuggestionParam, TagParam, TreeParam, filter_generic, filter_item, filter_item_resource, filter_post};
 use super::handlers::get_tags_pattern;
-use crate::actions::handlers::{get_activities, get_balance, get_stats_tag_per_month, get_tags};
+use crate::actions::handlers::{get_accounts, get_activities, get_balance, get_balance_history, get_reconciliation, get_stats_category_per_month, get_stats_tag_per_month, get_stats_tag_tree, get_tag_conflicts, get_tags, post_import};
+use crate::actions::handlers::{delete_activity_tags, delete_tag, delete_tags_pattern, get_suggestions, get_tag_list, post_suggestion_acceptance, post_tag, post_tags_pattern, put_activity_tags, put_tag, put_tags_pattern, put_tags_pattern_tags};
 use crate::db::ArcMutDB;
 use crate::db::DBActions;
+use crate::models::import::ImportOptions;
+use crate::models::tagging::{RuleDefinition, SuggestionAcceptance, TagDefinition, TagOverride};
+use crate::errors::Errors;
+use serde::Serialize;
+use std::convert::Infallible;
 use tracing::Level;
 use tracing_subscriber::FmtSubscriber;
+use warp::body::BodyDeserializeError;
+use warp::http::StatusCode;
 use warp::hyper::Method;
-use warp::Filter;
+use warp::reject::{InvalidQuery, MethodNotAllowed, PayloadTooLarge};
+use warp::{Filter, Rejection, Reply};
+use warp::multipart::FormData;
 /**
  * The definitions of APIs
  */
@@ -14,13 +24,61 @@ mod filters {
     use crate::{
         actions::utils::path_from_str,
         db::{ArcMutDB, DBActions},
+        models::DateRange,
     };
     use serde::Deserialize;
     use warp::{Filter, Rejection};
 
     #[derive(Deserialize)]
     pub struct QueryParam {        
-        pub value: String
+        pub value: String,
+        pub account: Option<u32>
+    }
+
+    /**
+     * Optional filter on the account id, ie. /api/activities?account=1
+     */
+    #[derive(Deserialize)]
+    pub struct AccountParam {
+        pub account: Option<u32>
+    }
+
+    /**
+     * Optional account and dates of a history, ie. /api/balance/history?account=1&from=2021-01-01&to=2021-12-31
+     */
+    #[derive(Deserialize)]
+    pub struct HistoryParam {
+        pub account: Option<u32>,
+        #[serde(flatten)]
+        pub range: DateRange,
+    }
+
+    /**
+     * Optional path of a tag, account and dates, ie. /api/stats/tag/tree?path=LOGEMENT/ENERGIE&account=1&from=2021-01-01
+     */
+    #[derive(Deserialize)]
+    pub struct TreeParam {
+        pub path: Option<String>,
+        pub account: Option<u32>,
+        #[serde(flatten)]
+        pub range: DateRange,
+    }
+
+    /**
+     * Optional account and confidence of the suggestions, ie. /api/activities/suggestions?account=1&min_confidence=0.8
+     */
+    #[derive(Deserialize)]
+    pub struct SuggestionParam {
+        pub account: Option<u32>,
+        pub min_confidence: Option<f64>,
+    }
+
+    /**
+     * Optional tag, ie. DELETE /api/activities/1/tags?tag=LOYER
+     */
+    #[derive(Deserialize)]
+    pub struct TagParam {
+        pub tag: Option<String>
     }
 
     impl QueryParam {
@@ -46,57 +104,379 @@ mod filters {
             .and(warp::path::end())
             .and(with_db)
     }
+
+    /**
+     * Same as `filter_generic` for the POST requests
+     */
+    pub fn filter_post<T>(
+        path: &str,
+        arc_db: ArcMutDB<T>,
+    ) -> impl Filter<Extract = (ArcMutDB<T>,), Error = Rejection>
+    where
+        T: DBActions + Send,
+    {
+        let with_db = warp::any().map(move || arc_db.clone());
+
+        let path_filter = path_from_str(path);
+
+        warp::post()
+            .and(path_filter)             
+            .and(warp::path::end())
+            .and(with_db)
+    }
+
+    /**
+     * Same as `filter_generic` for the requests on the item of the id ending the path, ie. PUT /api/tags/pattern/1
+     */
+    pub fn filter_item<T, M>(
+        method: M,
+        path: &str,
+        arc_db: ArcMutDB<T>,
+    ) -> impl Filter<Extract = (u32, ArcMutDB<T>), Error = Rejection>
+    where
+        T: DBActions + Send,
+        M: Filter<Extract = (), Error = Rejection> + Clone,
+    {
+        let with_db = warp::any().map(move || arc_db.clone());
+
+        let path_filter = path_from_str(path);
+
+        method
+            .and(path_filter)
+            .and(warp::path::param::<u32>())
+            .and(warp::path::end())
+            .and(with_db)
+    }
+
+    /**
+     * Same as `filter_item` for a resource of the item, ie. PUT /api/activities/1/tags
+     */
+    pub fn filter_item_resource<T, M>(
+        method: M,
+        path: &str,
+        resource: &str,
+        arc_db: ArcMutDB<T>,
+    ) -> impl Filter<Extract = (u32, ArcMutDB<T>), Error = Rejection>
+    where
+        T: DBActions + Send,
+        M: Filter<Extract = (), Error = Rejection> + Clone,
+    {
+        let with_db = warp::any().map(move || arc_db.clone());
+
+        method
+            .and(path_from_str(path))
+            .and(warp::path::param::<u32>())
+            .and(path_from_str(resource))
+            .and(warp::path::end())
+            .and(with_db)
+    }
 }
 
+/**
+ * Largest multipart form accepted by /api/import
+ */
+const MAX_UPLOAD_SIZE: u64 = 20 * 1024 * 1024;
+
+
 
-pub async fn http_server<T>(www_dir: String, www_port: u16, arc_db : ArcMutDB<T>) -> anyhow::Result<()> 
+pub async fn http_server<T>(www_dir: String, www_port: u16, arc_db : ArcMutDB<T>, import_options: ImportOptions) -> anyhow::Result<()> 
+where 
+    T: DBActions + Send + 'static
+{
+    let subscriber = FmtSubscriber::builder()
+        .with_max_level(Level::TRACE)
+        .finish();
+    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+
+    warp::serve(routes(www_dir, arc_db, import_options)).run(([127, 0, 0, 1], www_port)).await;
+    Ok(())
+}
+
+/**
+ * The web application and the API, the errors served as JSON
+ */
+pub fn routes<T>(www_dir: String, arc_db : ArcMutDB<T>, import_options: ImportOptions) -> impl Filter<Extract = (impl Reply,), Error = Rejection> + Clone
 where 
     T: DBActions + Send + 'static
 {
     let extract_param = warp::query::<QueryParam>();
+    let extract_account = warp::query::<AccountParam>();
 
     let cors = warp::cors()
         .allow_any_origin()
         .allow_header("content-type")
-        .allow_methods(&[Method::GET]);
+        .allow_methods(&[Method::GET, Method::POST, Method::PUT, Method::DELETE]);
 
     let www_root = warp::get().and(warp::fs::dir(www_dir));
 
+    let api_accounts = 
+        filter_generic("api/accounts", arc_db.clone())
+        .and(extract_account)
+        .and_then(|arc_db : ArcMutDB<T>, param : AccountParam| get_accounts(arc_db, param.account));
+
     let api_activities = 
         filter_generic("api/activities", arc_db.clone())
-        .and_then(get_activities);
+        .and(extract_account)
+        .and_then(|arc_db : ArcMutDB<T>, param : AccountParam| get_activities(arc_db, param.account));
+
+    let api_suggestions = 
+        filter_generic("api/activities/suggestions", arc_db.clone())
+        .and(warp::query::<SuggestionParam>())
+        .and_then(|arc_db : ArcMutDB<T>, param : SuggestionParam| get_suggestions(arc_db, param.account, param.min_confidence));
+
+    let api_suggestions_accept = 
+        filter_post("api/activities/suggestions/accept", arc_db.clone())
+        .and(warp::body::json())
+        .and_then(|arc_db : ArcMutDB<T>, acceptance : SuggestionAcceptance| post_suggestion_acceptance(arc_db, acceptance));
 
     let api_balance = 
         filter_generic("api/balance", arc_db.clone())
-        .and_then(get_balance);
+        .and(extract_account)
+        .and_then(|arc_db : ArcMutDB<T>, param : AccountParam| get_balance(arc_db, param.account));
+
+    let api_balance_history = 
+        filter_generic("api/balance/history", arc_db.clone())
+        .and(warp::query::<HistoryParam>())
+        .and_then(|arc_db : ArcMutDB<T>, param : HistoryParam| get_balance_history(arc_db, param.account, param.range));
+
+    let api_reconciliation = 
+        filter_generic("api/reconciliation", arc_db.clone())
+        .and(extract_account)
+        .and_then(|arc_db : ArcMutDB<T>, param : AccountParam| get_reconciliation(arc_db, param.account));
 
     let api_tags = 
         filter_generic("api/tags", arc_db.clone())
         .and_then(get_tags);
 
+    let api_tag_list = 
+        filter_generic("api/tags/list", arc_db.clone())
+        .and_then(get_tag_list);
+
+    let api_tag_add = 
+        filter_post("api/tags", arc_db.clone())
+        .and(warp::body::json())
+        .and_then(|arc_db : ArcMutDB<T>, definition : TagDefinition| post_tag(arc_db, definition));
+
+    let api_tag_update = 
+        filter_item(warp::put(), "api/tags", arc_db.clone())
+        .and(warp::body::json())
+        .and_then(|id : u32, arc_db : ArcMutDB<T>, definition : TagDefinition| put_tag(id, arc_db, definition));
+
+    let api_tag_delete = 
+        filter_item(warp::delete(), "api/tags", arc_db.clone())
+        .and_then(|id : u32, arc_db : ArcMutDB<T>| delete_tag(id, arc_db));
+
     let api_stats_tag_per_month = 
         filter_generic("api/stats/per_month/tag", arc_db.clone())
         .and(extract_param)
         .and_then( move |arc_db : ArcMutDB<T>, param : QueryParam|  {
-            get_stats_tag_per_month(arc_db, param.tokenize())
+            get_stats_tag_per_month(arc_db, param.tokenize(), param.account)
         });
 
+    let api_stats_category_per_month = 
+        filter_generic("api/stats/per_month/category", arc_db.clone())
+        .and(extract_account)
+        .and_then(|arc_db : ArcMutDB<T>, param : AccountParam| get_stats_category_per_month(arc_db, param.account));
+
+    let api_stats_tag_tree = 
+        filter_generic("api/stats/tag/tree", arc_db.clone())
+        .and(warp::query::<TreeParam>())
+        .and_then(|arc_db : ArcMutDB<T>, param : TreeParam| get_stats_tag_tree(arc_db, param.path, param.account, param.range));
+
     let api_tags_pattern = 
         filter_generic("api/tags/pattern", arc_db.clone())
         .and_then(get_tags_pattern);
 
-    let subscriber = FmtSubscriber::builder()
-        .with_max_level(Level::TRACE)
-        .finish();
-    tracing::subscriber::set_global_default(subscriber).expect("setting default subscriber failed");
+    let api_tags_conflicts = 
+        filter_generic("api/tags/conflicts", arc_db.clone())
+        .and(extract_account)
+        .and_then(|arc_db : ArcMutDB<T>, param : AccountParam| get_tag_conflicts(arc_db, param.account));
+
+    let api_tags_pattern_add = 
+        filter_post("api/tags/pattern", arc_db.clone())
+        .and(warp::body::json())
+        .and_then(|arc_db : ArcMutDB<T>, definition : RuleDefinition| post_tags_pattern(arc_db, definition));
+
+    let api_tags_pattern_update = 
+        filter_item(warp::put(), "api/tags/pattern", arc_db.clone())
+        .and(warp::body::json())
+        .and_then(|id : u32, arc_db : ArcMutDB<T>, definition : RuleDefinition| put_tags_pattern(id, arc_db, definition));
 
-    let route = www_root
+    let api_tags_pattern_delete = 
+        filter_item(warp::delete(), "api/tags/pattern", arc_db.clone())
+        .and_then(|id : u32, arc_db : ArcMutDB<T>| delete_tags_pattern(id, arc_db));
+
+    let api_tags_pattern_tags_update = 
+        filter_item_resource(warp::put(), "api/tags/pattern", "tags", arc_db.clone())
+        .and(warp::body::json())
+        .and_then(|id : u32, arc_db : ArcMutDB<T>, tag_ids : Vec<u32>| put_tags_pattern_tags(id, arc_db, tag_ids));
+
+    let api_activity_tags_update = 
+        filter_item_resource(warp::put(), "api/activities", "tags", arc_db.clone())
+        .and(warp::body::json())
+        .and_then(|id : u32, arc_db : ArcMutDB<T>, tag_overrides : Vec<TagOverride>| put_activity_tags(id, arc_db, tag_overrides));
+
+    let api_activity_tags_delete = 
+        filter_item_resource(warp::delete(), "api/activities", "tags", arc_db.clone())
+        .and(warp::query::<TagParam>())
+        .and_then(|id : u32, arc_db : ArcMutDB<T>, param : TagParam| delete_activity_tags(id, arc_db, param.tag));
+
+    let api_import = 
+        filter_post("api/import", arc_db.clone())
+        .and(warp::any().map(move || import_options.clone()))
+        .and(warp::multipart::form().max_length(MAX_UPLOAD_SIZE))
+        .and_then(|arc_db : ArcMutDB<T>, options : ImportOptions, form : FormData| post_import(arc_db, options, form));
+
+    www_root
+        .or(api_accounts.boxed())
         .or(api_activities.boxed())
+        .or(api_suggestions.boxed())
+        .or(api_suggestions_accept.boxed())
         .or(api_balance.boxed())
+        .or(api_balance_history.boxed())
+        .or(api_reconciliation.boxed())
         .or(api_tags.boxed())
+        .or(api_tag_list.boxed())
+        .or(api_tag_add.boxed())
+        .or(api_tag_update.boxed())
+        .or(api_tag_delete.boxed())
         .or(api_stats_tag_per_month.boxed())
+        .or(api_stats_category_per_month.boxed())
+        .or(api_stats_tag_tree.boxed())
         .or(api_tags_pattern.boxed())
-        .with(cors);
+        .or(api_tags_conflicts.boxed())
+        .or(api_tags_pattern_add.boxed())
+        .or(api_tags_pattern_update.boxed())
+        .or(api_tags_pattern_delete.boxed())
+        .or(api_tags_pattern_tags_update.boxed())
+        .or(api_activity_tags_update.boxed())
+        .or(api_activity_tags_delete.boxed())
+        .or(api_import.boxed())
+        .recover(handle_rejection)
+        .with(cors)
+}
+
+#[derive(Serialize)]
+struct ErrorWWW {
+    error: String,
+}
+
+/**
+ * Serve the rejections as `{"error": ...}`: the errors caused by the request (see `RequestError`) with their client error status
+ */
+async fn handle_rejection(rejection: Rejection) -> Result<impl Reply, Infallible> {
+    let (status, error) = if let Some(err) = rejection.find::<Errors>() {
+        (err.status(), err.message())
+    } else if let Some(err) = rejection.find::<BodyDeserializeError>() {
+        (StatusCode::BAD_REQUEST, err.to_string())
+    } else if let Some(err) = rejection.find::<InvalidQuery>() {
+        (StatusCode::BAD_REQUEST, err.to_string())
+    } else if let Some(err) = rejection.find::<PayloadTooLarge>() {
+        (StatusCode::PAYLOAD_TOO_LARGE, err.to_string())
+    } else if let Some(err) = rejection.find::<MethodNotAllowed>() {
+        (StatusCode::METHOD_NOT_ALLOWED, err.to_string())
+    } else if rejection.is_not_found() {
+        (StatusCode::NOT_FOUND, "Not found".to_string())
+    } else {
+        (StatusCode::INTERNAL_SERVER_ERROR, format!("{:?}", rejection))
+    };
+    Ok(warp::reply::with_status(warp::reply::json(&ErrorWWW { error }), status))
+}
+
+#[tokio::test]
+async fn test_error_status() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use serde_json::{json, Value};
+    use crate::actions::csv2db::csv2db_files;
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+
+    let sqlite_db =
+        SqliteDB::from_config(DBConfig::Memory)
+        .with_init_db_script("./data/init-db-test.toml".to_string());
+    sqlite_db.create_table()?;
+    let arc_db = Arc::new(Mutex::new(sqlite_db));
+    csv2db_files(vec!["./data/input01.csv".into()], arc_db.clone(), &ImportOptions::default())?;
+    let api = routes("./www".to_string(), arc_db, ImportOptions::default());
+
+    let request = |method: &str, path: &str, body: Value| warp::test::request().method(method).path(path).json(&body);
+    let error = |body: &[u8]| -> anyhow::Result<String> { Ok(serde_json::from_slice::<Value>(body)?["error"].as_str().unwrap_or_default().to_string()) };
+
+    let created = request("POST", "/api/tags", json!({"tag": "COURSES", "parent_id": 7})).reply(&api).await;
+    assert_eq!(created.status(), StatusCode::OK, "The tag should be created");
+
+    for (method, path, body, status, message) in [
+        ("POST", "/api/tags", json!({"tag": " "}), StatusCode::BAD_REQUEST, "Empty tag"),
+        ("PUT", "/api/tags/7", json!({"tag": "LOGEMENT", "parent_id": 1}), StatusCode::BAD_REQUEST, "Tag 'LOGEMENT' can not be a sub tag of itself"),
+        ("PUT", "/api/tags/100", json!({"tag": "SANTE"}), StatusCode::NOT_FOUND, "Unknown tag 100"),
+        ("POST", "/api/tags", json!({"tag": "courses"}), StatusCode::CONFLICT, "Tag 'COURSES' already exists (9)"),
+        ("DELETE", "/api/tags/3", Value::Null, StatusCode::CONFLICT, "Tag 3 is given by the tag patterns 4, 6"),
+        ("PUT", "/api/tags/pattern/5/tags", json!([]), StatusCode::BAD_REQUEST, "A rule needs at least one tag"),
+        ("POST", "/api/tags/pattern", json!({"pattern": "^PRLV (FREE", "match_kind": "regex", "tags": ["FREEMOBILE"]}), StatusCode::BAD_REQUEST, "Invalid regex '^PRLV (FREE'"),
+        ("POST", "/api/tags/pattern", json!({"pattern": "SNCF", "tags": [" "]}), StatusCode::BAD_REQUEST, "A rule needs at least one tag"),
+        ("POST", "/api/tags/pattern", json!({"pattern": "EDF", "match_kind": "word", "tags": ["EDF"]}), StatusCode::CONFLICT, "Tag pattern 1 has the same pattern and conditions"),
+        ("PUT", "/api/tags/pattern/100", json!({"pattern": "SNCF", "tags": ["TRAIN"]}), StatusCode::NOT_FOUND, "Unknown tag pattern 100"),
+        ("PUT", "/api/tags/pattern/5", json!({"pattern": "EDF", "match_kind": "word", "tags": ["EDF"]}), StatusCode::CONFLICT, "Tag pattern 1 has the same pattern and conditions"),
+        ("DELETE", "/api/tags/pattern/100", Value::Null, StatusCode::NOT_FOUND, "Unknown tag pattern 100"),
+        ("PUT", "/api/activities/1000/tags", json!([{"tag": "LOYER", "assigned": true}]), StatusCode::NOT_FOUND, "Unknown activity 1000"),
+        ("PUT", "/api/activities/1/tags", json!([{"tag": " ", "assigned": true}]), StatusCode::BAD_REQUEST, "Empty tag"),
+        ("PUT", "/api/activities/1/tags", json!([{"tag": "LOYER", "assigned": true}, {"tag": "LOYER", "assigned": false}]), StatusCode::BAD_REQUEST, "Tag 'LOYER' both given and removed"),
+        ("PUT", "/api/activities/1/tags", json!([{"tag": "LOGEMENT/EAU", "assigned": true}]), StatusCode::BAD_REQUEST, "Tag 'LOGEMENT/EAU' can not contain '/'"),
+        ("DELETE", "/api/activities/1000/tags", Value::Null, StatusCode::NOT_FOUND, "Unknown activity 1000"),
+        ("DELETE", "/api/activities/1/tags?tag=SANTE", Value::Null, StatusCode::NOT_FOUND, "Unknown tag 'SANTE'"),
+        ("GET", "/api/stats/tag/tree?path=LOGEMENT/EAU", Value::Null, StatusCode::NOT_FOUND, "Unknown tag path 'LOGEMENT/EAU'"),
+        ("POST", "/api/activities/suggestions/accept", json!({"activity_id": 1000, "tag": "COURSES"}), StatusCode::NOT_FOUND, "Unknown activity 1000"),
+        ("POST", "/api/activities/suggestions/accept", json!({"activity_id": 1, "tag": "COURSES", "accept_as": "later"}), StatusCode::BAD_REQUEST, "Request body deserialize error"),
+    ] {
+        let response = request(method, path, body).reply(&api).await;
+        let served = error(response.body())?;
+        assert!(response.status() == status && served.starts_with(message), "Wrong error for {} {} : {} {}", method, path, response.status(), served);
+    }
+
+    let response = request("POST", "/api/tags", json!({"name": "SANTE"})).reply(&api).await;
+    assert_eq!(response.status(), StatusCode::BAD_REQUEST, "An invalid body should be a bad request");
+
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_import_upload() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use serde_json::Value;
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+    use crate::models::import::ImportMode;
+
+    let upload = |mode: ImportMode| {
+        let sqlite_db =
+            SqliteDB::from_config(DBConfig::Memory)
+            .with_init_db_script("./data/init-db-test.toml".to_string());
+        let api = routes("./www".to_string(), Arc::new(Mutex::new(sqlite_db)), ImportOptions { mode, ..ImportOptions::default() });
+        let body = "--BOUNDARY\r\n\
+            Content-Disposition: form-data; name=\"statements\"; filename=\"notes.txt\"\r\n\
+            Content-Type: text/plain\r\n\r\n\
+            not a statement\r\n\
+            --BOUNDARY--\r\n";
+        async move {
+            warp::test::request()
+                .method("POST")
+                .path("/api/import")
+                .header("content-type", "multipart/form-data; boundary=BOUNDARY")
+                .body(body)
+                .reply(&api)
+                .await
+        }
+    };
+
+    let lenient = upload(ImportMode::Lenient).await;
+    assert_eq!(lenient.status(), StatusCode::OK, "A lenient import should report the bad file");
+    let report: Value = serde_json::from_slice(lenient.body())?;
+    assert_eq!(report["failed_files"][0]["path"], "notes.txt", "The bad file should be reported");
+
+    let strict = upload(ImportMode::Strict).await;
+    assert_eq!(strict.status(), StatusCode::BAD_REQUEST, "A strict import should fail on the bad file");
+    let error: Value = serde_json::from_slice(strict.body())?;
+    assert!(error["error"].as_str().unwrap_or_default().starts_with("notes.txt : Unsupported statement file"), "Wrong error {}", error);
 
-    Ok(warp::serve(route).run(([127, 0, 0, 1], www_port)).await)
+    Ok(())
 }
@@ -0,0 +1,129 @@
+use std::path::Path;
+
+use itertools::Itertools;
+
+use crate::db::{ArcMutDB, DBActions, DBConfig};
+use crate::errors::RequestError;
+use crate::models::tagging::{SavedTagOverride, TagOverride};
+
+/**
+ * Replace the tags given or removed by hand on an activity (the missing tags are created, the existing ones are found whatever their case).
+ * They are kept apart from the tags of the rules: retagging leaves them untouched. Returns the tags as saved.
+ */
+pub fn set_manual_tags<T: DBActions>(arc_db: ArcMutDB<T>, activity_id: u32, tag_overrides: &[TagOverride]) -> anyhow::Result<Vec<TagOverride>> {
+    let tag_overrides: Vec<TagOverride> = tag_overrides
+        .iter()
+        .map(|o| TagOverride { tag: o.tag.trim().to_string(), assigned: o.assigned })
+        .unique()
+        .collect();
+    if tag_overrides.iter().any(|o| o.tag.is_empty()) {
+        return Err(RequestError::Invalid("Empty tag".to_string()).into());
+    }
+    if let Some(tag) = tag_overrides.iter().map(|o| &o.tag).duplicates_by(|t| t.to_lowercase()).next() {
+        return Err(RequestError::Invalid(format!("Tag '{}' both given and removed", tag)).into());
+    }
+
+    let mut sqlite_db = arc_db.lock().unwrap();
+    sqlite_db.in_transaction(|db| {
+        let tag_ids = tag_overrides.iter().map(|o| db.insert_tag(&o.tag)).collect::<anyhow::Result<Vec<u32>>>()?;
+        // The tags as named in the database
+        let tags = db.get_tags()?;
+        let tag_overrides: Vec<TagOverride> = tag_overrides
+            .into_iter()
+            .zip(tag_ids)
+            .map(|(o, id)| TagOverride { tag: tags.iter().find(|t| t.id == id).map(|t| t.tag.clone()).unwrap_or(o.tag), assigned: o.assigned })
+            .collect();
+        db.set_tag_overrides(activity_id, &tag_overrides)?;
+        Ok(tag_overrides)
+    })
+}
+
+/**
+ * Give back to the rules the tags of an activity set by hand, only the given tag if any
+ */
+pub fn clear_manual_tags<T: DBActions>(arc_db: ArcMutDB<T>, activity_id: u32, tag: Option<&str>) -> anyhow::Result<usize> {
+    arc_db.lock().unwrap().delete_tag_overrides(activity_id, tag)
+}
+
+/**
+ * The manual tags of a database about to be rebuilt, none when it does not exist yet.
+ * A database older than the manual tags has none either.
+ */
+pub fn save_manual_tags<T: DBActions>(db_path: &str) -> anyhow::Result<Vec<SavedTagOverride>> {
+    if !Path::new(db_path).exists() {
+        return Ok(Vec::new());
+    }
+    let db = T::from_config(DBConfig::File { file_name: db_path.to_string() });
+    if !db.is_initialized().unwrap_or(false) {
+        return Ok(Vec::new());
+    }
+    match db.get_saved_tag_overrides() {
+        Ok(saved) => Ok(saved),
+        Err(err) => {
+            eprintln!("Manual tags not kept : {}", err);
+            Ok(Vec::new())
+        }
+    }
+}
+
+/**
+ * Put back the manual tags on the activities of the rebuilt database, returns how many found their activity
+ */
+pub fn restore_manual_tags<T: DBActions>(arc_db: ArcMutDB<T>, saved: &[SavedTagOverride]) -> anyhow::Result<usize> {
+    let mut sqlite_db = arc_db.lock().unwrap();
+    for tag in saved.iter().map(|s| s.tag_override.tag.as_str()).unique() {
+        sqlite_db.insert_tag(tag)?;
+    }
+    sqlite_db.restore_tag_overrides(saved)
+}
+
+#[test]
+fn test_manual_tags() -> anyhow::Result<()> {
+    use crate::actions::tagging::retag;
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::sqlite::tests::tagged_test_db;
+
+    let given = |tag: &str| TagOverride { tag: tag.to_string(), assigned: true };
+    let removed = |tag: &str| TagOverride { tag: tag.to_string(), assigned: false };
+    let rent_in = |arc_db: &ArcMutDB<SqliteDB>, month: u8| -> anyhow::Result<Option<String>> {
+        let stats = arc_db.lock().unwrap().get_stats_tag_per_month(&["LOYER".to_string()], None)?;
+        Ok(stats.into_iter().find(|s| s.month == month).map(|s| s.amount.to_string()))
+    };
+
+    let arc_db = tagged_test_db()?;
+    let activities = arc_db.lock().unwrap().get_activities(None)?;
+    let transfer = activities.iter().find(|a| a.statement.contains("VIREMENT")).unwrap().row_id.unwrap();
+    let rent = activities.iter().find(|a| a.statement.contains("LOYER") && a.date.format("%m").to_string() == "02").unwrap().row_id.unwrap();
+
+    assert!(set_manual_tags(arc_db.clone(), transfer, &[given("LOYER"), removed("LOYER")]).is_err(), "A tag can not be given and removed");
+    assert!(set_manual_tags(arc_db.clone(), 1000, &[given("LOYER")]).is_err(), "Unknown activity should fail");
+    assert!(set_manual_tags(arc_db.clone(), transfer, &[given("loyer"), removed("LOYER")]).is_err(), "A tag can not be given and removed whatever its case");
+    assert!(set_manual_tags(arc_db.clone(), transfer, &[given("LOGEMENT/LOYER")]).is_err(), "A new tag can not contain the path separator");
+    assert!(set_manual_tags(arc_db.clone(), 1000, &[given("SANTE")]).is_err(), "Unknown activity should fail");
+    assert!(!arc_db.lock().unwrap().get_tags()?.iter().any(|t| t.tag == "SANTE"), "The tags of a failed change should not be created");
+    let saved = set_manual_tags(arc_db.clone(), transfer, &[given("loyer"), given("LEBONCOIN"), removed("VIREMENT_BANCAIRE")])?;
+    assert_eq!(saved, vec![given("LOYER"), given("LEBONCOIN"), removed("VIREMENT_BANCAIRE")], "An existing tag should be found whatever its case");
+    set_manual_tags(arc_db.clone(), rent, &[removed("LOYER")])?;
+    assert_eq!(rent_in(&arc_db, 5)?, Some("10.00".to_string()), "A tag given by hand should be counted");
+    assert_eq!(rent_in(&arc_db, 2)?, None, "A tag removed by hand should not be counted");
+
+    retag(arc_db.clone(), &[])?;
+    assert_eq!(rent_in(&arc_db, 5)?, Some("10.00".to_string()), "Manual tags should survive retagging");
+
+    let saved = arc_db.lock().unwrap().get_saved_tag_overrides()?;
+    assert_eq!(saved.len(), 4, "All the manual tags should be saved");
+    // The database rebuilt from the same statements
+    let arc_db = tagged_test_db()?;
+    assert_eq!(restore_manual_tags(arc_db.clone(), &saved)?, 4, "Manual tags should find their activities after a rebuild");
+    assert_eq!((rent_in(&arc_db, 5)?, rent_in(&arc_db, 2)?), (Some("10.00".to_string()), None), "Manual tags should survive a rebuild");
+
+    let activities = arc_db.lock().unwrap().get_activities(None)?;
+    let transfer = activities.iter().find(|a| a.statement.contains("VIREMENT")).unwrap();
+    assert_eq!(transfer.tag_overrides, vec![given("LEBONCOIN"), given("LOYER"), removed("VIREMENT_BANCAIRE")], "Manual tags should be served with the activity");
+
+    assert_eq!(clear_manual_tags(arc_db.clone(), transfer.row_id.unwrap(), Some("loyer"))?, 1, "Only the given tag should be cleared");
+    assert_eq!(rent_in(&arc_db, 5)?, None, "A cleared tag should be given back to the rules");
+    assert_eq!(clear_manual_tags(arc_db.clone(), transfer.row_id.unwrap(), None)?, 2, "All the manual tags should be cleared");
+
+    Ok(())
+}
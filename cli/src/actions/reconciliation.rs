@@ -0,0 +1,139 @@
+use itertools::Itertools;
+
+use crate::db::{ArcMutDB, DBActions};
+use crate::models::{AccountActivity, AccountBalance, DateRange};
+use crate::models::reconciliation::ReconciliationInterval;
+use crate::money::Money;
+
+/**
+ * Check every interval between 2 consecutive balances of an account:
+ * balance(from) + sum(activities in (from, to]) should be balance(to)
+ */
+pub fn reconcile_balances(balances: &[AccountBalance], activities: &[AccountActivity]) -> Vec<ReconciliationInterval> {
+    let balances_per_account = balances
+        .iter()
+        .filter_map(|b| b.account_id.map(|account_id| (account_id, b)))
+        .into_group_map();
+
+    let mut intervals: Vec<ReconciliationInterval> = Vec::new();
+    for (account_id, mut account_balances) in balances_per_account {
+        account_balances.sort_by_key(|b| b.date);
+
+        for (opening, closing) in account_balances.into_iter().tuple_windows() {
+            let period_activities: Vec<&AccountActivity> = activities
+                .iter()
+                .filter(|a| a.account_id == Some(account_id) && a.date > opening.date && a.date <= closing.date)
+                .collect();
+
+            let expected_delta = closing.balance_euro - opening.balance_euro;
+            let actual_delta: Money = period_activities.iter().map(|a| a.amount).sum();
+            let discrepancy = expected_delta - actual_delta;
+
+            intervals.push(ReconciliationInterval {
+                account_id,
+                from: opening.date,
+                to: closing.date,
+                opening_balance: opening.balance_euro,
+                closing_balance: closing.balance_euro,
+                expected_delta,
+                actual_delta,
+                discrepancy,
+                activities: period_activities.len(),
+                reconciled: discrepancy == Money::ZERO,
+            });
+        }
+    }
+
+    intervals.sort_by_key(|i| (i.account_id, i.from));
+    intervals
+}
+
+/**
+ * Reconcile the balances and activities stored of an account, or of all accounts
+ */
+pub fn reconcile<T: DBActions>(arc_db: ArcMutDB<T>, account_id: Option<u32>) -> anyhow::Result<Vec<ReconciliationInterval>> {
+    let db = arc_db.lock().unwrap();
+    let balances = db.get_balance_history(account_id, &DateRange::default())?;
+    // An activity comes once per tag: keep it once
+    let activities: Vec<AccountActivity> = db
+        .get_activities(account_id)?
+        .into_iter()
+        .unique_by(|a| a.row_id)
+        .collect();
+
+    Ok(reconcile_balances(&balances, &activities))
+}
+
+/**
+ * Print the intervals, the ones not adding up first
+ */
+pub fn print_reconciliation(intervals: &[ReconciliationInterval]) {
+    let (failed, reconciled): (Vec<&ReconciliationInterval>, Vec<&ReconciliationInterval>) = intervals
+        .iter()
+        .partition(|i| !i.reconciled);
+
+    for interval in failed.iter().chain(reconciled.iter()) {
+        println!(
+            "{} account {} from {} to {} : expected {}, activities {} ({}), discrepancy {}",
+            if interval.reconciled { "OK     " } else { "MISSING" },
+            interval.account_id, interval.from, interval.to,
+            interval.expected_delta, interval.actual_delta, interval.activities, interval.discrepancy
+        );
+    }
+    println!("{} intervals, {} not adding up", intervals.len(), failed.len());
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+    use crate::actions::reconciliation::reconcile_balances;
+    use crate::models::{AccountActivity, AccountBalance, ActivityDetails};
+    use crate::money::Money;
+
+    fn balance(account_id: u32, day: u32, amount: i64) -> AccountBalance {
+        AccountBalance { row_id: None, account_id: Some(account_id), date: NaiveDate::from_ymd(2021, 3, day), balance_euro: Money::from_cents(amount) }
+    }
+
+    fn activity(account_id: u32, day: u32, amount: i64) -> AccountActivity {
+        AccountActivity {
+            row_id: None,
+            account_id: Some(account_id),
+            date: NaiveDate::from_ymd(2021, 3, day),
+            statement: format!("ACTIVITY {}", day),
+            amount: Money::from_cents(amount),
+            tag_pattern_id: None,
+            tag_pattern_ids: Vec::new(),
+            tag_overrides: Vec::new(),
+            transaction_id: None,
+            details: ActivityDetails::default(),
+        }
+    }
+
+    #[test]
+    fn test_reconcile_balances() {
+        let balances = vec![balance(1, 1, 10000), balance(1, 10, 7010), balance(1, 20, 5000), balance(2, 5, 100000)];
+        let activities = vec![
+            // Before the first balance: ignored
+            activity(1, 1, -50000),
+            activity(1, 3, -2000),
+            activity(1, 10, -990),
+            // Missing a -20.10 activity between the 10th and the 20th
+            activity(1, 15, 0),
+            activity(2, 6, -1000),
+        ];
+
+        let intervals = reconcile_balances(&balances, &activities);
+        assert_eq!(intervals.len(), 2, "One interval per consecutive balances of an account");
+
+        let first = &intervals[0];
+        assert!(first.reconciled, "The first interval should add up");
+        assert_eq!(first.activities, 2, "Activities of (from, to] expected");
+        assert_eq!(first.expected_delta, Money::from_cents(-2990), "Wrong expected delta");
+
+        let second = &intervals[1];
+        assert!(!second.reconciled, "The second interval should not add up");
+        assert_eq!(second.discrepancy, Money::from_cents(-2010), "Wrong discrepancy");
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::HashMap;
+
+use itertools::Itertools;
+
+use crate::actions::manual_tags::set_manual_tags;
+use crate::actions::tagging::add_rule;
+use crate::classifier::{NaiveBayes, features};
+use crate::db::{ArcMutDB, DBActions};
+use crate::errors::RequestError;
+use crate::models::tagging::{AcceptAs, Accepted, MatchKind, RuleDefinition, RuleOptions, Suggestion, SuggestionAcceptance, TagOverride};
+use crate::models::{AccountActivity, DateRange};
+
+/**
+ * Confidence of the suggestions when none is given
+ */
+pub const MIN_CONFIDENCE: f64 = 0.5;
+
+/**
+ * Train the classifier on the activities with tags (given by the rules or by hand), returns it with the activities without tag
+ */
+fn train<T: DBActions>(sqlite_db: &T) -> anyhow::Result<(NaiveBayes, Vec<AccountActivity>)> {
+    let tags: HashMap<u32, String> = sqlite_db.get_tags()?.into_iter().map(|t| (t.id, t.tag)).collect();
+    let tags_per_activity = sqlite_db
+        .get_tagged_amounts(None, &DateRange::default())?
+        .into_iter()
+        .filter_map(|t| tags.get(&t.tag_id).map(|tag| (t.activity_id, tag)))
+        .into_group_map();
+
+    let mut classifier = NaiveBayes::default();
+    let mut untagged: Vec<AccountActivity> = Vec::new();
+    for activity in sqlite_db.get_activities(None)? {
+        match activity.row_id.and_then(|id| tags_per_activity.get(&id)) {
+            Some(activity_tags) => {
+                let activity_features = features(&activity.statement, activity.amount);
+                for tag in activity_tags.iter().unique() {
+                    classifier.train(&activity_features, tag);
+                }
+            }
+            None => untagged.push(activity),
+        }
+    }
+    Ok((classifier, untagged))
+}
+
+/**
+ * Tags suggested for the activities without tag, the most confident first
+ */
+pub fn suggestions<T: DBActions>(arc_db: ArcMutDB<T>, account_id: Option<u32>, min_confidence: f64) -> anyhow::Result<Vec<Suggestion>> {
+    let (classifier, untagged) = train(&*arc_db.lock().unwrap())?;
+
+    let mut suggestions: Vec<Suggestion> = Vec::new();
+    for activity in untagged.iter().filter(|a| account_id.is_none() || a.account_id == account_id) {
+        for (tag, confidence) in classifier.predict(&features(&activity.statement, activity.amount)) {
+            if confidence < min_confidence {
+                break;
+            }
+            suggestions.push(Suggestion {
+                activity_id: activity.row_id.unwrap_or_default(),
+                date: activity.date,
+                statement: activity.statement.clone(),
+                amount: activity.amount,
+                tag,
+                confidence,
+            });
+        }
+    }
+    suggestions.sort_by(|a, b| b.confidence.total_cmp(&a.confidence).then_with(|| b.date.cmp(&a.date)));
+    Ok(suggestions)
+}
+
+/**
+ * Give the suggested tag to the activity by hand (on top of its manual tags), or add a rule giving it
+ * to the activity and to the similar ones
+ */
+pub fn accept_suggestion<T: DBActions>(arc_db: ArcMutDB<T>, acceptance: &SuggestionAcceptance) -> anyhow::Result<Accepted> {
+    let (classifier, activity) = {
+        let sqlite_db = arc_db.lock().unwrap();
+        let (classifier, _) = train(&*sqlite_db)?;
+        let activity = sqlite_db
+            .get_activities(None)?
+            .into_iter()
+            .find(|a| a.row_id == Some(acceptance.activity_id))
+            .ok_or_else(|| RequestError::NotFound(format!("Unknown activity {}", acceptance.activity_id)))?;
+        (classifier, activity)
+    };
+
+    match acceptance.accept_as {
+        AcceptAs::Manual => {
+            let tag_overrides: Vec<TagOverride> = activity
+                .tag_overrides
+                .into_iter()
+                .filter(|o| o.tag != acceptance.tag)
+                .chain(std::iter::once(TagOverride { tag: acceptance.tag.clone(), assigned: true }))
+                .collect();
+            Ok(Accepted::ManualTags(set_manual_tags(arc_db, acceptance.activity_id, &tag_overrides)?))
+        }
+        AcceptAs::Rule => {
+            let pattern = acceptance
+                .pattern
+                .clone()
+                .or(activity.details.merchant)
+                .or_else(|| classifier.best_word(&features(&activity.statement, activity.amount), &acceptance.tag))
+                .ok_or_else(|| RequestError::Invalid(format!("No pattern found for a rule from activity {}, give one", acceptance.activity_id)))?;
+            let definition = RuleDefinition {
+                pattern,
+                match_kind: MatchKind::Word,
+                rule: None,
+                options: RuleOptions::default(),
+                tags: vec![acceptance.tag.clone()],
+            };
+            Ok(Accepted::Rule(add_rule(arc_db, &definition)?))
+        }
+    }
+}
+
+/**
+ * Print the suggestions, the most confident first
+ */
+pub fn print_suggestions(suggestions: &[Suggestion]) {
+    for suggestion in suggestions {
+        println!(
+            "{} {} {} : {} ({:.0}%)",
+            suggestion.date,
+            suggestion.statement,
+            suggestion.amount,
+            suggestion.tag,
+            suggestion.confidence * 100.0
+        );
+    }
+    println!("{} tags suggested for {} activities", suggestions.len(), suggestions.iter().map(|s| s.activity_id).unique().count());
+}
+
+#[test]
+fn test_suggestions() -> anyhow::Result<()> {
+    use chrono::NaiveDate;
+    use crate::db::sqlite::tests::tagged_test_db;
+    use crate::label::parsing::fill_details;
+    use crate::models::ActivityDetails;
+
+    let arc_db = tagged_test_db()?;
+
+    let card = |statement: &str, date: NaiveDate, amount: &str| -> anyhow::Result<AccountActivity> {
+        let mut details = ActivityDetails::default();
+        fill_details(statement, date, &mut details);
+        Ok(AccountActivity {
+            row_id: None, account_id: Some(1), date, statement: statement.to_string(), amount: amount.parse()?,
+            tag_pattern_id: None, tag_pattern_ids: Vec::new(), tag_overrides: Vec::new(), transaction_id: None, details
+        })
+    };
+    let ids = arc_db.lock().unwrap().insert_activities(&[
+        card("CARTE X1234 01/08 MONOPRIX PARIS", NaiveDate::from_ymd(2021, 8, 3), "-23.10")?,
+        card("CARTE X1234 10/08 MONOPRIX PARIS", NaiveDate::from_ymd(2021, 8, 12), "-8.20")?,
+        card("CARTE X1234 20/08 MONOPRIX NICE", NaiveDate::from_ymd(2021, 8, 23), "-15.00")?,
+    ])?;
+    let groceries = TagOverride { tag: "COURSES".to_string(), assigned: true };
+    set_manual_tags(arc_db.clone(), ids[0], std::slice::from_ref(&groceries))?;
+    set_manual_tags(arc_db.clone(), ids[1], std::slice::from_ref(&groceries))?;
+
+    let found = suggestions(arc_db.clone(), None, MIN_CONFIDENCE)?;
+    assert_eq!(
+        found.iter().map(|s| (s.activity_id, s.tag.as_str())).collect_vec(), vec![(ids[2], "COURSES")], 
+        "Only the activity like the ones tagged COURSES should get a confident suggestion"
+    );
+    assert!(found[0].confidence < 1.0, "Wrong confidence {}", found[0].confidence);
+    let all = suggestions(arc_db.clone(), None, 0.0)?;
+    assert!(all.len() > found.len(), "Suggestions below the confidence asked should be left out");
+    assert!(all.windows(2).all(|w| w[0].confidence >= w[1].confidence), "The most confident suggestions should be first");
+    let tagged: Vec<u32> = arc_db.lock().unwrap().get_activities(None)?.into_iter().filter(|a| !a.tag_pattern_ids.is_empty()).filter_map(|a| a.row_id).collect();
+    assert!(!all.iter().any(|s| tagged.contains(&s.activity_id) || ids[..2].contains(&s.activity_id)), "Only the activities without tag should get suggestions");
+
+    let manual = SuggestionAcceptance { activity_id: ids[2], tag: "COURSES".to_string(), accept_as: AcceptAs::Manual, pattern: None };
+    assert_eq!(accept_suggestion(arc_db.clone(), &manual)?, Accepted::ManualTags(vec![groceries]), "The tag should be given by hand");
+    assert!(!suggestions(arc_db.clone(), None, 0.0)?.iter().any(|s| s.activity_id == ids[2]), "An accepted suggestion should not be suggested again");
+
+    let rule = SuggestionAcceptance { activity_id: ids[2], tag: "COURSES".to_string(), accept_as: AcceptAs::Rule, pattern: None };
+    match accept_suggestion(arc_db.clone(), &rule)? {
+        Accepted::Rule(report) => assert_eq!(report.activities, 3, "The rule should tag the activities of the same merchant"),
+        accepted => panic!("A rule should be added, got {:?}", accepted),
+    }
+    let patterns = arc_db.lock().unwrap().get_tag_patterns()?;
+    assert!(patterns.iter().any(|p| p.pattern == "MONOPRIX" && p.tag == "COURSES"), "The rule should be written from the merchant");
+
+    Ok(())
+}
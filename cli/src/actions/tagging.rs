@@ -1,5 +1,8 @@
-use crate::models::tagging::ActivityToTags;
+use crate::models::AccountActivity;
+use crate::models::tagging::{ActivityToTags, CompetingRule, InvalidRule, RetagReport, Rule, RuleDefinition, RuleOptions, Tag, TagConflict, TagsPattern};
+use itertools::Itertools;
 use crate::db::{ArcMutDB, DBActions};
+use crate::errors::RequestError;
 
 
 
@@ -8,17 +11,81 @@ use crate::db::{ArcMutDB, DBActions};
  */
 pub fn tagging<T: DBActions>(arc_db : ArcMutDB<T>) -> anyhow::Result<usize> {
     let mut sqlite_db = arc_db.lock().unwrap();
+    let activities = sqlite_db.get_activities(None)?;
+    tag_activities(&mut *sqlite_db, activities)
+}
+
+/**
+ * Same as `tagging` but only for the given activities (ie. the ones newly imported)
+ */
+pub fn tagging_activities<T: DBActions>(arc_db : ArcMutDB<T>, activity_ids: &[u32]) -> anyhow::Result<usize> {
+    if activity_ids.is_empty() {
+        return Ok(0);
+    }
+    let mut sqlite_db = arc_db.lock().unwrap();
+    let activities = sqlite_db.get_activities_by_ids(activity_ids)?;
+    tag_activities(&mut *sqlite_db, activities)
+}
+
+/**
+ * A rule ready to be applied, with its options
+ */
+struct CompiledRule<'a> {
+    id: u32,
+    options: &'a RuleOptions,
+    rule: Rule,
+}
+
+/**
+ * Compile each rule once (a rule has one row per tag), sorted in the order they are applied.
+ * Rules saved before being validated (ie. in the init script) are skipped when invalid, and returned apart.
+ */
+fn compile_patterns(tags_patterns: &[TagsPattern]) -> (Vec<CompiledRule<'_>>, Vec<InvalidRule>) {
+    let mut rules: Vec<CompiledRule> = Vec::new();
+    let mut invalid_rules: Vec<InvalidRule> = Vec::new();
+    for p in tags_patterns.iter().unique_by(|p| p.id) {
+        match p.compile() {
+            Ok(rule) => rules.push(CompiledRule { id: p.id, options: &p.options, rule }),
+            Err(err) => invalid_rules.push(InvalidRule { id: p.id, error: err.to_string() }),
+        }
+    }
+    rules.sort_by_key(|r| (std::cmp::Reverse(r.options.priority), r.id));
+    (rules, invalid_rules)
+}
+
+/**
+ * Apply the rules to an activity, by priority, until a rule with `stop` matches.
+ * The first rule with a category gives the primary category, the next ones with a category are not applied.
+ * Returns the rules applied (the primary one flagged) and all the rules with a category matching the activity.
+ */
+fn apply_rules<'a>(rules: &'a [CompiledRule], activity: &AccountActivity) -> (Vec<ActivityToTags>, Vec<&'a CompiledRule<'a>>) {
+    let mut applied: Vec<ActivityToTags> = Vec::new();
+    let mut with_category: Vec<&CompiledRule> = Vec::new();
+    let activity_id = activity.row_id.unwrap_or_default();
+
+    for rule in rules.iter().filter(|r| r.rule.is_match(activity)) {
+        if rule.options.category.is_some() {
+            if with_category.is_empty() {
+                applied.push(ActivityToTags { activity_id, tags_pattern_id: rule.id, primary: true, captured_tag: rule.rule.captured_tag(activity) });
+            }
+            with_category.push(rule);
+        } else {
+            applied.push(ActivityToTags { activity_id, tags_pattern_id: rule.id, primary: false, captured_tag: rule.rule.captured_tag(activity) });
+        }
+        if rule.options.stop {
+            break;
+        }
+    }
+    (applied, with_category)
+}
+
+fn tag_activities<T: DBActions>(sqlite_db: &mut T, activities: Vec<AccountActivity>) -> anyhow::Result<usize> {
     let tags_patterns = sqlite_db.get_tag_patterns()?;
-    let activities = sqlite_db.get_activities()?;
+    let (rules, _) = compile_patterns(&tags_patterns);
     let mut activity_tags: Vec<ActivityToTags> = Vec::new();
 
     for activity in activities {
-        for p in tags_patterns.iter() {
-            if  activity.statement.to_lowercase().contains(&p.pattern.to_lowercase()) || 
-                activity.amount.to_string().contains(&p.pattern.to_lowercase()){ //some activities only can be tagged from amount
-                activity_tags.push(ActivityToTags{ activity_id : activity.row_id.unwrap(), tags_pattern_id : p.id});
-            }
-        }
+        activity_tags.extend(apply_rules(&rules, &activity).0);
     }
 
     let result = sqlite_db.insert_activity_tags(&activity_tags)?;
@@ -26,23 +93,167 @@ pub fn tagging<T: DBActions>(arc_db : ArcMutDB<T>) -> anyhow::Result<usize> {
     Ok(result)
 }
 
+/**
+ * Recompute the tags of the activities matched by the given rules or tagged by them, in one transaction.
+ * Without rules, the tags of all the activities are recomputed (ie. after editing `tags_pattern` by hand).
+ */
+pub fn retag<T: DBActions>(arc_db : ArcMutDB<T>, rule_ids: &[u32]) -> anyhow::Result<RetagReport> {
+    let mut sqlite_db = arc_db.lock().unwrap();
+    retag_rules(&mut *sqlite_db, rule_ids, &[])
+}
 
-#[test]
-fn test() -> anyhow::Result<()> {
-    use std::sync::{Arc, Mutex};
-    use crate::actions::csv2db::csv2db;    
-    use crate::db::sqlite::SqliteDB;
-    use crate::db::DBConfig;
-    
+/**
+ * Save a new rule and tag the activities it matches, the rule (and its new tags) are kept only if the retagging succeeds
+ */
+pub fn add_rule<T: DBActions>(arc_db : ArcMutDB<T>, definition: &RuleDefinition) -> anyhow::Result<RetagReport> {
+    let mut sqlite_db = arc_db.lock().unwrap();
+    sqlite_db.in_transaction(|db| {
+        let tag_ids = insert_tags(db, &definition.tags)?;
+        let id = db.insert_tag_pattern(&definition.pattern, definition.match_kind, definition.rule.as_ref(), &definition.options, &tag_ids)?;
+        retag_rules(db, &[id], &[])
+    })
+}
+
+/**
+ * Replace a rule and retag the activities matched by its previous version or by the new one, in one transaction
+ */
+pub fn update_rule<T: DBActions>(arc_db : ArcMutDB<T>, id: u32, definition: &RuleDefinition) -> anyhow::Result<RetagReport> {
+    let mut sqlite_db = arc_db.lock().unwrap();
+    sqlite_db.in_transaction(|db| {
+        let before = previous_rule(db, id)?;
+        let tag_ids = insert_tags(db, &definition.tags)?;
+        db.update_tag_pattern(id, &definition.pattern, definition.match_kind, definition.rule.as_ref(), &definition.options, &tag_ids)?;
+        retag_rules(db, &[id], &before)
+    })
+}
+
+/**
+ * Remove a rule and retag the activities it matched, in one transaction
+ */
+pub fn delete_rule<T: DBActions>(arc_db : ArcMutDB<T>, id: u32) -> anyhow::Result<RetagReport> {
+    let mut sqlite_db = arc_db.lock().unwrap();
+    sqlite_db.in_transaction(|db| {
+        let before = previous_rule(db, id)?;
+        db.delete_tag_pattern(id)?;
+        retag_rules(db, &[id], &before)
+    })
+}
+
+/**
+ * Replace the tags given by a rule, returns them. No retagging: the activities keep the rules matching them
+ * and get the tags of these rules when read.
+ */
+pub fn set_rule_tags<T: DBActions>(arc_db : ArcMutDB<T>, id: u32, tag_ids: &[u32]) -> anyhow::Result<Vec<Tag>> {
+    let mut sqlite_db = arc_db.lock().unwrap();
+    sqlite_db.set_tag_pattern_tags(id, tag_ids)?;
+    Ok(sqlite_db.get_tags()?.into_iter().filter(|t| tag_ids.contains(&t.id)).collect())
+}
+
+fn insert_tags<T: DBActions>(sqlite_db: &T, tags: &[String]) -> anyhow::Result<Vec<u32>> {
+    let tags: Vec<&str> = tags.iter().map(|t| t.trim()).filter(|t| !t.is_empty()).collect();
+    if tags.is_empty() {
+        return Err(RequestError::Invalid("A rule needs at least one tag".to_string()).into());
+    }
+    tags.into_iter().map(|t| sqlite_db.insert_tag(t)).collect()
+}
+
+/**
+ * The rule before its change, none if it was invalid (it was not applied then)
+ */
+fn previous_rule<T: DBActions>(sqlite_db: &T, id: u32) -> anyhow::Result<Vec<Rule>> {
+    let tags_patterns = sqlite_db.get_tag_patterns()?;
+    let pattern = tags_patterns
+        .iter()
+        .find(|p| p.id == id)
+        .ok_or_else(|| RequestError::NotFound(format!("Unknown tag pattern {}", id)))?;
+    Ok(pattern.compile().into_iter().collect())
+}
+
+/**
+ * Only the activities matched by the rules changed (`before` are their previous versions) may get other tags:
+ * a rule applies its tags, gives a category or stops the next rules only where it matches.
+ */
+fn retag_rules<T: DBActions>(sqlite_db: &mut T, rule_ids: &[u32], before: &[Rule]) -> anyhow::Result<RetagReport> {
+    let tags_patterns = sqlite_db.get_tag_patterns()?;
+    let (rules, invalid_rules) = compile_patterns(&tags_patterns);
+    let changed: Vec<&Rule> = rules
+        .iter()
+        .filter(|r| rule_ids.contains(&r.id))
+        .map(|r| &r.rule)
+        .chain(before)
+        .collect();
+
+    let activities: Vec<AccountActivity> = sqlite_db
+        .get_activities(None)?
+        .into_iter()
+        .filter(|a| {
+            rule_ids.is_empty()
+                || a.tag_pattern_ids.iter().any(|id| rule_ids.contains(id))
+                || changed.iter().any(|r| r.is_match(a))
+        })
+        .collect();
+
+    let activity_ids: Vec<u32> = activities.iter().filter_map(|a| a.row_id).collect();
+    let activity_tags: Vec<ActivityToTags> = activities
+        .iter()
+        .flat_map(|a| apply_rules(&rules, a).0)
+        .collect();
+    let result = sqlite_db.replace_activity_tags(&activity_ids, &activity_tags)?;
+
+    Ok(RetagReport { rules: rule_ids.to_vec(), activities: activity_ids.len(), activity_tags: result, invalid_rules })
+}
+
+/**
+ * Activities matched by rules giving different categories, with the rules competing for them
+ */
+pub fn conflicts<T: DBActions>(arc_db : ArcMutDB<T>, account_id: Option<u32>) -> anyhow::Result<Vec<TagConflict>> {
+    let sqlite_db = arc_db.lock().unwrap();
+    let tags_patterns = sqlite_db.get_tag_patterns()?;
+    let (rules, _) = compile_patterns(&tags_patterns);
+
+    let mut conflicts: Vec<TagConflict> = Vec::new();
+    for activity in sqlite_db.get_activities(account_id)? {
+        let (_, with_category) = apply_rules(&rules, &activity);
+        let competing: Vec<CompetingRule> = with_category
+            .iter()
+            .map(|r| CompetingRule { id: r.id, priority: r.options.priority, category: r.options.category.clone().unwrap_or_default() })
+            .collect();
+        if competing.iter().map(|r| &r.category).unique().count() < 2 {
+            continue;
+        }
+        conflicts.push(TagConflict {
+            activity_id: activity.row_id.unwrap_or_default(),
+            date: activity.date,
+            category: competing[0].category.clone(),
+            tie: competing[0].priority == competing[1].priority,
+            statement: activity.statement,
+            amount: activity.amount,
+            rules: competing,
+        });
+    }
+    Ok(conflicts)
+}
 
-    let sqlite_db = 
-        SqliteDB::from_config(DBConfig::Memory)
-        .with_init_db_script("./data/init-db-test.toml".to_string());
+pub fn print_conflicts(conflicts: &[TagConflict]) {
+    for conflict in conflicts {
+        println!(
+            "{} {} {} : {} ({})",
+            conflict.date,
+            conflict.statement,
+            conflict.amount,
+            conflict.category,
+            conflict.rules.iter().map(|r| format!("rule {} priority {} -> {}", r.id, r.priority, r.category)).join(", ")
+        );
+    }
+    println!("{} activities with conflicting categories, {} decided by a tie", conflicts.len(), conflicts.iter().filter(|c| c.tie).count());
+}
 
-    let arc_db = Arc::new(Mutex::new(sqlite_db));
-    csv2db("./data/", arc_db.clone())?;
-    tagging(arc_db.clone())?;
 
+#[test]
+fn test() -> anyhow::Result<()> {
+    use crate::db::sqlite::tests::tagged_test_db;
+
+    let arc_db = tagged_test_db()?;
     let db = arc_db.lock().unwrap();
     
     let check_query: usize = db.connection()
@@ -62,4 +273,254 @@ fn test() -> anyhow::Result<()> {
 
     Ok(())
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_categories_and_conflicts() -> anyhow::Result<()> {
+    use std::collections::HashMap;
+    use crate::db::sqlite::tests::tagged_test_db;
+    use crate::models::tagging::MatchKind;
+    use crate::money::Money;
+
+    let arc_db = tagged_test_db()?;
+    {
+        let mut db = arc_db.lock().unwrap();
+        let shopping = db.insert_tag("SHOPPING")?;
+        let category = RuleOptions { category: Some("SHOPPING".to_string()), ..RuleOptions::default() };
+        db.insert_tag_pattern("BUY SOMETHING", MatchKind::Prefix, None, &category, &[shopping])?;
+        let stop = RuleOptions { priority: 20, stop: true, category: None };
+        db.insert_tag_pattern("EDF 04", MatchKind::Word, None, &stop, &[shopping])?;
+    }
+    retag(arc_db.clone(), &[])?;
+
+    let found = conflicts(arc_db.clone(), None)?;
+    let free = found.iter().find(|c| c.statement.contains("FREE MOBILE")).expect("FREE MOBILE conflict not found");
+    assert_eq!(free.category, "TELEPHONE", "The oldest rule should win a tie");
+    assert!(free.tie, "Rules of the same priority should be a tie");
+    let rent = found.iter().find(|c| c.statement.contains("LOYER")).expect("LOYER conflict not found");
+    assert_eq!((rent.category.as_str(), rent.tie), ("LOGEMENT", false), "The rule of higher priority should win");
+    assert!(!found.iter().any(|c| c.statement.contains("EDF")), "No rule should be applied after a stop");
+    assert!(!found.iter().any(|c| c.statement.contains("VIREMENT")), "A rule without category is not competing");
+
+    let db = arc_db.lock().unwrap();
+    let activities = db.get_activities(None)?;
+    assert_eq!(activities.iter().map(|a| a.row_id).unique().count(), activities.len(), "Each activity should be returned once");
+
+    let phone = activities.iter().find(|a| a.statement.contains("FREE MOBILE")).unwrap();
+    assert_eq!((phone.tag_pattern_id, phone.tag_pattern_ids.as_slice()), (Some(5), [5].as_slice()), "The losing category should not be applied");
+    let edf = activities.iter().find(|a| a.statement.contains("EDF")).unwrap();
+    assert_eq!((edf.tag_pattern_id, edf.tag_pattern_ids.len()), (None, 1), "Only the stopping rule should be applied");
+
+    let mut totals: HashMap<u32, Money> = HashMap::new();
+    for activity in activities.iter() {
+        let month_year = activity.date.format("%Y%m").to_string().parse()?;
+        *totals.entry(month_year).or_default() += activity.amount;
+    }
+    let mut by_category: HashMap<u32, Money> = HashMap::new();
+    for stat in db.get_stats_category_per_month(None)? {
+        *by_category.entry(stat.month_year).or_default() += stat.amount;
+    }
+    assert_eq!(by_category, totals, "The categories of a month should add up to its total");
+
+    Ok(())
+}
+
+#[test]
+fn test_retag_rule_changes() -> anyhow::Result<()> {
+    use crate::db::sqlite::tests::tagged_test_db;
+    use crate::models::tagging::MatchKind;
+
+    let arc_db = tagged_test_db()?;
+
+    let activity_tags = || -> anyhow::Result<Vec<(u32, u32, bool)>> {
+        let db = arc_db.lock().unwrap();
+        let mut stmt = db.connection().prepare("SELECT activity_id, tags_pattern_id, is_primary FROM activities_tags ORDER BY 1, 2")?;
+        let rows = stmt.query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?;
+        Ok(rows.collect::<rusqlite::Result<_>>()?)
+    };
+    // Retagging only the activities of a change should give the tags of a full retagging
+    let assert_retagged = |step: &str| -> anyhow::Result<()> {
+        let incremental = activity_tags()?;
+        retag(arc_db.clone(), &[])?;
+        assert_eq!(incremental, activity_tags()?, "Wrong tags after {}", step);
+        Ok(())
+    };
+    let edf_rules = || -> anyhow::Result<Vec<u32>> {
+        let activities = arc_db.lock().unwrap().get_activities(None)?;
+        Ok(activities.into_iter().filter(|a| a.statement.contains("EDF")).flat_map(|a| a.tag_pattern_ids).unique().collect())
+    };
+
+    let mut groceries = RuleDefinition {
+        pattern: "BUY SOMETHING 04".to_string(),
+        match_kind: MatchKind::Prefix,
+        rule: None,
+        options: RuleOptions { priority: 20, stop: true, category: Some("COURSES".to_string()) },
+        tags: vec!["COURSES".to_string()],
+    };
+    let added = add_rule(arc_db.clone(), &groceries)?;
+    assert_eq!(added.activities, 4, "Only the activities matched by the new rule should be retagged");
+    assert_retagged("adding a rule")?;
+    let id = added.rules[0];
+
+    groceries.pattern = "EDF".to_string();
+    groceries.match_kind = MatchKind::Word;
+    assert!(update_rule(arc_db.clone(), id, &groceries).is_err(), "A rule can not be edited into another one");
+    groceries.match_kind = MatchKind::Substring;
+    let updated = update_rule(arc_db.clone(), id, &groceries)?;
+    assert_eq!(updated.activities, 7, "The activities matched before and after the change should be retagged");
+    assert_retagged("editing a rule")?;
+    assert_eq!(edf_rules()?, vec![id], "The rule should now stop the others");
+
+    let deleted = delete_rule(arc_db.clone(), id)?;
+    assert_eq!(deleted.activities, 3, "Only the activities of the removed rule should be retagged");
+    assert_retagged("removing a rule")?;
+    assert_eq!(edf_rules()?, vec![1], "The rule stopped should be applied again");
+
+    assert!(update_rule(arc_db.clone(), id, &groceries).is_err(), "A removed rule can not be edited");
+    groceries.tags = vec![" ".to_string()];
+    assert!(add_rule(arc_db.clone(), &groceries).is_err(), "A rule needs a tag");
+
+    groceries.pattern = "^BUY (SOMETHING".to_string();
+    groceries.match_kind = MatchKind::Regex;
+    groceries.tags = vec!["ALIMENTATION".to_string()];
+    assert!(add_rule(arc_db.clone(), &groceries).is_err(), "An invalid rule can not be added");
+    let tags = arc_db.lock().unwrap().get_tags()?;
+    assert!(!tags.iter().any(|t| t.tag == "ALIMENTATION"), "The tags of a rule not added should not be kept");
+    assert!(arc_db.lock().unwrap().connection().is_autocommit(), "The transaction of a failed change should be ended");
+
+    // A rule written by hand is not validated
+    arc_db.lock().unwrap().connection().execute_batch("
+        INSERT INTO tags_pattern (id, tags_pattern, match_kind) VALUES (100, '^PRLV (FREE', 'regex');
+        INSERT INTO tags_pattern_to_tags (tags_pattern_id, tags_id) VALUES (100, 2);
+    ")?;
+    let retagged = retag(arc_db.clone(), &[])?;
+    assert_eq!(retagged.invalid_rules.len(), 1, "The invalid rule should be reported");
+    assert_eq!(retagged.invalid_rules[0].id, 100, "Wrong invalid rule reported");
+    assert!(retagged.invalid_rules[0].error.starts_with("Invalid regex '^PRLV (FREE'"), "Wrong error : {}", retagged.invalid_rules[0].error);
+
+    Ok(())
+}
+
+#[test]
+fn test_rule_tags() -> anyhow::Result<()> {
+    use crate::db::sqlite::tests::tagged_test_db;
+    use crate::money::Money;
+
+    let arc_db = tagged_test_db()?;
+
+    let total = |tag: &str| -> anyhow::Result<Money> {
+        let stats = arc_db.lock().unwrap().get_stats_tag_per_month(&[tag.to_string()], None)?;
+        Ok(stats.iter().map(|s| s.amount).sum())
+    };
+    let (paris, free_mobile) = (total("PARIS")?, total("FREEMOBILE")?);
+    assert_ne!(free_mobile, Money::from_cents(0), "FREE MOBILE should be in the test data");
+
+    let tags = set_rule_tags(arc_db.clone(), 5, &[2])?;
+    assert_eq!(tags.iter().map(|t| t.tag.as_str()).collect_vec(), vec!["FREEMOBILE"], "Wrong tags returned");
+    assert_eq!(total("PARIS")?, paris - free_mobile, "The tag removed from the rule should be removed from its activities without retagging");
+    assert!(set_rule_tags(arc_db.clone(), 5, &[100]).is_err(), "A rule can not give an unknown tag");
+
+    Ok(())
+}
+
+#[test]
+fn test_captured_tags() -> anyhow::Result<()> {
+    use crate::actions::manual_tags::set_manual_tags;
+    use crate::db::sqlite::tests::tagged_test_db;
+    use crate::models::tagging::{MatchKind, TagOverride};
+    use crate::money::Money;
+
+    let arc_db = tagged_test_db()?;
+    let total = |tag: &str| -> anyhow::Result<Money> {
+        let stats = arc_db.lock().unwrap().get_stats_tag_per_month(&[tag.to_string()], None)?;
+        Ok(stats.iter().map(|s| s.amount).sum())
+    };
+    let (edf, free_mobile) = (total("EDF")?, total("FREEMOBILE")?);
+    let tag_count = arc_db.lock().unwrap().get_tags()?.len();
+
+    let subscriptions = RuleDefinition {
+        pattern: r"^BUY SOMETHING (?P<tag>free mobile|edf) \d+$".to_string(),
+        match_kind: MatchKind::Regex,
+        rule: None,
+        options: RuleOptions::default(),
+        tags: vec!["ABONNEMENT".to_string()],
+    };
+    add_rule(arc_db.clone(), &subscriptions)?;
+    assert_eq!(total("ABONNEMENT")?, edf + free_mobile, "The rule should give its tag");
+    assert_eq!(total("FREE MOBILE")?, free_mobile, "The captured text should tag the activity");
+    assert_eq!(total("EDF")?, edf, "An existing tag captured should be reused");
+    assert_eq!(arc_db.lock().unwrap().get_tags()?.len(), tag_count + 2, "Only the rule tag and the new captured tag should be created");
+
+    retag(arc_db.clone(), &[])?;
+    assert_eq!(total("FREE MOBILE")?, free_mobile, "The captured tags should survive retagging");
+
+    let activities = arc_db.lock().unwrap().get_activities(None)?;
+    let phone = activities.iter().find(|a| a.statement.contains("FREE MOBILE")).unwrap();
+    set_manual_tags(arc_db.clone(), phone.row_id.unwrap(), &[TagOverride { tag: "FREE MOBILE".to_string(), assigned: false }])?;
+    assert_eq!(total("FREE MOBILE")?, free_mobile - phone.amount.abs(), "A captured tag removed by hand should not be counted");
+
+    Ok(())
+}
+
+#[test]
+fn test_match_kinds() -> anyhow::Result<()> {
+    use crate::models::tagging::MatchKind;
+
+    let statement = "PRLV SEPA FREE MOBILE ECH/150321";
+
+    assert!(MatchKind::Substring.compile("free")?.is_match("CARTE X1234 FREEBOX"), "Substring should match inside a word");
+    assert!(!MatchKind::Word.compile("free")?.is_match("CARTE X1234 FREEBOX"), "Word should not match inside a word");
+    assert!(MatchKind::Word.compile("free")?.is_match(statement), "Word should match a whole word");
+    assert!(MatchKind::Prefix.compile("prlv sepa")?.is_match(statement), "Wrong prefix match");
+    assert!(!MatchKind::Prefix.compile("FREE")?.is_match(statement), "Prefix should only match the start");
+    assert!(MatchKind::Regex.compile("^PRLV SEPA (FREE|SFR) MOBILE")?.is_match(statement), "Wrong regex match");
+    assert!(!MatchKind::Regex.compile("^PRLV SEPA FREE MOBILE")?.is_match("CARTE X1234 FREE MOBILE"), "Anchored regex should not match");
+    assert!(MatchKind::Regex.compile("(FREE").is_err(), "Invalid regex should fail");
+    assert_eq!(MatchKind::Regex.compile("^PRLV SEPA (?P<tag>free|sfr) MOBILE")?.captured_tag(statement), Some("FREE".to_string()), "Wrong captured tag");
+    assert_eq!(MatchKind::Regex.compile("^PRLV SEPA (FREE|SFR) MOBILE")?.captured_tag(statement), None, "Only the group named tag should be captured");
+    assert_eq!(MatchKind::Regex.compile(r"ECH(?P<tag>/\d+)")?.captured_tag(statement), None, "A capture with the path separator is not a tag");
+    assert!(MatchKind::Substring.compile(" ").is_err(), "Empty pattern should fail");
+
+    Ok(())
+}
+
+#[test]
+fn test_rules() -> anyhow::Result<()> {
+    use chrono::NaiveDate;
+    use crate::models::ActivityDetails;
+    use crate::models::tagging::Condition;
+
+    let activity = |date: NaiveDate, amount: &str| -> anyhow::Result<AccountActivity> {
+        Ok(AccountActivity {
+            row_id: Some(1), account_id: Some(1), date, statement: "VIREMENT POUR AGENCE IMMO".to_string(), amount: amount.parse()?,
+            tag_pattern_id: None, tag_pattern_ids: Vec::new(), tag_overrides: Vec::new(), transaction_id: None, details: ActivityDetails::default()
+        })
+    };
+    let rent: Condition = serde_json::from_str(r#"{"and": [{"amount": "-650.00"}, {"day_of_month": {"from": 1, "to": 5}}]}"#)?;
+    let rent = rent.compile()?;
+
+    assert!(rent.is_match(&activity(NaiveDate::from_ymd(2021, 4, 2), "-650.00")?), "Rent should match");
+    assert!(!rent.is_match(&activity(NaiveDate::from_ymd(2021, 4, 12), "-650.00")?), "Day of month should not match");
+    assert!(!rent.is_match(&activity(NaiveDate::from_ymd(2021, 4, 2), "-65.00")?), "Amount should not match");
+
+    let not_small_debit: Condition = serde_json::from_str(
+        r#"{"or": [{"sign": "credit"}, {"not": {"amount_range": {"min": -10, "max": 0}}}]}"#
+    )?;
+    let not_small_debit = not_small_debit.compile()?;
+    assert!(not_small_debit.is_match(&activity(NaiveDate::from_ymd(2021, 4, 2), "5.00")?), "Credit should match");
+    assert!(!not_small_debit.is_match(&activity(NaiveDate::from_ymd(2021, 4, 2), "-5.00")?), "Small debit should not match");
+    assert!(not_small_debit.is_match(&activity(NaiveDate::from_ymd(2021, 4, 2), "-50.00")?), "Large debit should match");
+
+    let later = Condition::And(vec![
+        Condition::DateRange { from: NaiveDate::from_ymd_opt(2021, 4, 1), to: None },
+        Condition::Account(1),
+        Condition::Label { pattern: "agence".to_string(), match_kind: Default::default() },
+    ]).compile()?;
+    assert!(later.is_match(&activity(NaiveDate::from_ymd(2021, 4, 2), "1.00")?), "Date, account and label should match");
+    assert!(!later.is_match(&activity(NaiveDate::from_ymd(2021, 3, 2), "1.00")?), "Date before the range should not match");
+
+    assert!(Condition::Or(vec![]).compile().is_err(), "Empty list of conditions should fail");
+    assert!(serde_json::from_str::<Condition>(r#"{"amount": "5", "extra": 1}"#).is_err(), "Unknown conditions should fail");
+
+    Ok(())
+}
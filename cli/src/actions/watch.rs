@@ -0,0 +1,180 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use futures::StreamExt;
+use inotify::{EventMask, Inotify, WatchDescriptor, WatchMask, Watches};
+
+use crate::actions::csv2db::{csv2db_files, is_statement_file, print_report};
+use crate::actions::tagging::tagging_activities;
+use crate::csv::archive::is_archive_file;
+use crate::csv::dir::{is_included, list_files};
+use crate::db::{ArcMutDB, DBActions};
+use crate::models::import::ImportOptions;
+
+/**
+ * Watch `dir_path` and its sub folders (the ones created later too), and import every statement (or archive of statements)
+ * created or changed in them. Files written in place are caught on close, files moved in (ie. a finished browser download) on rename.
+ * The globs of the options are matched against the path relative to `dir_path`, as for `--import`.
+ */
+pub async fn watch<T, P>(dir_path: P, arc_db: ArcMutDB<T>, options: ImportOptions) -> anyhow::Result<()>
+where
+    T: DBActions + Send + 'static,
+    P: AsRef<Path>,
+{
+    let dir_path = dir_path.as_ref().to_path_buf();
+    let inotify = Inotify::init()?;
+    let mut watches = inotify.watches();
+    let mut folders: HashMap<WatchDescriptor, PathBuf> = HashMap::new();
+    add_watches(&mut watches, &mut folders, &dir_path)?;
+
+    println!("Watching {} for new statements", dir_path.display());
+
+    let mut buffer = [0; 4096];
+    let mut events = inotify.into_event_stream(&mut buffer)?;
+
+    while let Some(event) = events.next().await {
+        let event = event?;
+        // The folder was removed
+        if event.mask.contains(EventMask::IGNORED) {
+            folders.remove(&event.wd);
+            continue;
+        }
+        let path = match (folders.get(&event.wd), event.name) {
+            (Some(folder), Some(name)) => folder.join(name),
+            _ => continue,
+        };
+
+        let statement_paths = if event.mask.contains(EventMask::ISDIR) {
+            // A folder created or moved in: watched from now on, with the statements already in it
+            add_watches(&mut watches, &mut folders, &path).and_then(|_| list_files(&path, &[], &[]))
+        } else if event.mask.contains(EventMask::CREATE) {
+            // A file is imported once written
+            continue;
+        } else {
+            Ok(vec![path])
+        };
+        let statement_paths: Vec<PathBuf> = match statement_paths {
+            Ok(paths) => paths.into_iter().filter(|p| is_watched(&dir_path, p, &options)).collect(),
+            Err(err) => {
+                eprintln!("Fail watching folder : {:?}", err);
+                continue;
+            }
+        };
+        if statement_paths.is_empty() {
+            continue;
+        }
+
+        let arc_db = arc_db.clone();
+        let options = options.clone();
+        let result = tokio::task::spawn_blocking(move || import(statement_paths, arc_db, &options)).await?;
+        //A bad statement must not stop the watcher
+        if let Err(err) = result {
+            eprintln!("Fail importing statement : {:?}", err);
+        }
+    }
+
+    Ok(())
+}
+
+/**
+ * Watch the folder and all its sub folders, remembering the folder of each watch to rebuild the paths of the events
+ */
+fn add_watches(watches: &mut Watches, folders: &mut HashMap<WatchDescriptor, PathBuf>, folder: &Path) -> anyhow::Result<()> {
+    let mut pending: Vec<PathBuf> = vec![folder.to_path_buf()];
+    while let Some(folder) = pending.pop() {
+        let wd = watches.add(&folder, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO | WatchMask::CREATE)?;
+        for entry in std::fs::read_dir(&folder)? {
+            let entry = entry?;
+            if entry.file_type()?.is_dir() {
+                pending.push(entry.path());
+            }
+        }
+        folders.insert(wd, folder);
+    }
+    Ok(())
+}
+
+/**
+ * A statement or an archive of statements, not filtered out by the globs
+ */
+fn is_watched(dir_path: &Path, path: &Path, options: &ImportOptions) -> bool {
+    let relative_path = path.strip_prefix(dir_path).unwrap_or(path);
+    (is_statement_file(path) || is_archive_file(path))
+        && is_included(relative_path, &options.include, &options.exclude)
+}
+
+fn import<T: DBActions>(statement_paths: Vec<PathBuf>, arc_db: ArcMutDB<T>, options: &ImportOptions) -> anyhow::Result<()> {
+    let report = csv2db_files(statement_paths, arc_db.clone(), options)?;
+    print_report(&report, None)?;
+    tagging_activities(arc_db, &report.activity_ids)?;
+    Ok(())
+}
+
+#[tokio::test]
+async fn test_watch_sub_folders() -> anyhow::Result<()> {
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+    use glob::Pattern;
+    use crate::db::sqlite::SqliteDB;
+    use crate::db::DBConfig;
+
+    // Removed with its content at the end of the test, even when it fails
+    struct TempDir(PathBuf);
+    impl Drop for TempDir {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+    let dir = TempDir(std::env::temp_dir().join(format!("watch-{}", std::process::id())));
+    std::fs::create_dir_all(dir.0.join("2021"))?;
+
+    let sqlite_db =
+        SqliteDB::from_config(DBConfig::Memory)
+        .with_init_db_script("./data/init-db-test.toml".to_string());
+    let arc_db = Arc::new(Mutex::new(sqlite_db));
+    let options = ImportOptions { include: vec![Pattern::new("*/*.csv")?], ..ImportOptions::default() };
+    let watcher = tokio::spawn(watch(dir.0.clone(), arc_db.clone(), options));
+
+    let imported = || -> anyhow::Result<Vec<String>> {
+        let db = arc_db.lock().unwrap();
+        if !db.is_initialized()? {
+            return Ok(Vec::new());
+        }
+        let mut stmt = db.connection().prepare("SELECT path FROM imports ORDER BY 1")?;
+        let paths = stmt.query_map([], |row| row.get(0))?.collect::<rusqlite::Result<Vec<String>>>()?;
+        Ok(paths)
+    };
+    // The statement is moved in as a finished download, again until imported: the watch of its folder may not be set yet
+    let drop_until_imported = |from: &'static str, to: PathBuf| {
+        let imported = &imported;
+        async move {
+            let deadline = Instant::now() + Duration::from_secs(10);
+            while !imported()?.iter().any(|p| Path::new(p) == to) {
+                if Instant::now() > deadline {
+                    return Err(anyhow::anyhow!("{} not imported in time", to.display()));
+                }
+                let part = to.with_extension("csv.part");
+                std::fs::copy(from, &part)?;
+                std::fs::rename(&part, &to)?;
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+            Ok(())
+        }
+    };
+
+    drop_until_imported("./data/input02.csv", dir.0.join("2021/input02.csv")).await?;
+    // Excluded by the globs: the events are handled in order, it is done once the next statement is imported
+    std::fs::copy("./data/input01.csv", dir.0.join("input01.csv"))?;
+    std::fs::create_dir(dir.0.join("2022"))?;
+    drop_until_imported("./data/input03.csv", dir.0.join("2022/input03.csv")).await?;
+    let paths = imported()?;
+    watcher.abort();
+
+    let relative: Vec<String> = paths
+        .iter()
+        .map(|p| Path::new(p).strip_prefix(&dir.0).map(|p| p.display().to_string()).unwrap_or_else(|_| p.clone()))
+        .collect();
+    assert_eq!(relative, vec!["2021/input02.csv", "2022/input03.csv"], "Only the statements of the sub folders matching the globs should be imported");
+
+    Ok(())
+}
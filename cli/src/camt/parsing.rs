@@ -0,0 +1,309 @@
+use chrono::NaiveDate;
+use roxmltree::{Document, Node};
+use anyhow;
+
+use crate::csv::encoding::decode;
+use crate::models::{Account, AccountBalance, AccountActivity, ActivityDetails, BankingStatement};
+use crate::models::import::RejectedRow;
+use crate::money::Money;
+
+/**
+ * Balance types, by order of preference for the statement balance:
+ * closing booked (camt.053), interim booked (camt.052), closing available
+ */
+const BALANCE_TYPES: [&str; 3] = ["CLBD", "ITBD", "CLAV"];
+
+fn child<'a, 'input>(node: Node<'a, 'input>, name: &str) -> Option<Node<'a, 'input>> {
+    node.children().find(|n| n.is_element() && n.tag_name().name() == name)
+}
+
+fn children<'a, 'input: 'a>(node: Node<'a, 'input>, name: &'a str) -> impl Iterator<Item = Node<'a, 'input>> + 'a {
+    node.children().filter(move |n| n.is_element() && n.tag_name().name() == name)
+}
+
+/**
+ * Text of the element at `path` below `node`, ie. text_at(ntry, &["BookgDt", "Dt"])
+ */
+fn text_at<'a>(node: Node<'a, '_>, path: &[&str]) -> Option<&'a str> {
+    path.iter()
+        .try_fold(node, |n, name| child(n, name))
+        .and_then(|n| n.text())
+        .map(str::trim)
+        .filter(|t| !t.is_empty())
+}
+
+/**
+ * Dates are either a day (Dt) or a date time (DtTm): only the day is kept
+ */
+fn date_at(node: Node, path: &[&str]) -> anyhow::Result<Option<NaiveDate>> {
+    let date = match path.iter().try_fold(node, |n, name| child(n, name)) {
+        Some(n) => text_at(n, &["Dt"]).or_else(|| text_at(n, &["DtTm"])),
+        None => None,
+    };
+    match date {
+        Some(d) => {
+            let day = d.get(0..10).ok_or_else(|| anyhow::anyhow!("Invalid camt date '{}'", d))?;
+            Ok(Some(NaiveDate::parse_from_str(day, "%Y-%m-%d")?))
+        }
+        None => Ok(None),
+    }
+}
+
+/**
+ * Amounts are always positive, the sign is given by CdtDbtInd
+ */
+fn signed_amount(node: Node) -> anyhow::Result<Money> {
+    let amount = text_at(node, &["Amt"])
+        .ok_or_else(|| anyhow::anyhow!("Missing Amt"))?
+        .parse::<Money>()?;
+    match text_at(node, &["CdtDbtInd"]) {
+        Some("DBIT") => Ok(-amount),
+        _ => Ok(amount),
+    }
+}
+
+fn parse_balance(statement: Node) -> anyhow::Result<Option<AccountBalance>> {
+    let mut balances: Vec<(usize, AccountBalance)> = Vec::new();
+    for bal in children(statement, "Bal") {
+        let code = text_at(bal, &["Tp", "CdOrPrtry", "Cd"]).unwrap_or_default();
+        if let Some(rank) = BALANCE_TYPES.iter().position(|t| *t == code) {
+            let date = date_at(bal, &["Dt"])?.ok_or_else(|| anyhow::anyhow!("Missing date for balance {}", code))?;
+            balances.push((rank, AccountBalance { row_id: None, account_id: None, date, balance_euro: signed_amount(bal)? }));
+        }
+    }
+    Ok(balances.into_iter().min_by_key(|(rank, _)| *rank).map(|(_, balance)| balance))
+}
+
+fn parse_entry(ntry: Node) -> anyhow::Result<Option<AccountActivity>> {
+    // Pending entries (camt.052) may still change: only booked ones are imported
+    let status = text_at(ntry, &["Sts", "Cd"]).or_else(|| text_at(ntry, &["Sts"]));
+    if matches!(status, Some(s) if s != "BOOK") {
+        return Ok(None);
+    }
+
+    let amount = signed_amount(ntry)?;
+    let date = date_at(ntry, &["BookgDt"])?
+        .or(date_at(ntry, &["ValDt"])?)
+        .ok_or_else(|| anyhow::anyhow!("Missing BookgDt for entry"))?;
+    let value_date = date_at(ntry, &["ValDt"])?;
+
+    let tx = child(ntry, "NtryDtls").and_then(|d| child(d, "TxDtls"));
+    let end_to_end_id = tx
+        .and_then(|t| text_at(t, &["Refs", "EndToEndId"]))
+        .filter(|id| *id != "NOTPROVIDED")
+        .map(str::to_string);
+
+    // The counterparty is the creditor of a debit and the debtor of a credit
+    let party = if amount.is_negative() { "Cdtr" } else { "Dbtr" };
+    let counterparty = tx
+        .and_then(|t| text_at(t, &["RltdPties", party, "Nm"]).or_else(|| text_at(t, &["RltdPties", party, "Pty", "Nm"])))
+        .map(str::to_string);
+
+    let remittance = tx
+        .and_then(|t| child(t, "RmtInf"))
+        .map(|r| children(r, "Ustrd").filter_map(|u| u.text()).map(str::trim).collect::<Vec<&str>>().join(" "))
+        .filter(|r| !r.is_empty());
+
+    let statement = match (text_at(ntry, &["AddtlNtryInf"]), &counterparty, &remittance) {
+        (Some(info), _, _) => info.to_string(),
+        (None, Some(c), Some(r)) => format!("{} {}", c, r),
+        (None, Some(c), None) => c.to_string(),
+        (None, None, Some(r)) => r.to_string(),
+        (None, None, None) => String::new(),
+    };
+
+    Ok(Some(AccountActivity {
+        row_id: None,
+        account_id: None,
+        date,
+        statement,
+        amount,
+        tag_pattern_id: None,
+        tag_pattern_ids: Vec::new(),
+        tag_overrides: Vec::new(),
+        transaction_id: text_at(ntry, &["AcctSvcrRef"]).or_else(|| text_at(ntry, &["NtryRef"])).map(str::to_string),
+        details: ActivityDetails {
+            counterparty,
+            end_to_end_id,
+            value_date,
+            ..ActivityDetails::default()
+        },
+    }))
+}
+
+/**
+ * Parse the content of a camt file, whatever its encoding
+ */
+pub fn parse_camt(bytes: &[u8]) -> anyhow::Result<Vec<BankingStatement>> {
+    parse_camt_content(&decode(bytes))
+}
+
+/**
+ * Parse an ISO 20022 camt.053 (Stmt) or camt.052 (Rpt) document: one banking statement per Stmt / Rpt.
+ * Entries that can not be read are rejected.
+ */
+pub fn parse_camt_content(content: &str) -> anyhow::Result<Vec<BankingStatement>> {
+    let document = Document::parse(content)?;
+    let mut statements: Vec<BankingStatement> = Vec::new();
+
+    let nodes = document
+        .descendants()
+        .filter(|n| n.is_element() && (n.tag_name().name() == "Stmt" || n.tag_name().name() == "Rpt"));
+
+    for node in nodes {
+        let account = child(node, "Acct").ok_or_else(|| anyhow::anyhow!("Missing Acct"))?;
+        let number = text_at(account, &["Id", "IBAN"])
+            .or_else(|| text_at(account, &["Id", "Othr", "Id"]))
+            .ok_or_else(|| anyhow::anyhow!("Missing account id"))?;
+        let account_type = text_at(account, &["Tp", "Cd"])
+            .or_else(|| text_at(account, &["Tp", "Prtry"]))
+            .unwrap_or_default();
+
+        let mut activities: Vec<AccountActivity> = Vec::new();
+        let mut rejected: Vec<RejectedRow> = Vec::new();
+        for ntry in children(node, "Ntry") {
+            match parse_entry(ntry) {
+                Ok(Some(activity)) => { activities.push(activity); },
+                Ok(None) => (),
+                Err(err) => rejected.push(RejectedRow {
+                    line: document.text_pos_at(ntry.range().start).row as usize,
+                    content: content[ntry.range()].to_string(),
+                    reason: err.to_string(),
+                }),
+            }
+        }
+
+        statements.push(BankingStatement {
+            row_id: None,
+            account: Account { row_id: None, number: number.to_string(), account_type: account_type.to_string() },
+            balance: parse_balance(node)?,
+            opening_balance: None,
+            activities,
+            rejected,
+        });
+    }
+
+    if statements.is_empty() {
+        return Err(anyhow::anyhow!("No camt statement found in document"));
+    }
+    Ok(statements)
+}
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+    use crate::money::Money;
+    use crate::camt::parsing::parse_camt_content;
+
+    const CAMT_053: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.053.001.02">
+  <BkToCstmrStmt>
+    <GrpHdr><MsgId>MSG1</MsgId><CreDtTm>2021-03-31T20:00:00</CreDtTm></GrpHdr>
+    <Stmt>
+      <Id>STMT1</Id>
+      <Acct><Id><IBAN>FR7620041010050000123456789</IBAN></Id><Tp><Cd>CACC</Cd></Tp></Acct>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>OPBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">1000.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Dt><Dt>2021-03-01</Dt></Dt>
+      </Bal>
+      <Bal>
+        <Tp><CdOrPrtry><Cd>CLBD</Cd></CdOrPrtry></Tp>
+        <Amt Ccy="EUR">1850.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Dt><Dt>2021-03-31</Dt></Dt>
+      </Bal>
+      <Ntry>
+        <Amt Ccy="EUR">650.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts>BOOK</Sts>
+        <BookgDt><Dt>2021-03-02</Dt></BookgDt><ValDt><Dt>2021-03-01</Dt></ValDt>
+        <AcctSvcrRef>REF-001</AcctSvcrRef>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>LOYER-2021-03</EndToEndId></Refs>
+          <RltdPties><Cdtr><Nm>AGENCE IMMO</Nm></Cdtr></RltdPties>
+          <RmtInf><Ustrd>LOYER MARS</Ustrd></RmtInf>
+        </TxDtls></NtryDtls>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">1500.00</Amt><CdtDbtInd>CRDT</CdtDbtInd><Sts>BOOK</Sts>
+        <BookgDt><Dt>2021-03-28</Dt></BookgDt>
+        <AcctSvcrRef>REF-002</AcctSvcrRef>
+        <NtryDtls><TxDtls>
+          <Refs><EndToEndId>NOTPROVIDED</EndToEndId></Refs>
+          <RltdPties><Dbtr><Nm>EMPLOYEUR SA</Nm></Dbtr></RltdPties>
+        </TxDtls></NtryDtls>
+        <AddtlNtryInf>VIREMENT DE EMPLOYEUR SA SALAIRE</AddtlNtryInf>
+      </Ntry>
+      <Ntry>
+        <Amt Ccy="EUR">10.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Sts>PDNG</Sts>
+        <BookgDt><Dt>2021-03-31</Dt></BookgDt>
+      </Ntry>
+    </Stmt>
+  </BkToCstmrStmt>
+</Document>
+"#;
+
+    #[test]
+    fn test_camt_053() -> anyhow::Result<()> {
+        let statements = parse_camt_content(CAMT_053)?;
+        assert_eq!(statements.len(), 1, "Wrong count of statements");
+
+        let statement = &statements[0];
+        assert_eq!(statement.account.number, "FR7620041010050000123456789", "Wrong account number");
+        assert_eq!(statement.account.account_type, "CACC", "Wrong account type");
+
+        let balance = statement.balance.as_ref().expect("Balance not found");
+        assert_eq!(balance.balance_euro, Money::from_cents(185000), "The closing balance should be kept");
+        assert_eq!(balance.date, NaiveDate::from_ymd(2021, 3, 31), "Wrong balance date");
+
+        assert_eq!(statement.activities.len(), 2, "Pending entries should be ignored");
+        assert!(statement.rejected.is_empty(), "No entry should be rejected");
+
+        let rent = statement.activities.iter()
+            .find(|a| a.transaction_id.as_deref() == Some("REF-001"))
+            .expect("Rent not found");
+        assert_eq!(rent.amount, Money::from_cents(-65000), "Debit should be negative");
+        assert_eq!(rent.date, NaiveDate::from_ymd(2021, 3, 2), "Wrong booking date");
+        assert_eq!(rent.statement, "AGENCE IMMO LOYER MARS", "Wrong statement");
+        assert_eq!(rent.details.value_date, Some(NaiveDate::from_ymd(2021, 3, 1)), "Wrong value date");
+        assert_eq!(rent.details.counterparty.as_deref(), Some("AGENCE IMMO"), "Wrong counterparty");
+        assert_eq!(rent.details.end_to_end_id.as_deref(), Some("LOYER-2021-03"), "Wrong end to end id");
+
+        let salary = statement.activities.iter()
+            .find(|a| a.transaction_id.as_deref() == Some("REF-002"))
+            .expect("Salary not found");
+        assert_eq!(salary.statement, "VIREMENT DE EMPLOYEUR SA SALAIRE", "Wrong statement");
+        assert_eq!(salary.details.counterparty.as_deref(), Some("EMPLOYEUR SA"), "Wrong counterparty");
+        assert_eq!(salary.details.end_to_end_id, None, "NOTPROVIDED is not an end to end id");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_camt_052() -> anyhow::Result<()> {
+        let content = r#"<Document xmlns="urn:iso:std:iso:20022:tech:xsd:camt.052.001.02"><BkToCstmrAcctRpt><Rpt>
+            <Acct><Id><Othr><Id>123456789</Id></Othr></Id></Acct>
+            <Bal><Tp><CdOrPrtry><Cd>ITBD</Cd></CdOrPrtry></Tp><Amt Ccy="EUR">42.00</Amt><CdtDbtInd>DBIT</CdtDbtInd><Dt><DtTm>2021-03-15T10:00:00</DtTm></Dt></Bal>
+            </Rpt></BkToCstmrAcctRpt></Document>"#;
+
+        let statements = parse_camt_content(content)?;
+        assert_eq!(statements[0].account.number, "123456789", "Wrong account number");
+        assert_eq!(statements[0].balance.as_ref().map(|b| b.balance_euro), Some(Money::from_cents(-4200)), "Wrong interim balance");
+        assert!(statements[0].activities.is_empty(), "No entry in the report");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_camt_rejected_entry() -> anyhow::Result<()> {
+        let content = "<Document><BkToCstmrStmt><Stmt>\n\
+            <Acct><Id><IBAN>FR76</IBAN></Id></Acct>\n\
+            <Ntry><Amt>12,5O</Amt><CdtDbtInd>DBIT</CdtDbtInd><BookgDt><Dt>2021-03-02</Dt></BookgDt></Ntry>\n\
+            </Stmt></BkToCstmrStmt></Document>";
+
+        let statements = parse_camt_content(content)?;
+        let rejected = &statements[0].rejected;
+        assert_eq!(rejected.len(), 1, "The invalid entry should be rejected");
+        assert_eq!(rejected[0].line, 3, "Wrong line of the rejected entry");
+        assert!(rejected[0].content.starts_with("<Ntry><Amt>12,5O</Amt>"), "Wrong content of the rejected entry");
+
+        Ok(())
+    }
+}
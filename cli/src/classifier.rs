@@ -0,0 +1,158 @@
+use std::collections::{HashMap, HashSet};
+
+use itertools::Itertools;
+
+use crate::money::Money;
+
+/**
+ * Upper bounds (in cents) of the buckets of the amounts, the amounts above the last one are in a last bucket
+ */
+const AMOUNT_BUCKETS: [i64; 6] = [1000, 5000, 10000, 50000, 100000, 500000];
+
+/**
+ * Prefix of the feature of the amount, see `features`
+ */
+const AMOUNT_PREFIX: &str = "amount:";
+
+/**
+ * What an activity is classified on: the words of its statement (without numbers, ie. dates, references and card numbers)
+ * and the bucket of its amount, ie. ["PRLV", "SEPA", "EDF", "amount:debit:100"]
+ */
+pub fn features(statement: &str, amount: Money) -> Vec<String> {
+    let words = statement
+        .split(|c: char| !c.is_alphanumeric())
+        .filter(|w| w.chars().count() > 1 && !w.chars().any(|c| c.is_numeric()))
+        .map(str::to_uppercase)
+        .unique();
+
+    let bucket = AMOUNT_BUCKETS
+        .iter()
+        .find(|bound| amount.abs() < Money::from_cents(**bound))
+        .map(|bound| (bound / 100).to_string())
+        .unwrap_or_else(|| "more".to_string());
+    let sign = if amount.is_negative() { "debit" } else { "credit" };
+
+    words.chain(std::iter::once(format!("{}{}:{}", AMOUNT_PREFIX, sign, bucket))).collect()
+}
+
+/**
+ * Multinomial naive Bayes: the probability of each tag given the features of an activity,
+ * learnt from the activities already tagged (an activity with 2 tags is an example of each)
+ */
+#[derive(Default)]
+pub struct NaiveBayes {
+    /**
+     * Activities of each tag
+     */
+    examples: HashMap<String, usize>,
+    /**
+     * Occurrences of each feature in the activities of each tag
+     */
+    counts: HashMap<String, HashMap<String, usize>>,
+    /**
+     * Occurrences of all the features in the activities of each tag
+     */
+    totals: HashMap<String, usize>,
+    vocabulary: HashSet<String>,
+}
+
+impl NaiveBayes {
+    pub fn train(&mut self, features: &[String], tag: &str) {
+        *self.examples.entry(tag.to_string()).or_default() += 1;
+        let counts = self.counts.entry(tag.to_string()).or_default();
+        for feature in features {
+            *counts.entry(feature.clone()).or_default() += 1;
+            self.vocabulary.insert(feature.clone());
+        }
+        *self.totals.entry(tag.to_string()).or_default() += features.len();
+    }
+
+    /**
+     * The tags by decreasing probability, with their probability (they add up to 1).
+     * None when no word of the statement was seen in training: the amount alone tells nothing.
+     */
+    pub fn predict(&self, features: &[String]) -> Vec<(String, f64)> {
+        let known: Vec<&String> = features.iter().filter(|f| self.vocabulary.contains(*f)).collect();
+        if !known.iter().any(|f| !f.starts_with(AMOUNT_PREFIX)) {
+            return Vec::new();
+        }
+
+        let all_examples: usize = self.examples.values().sum();
+        let vocabulary = self.vocabulary.len() as f64;
+        // Log probabilities with Laplace smoothing, against underflow
+        let scores: Vec<(&String, f64)> = self
+            .examples
+            .iter()
+            .map(|(tag, examples)| {
+                let counts = &self.counts[tag];
+                let total = self.totals[tag] as f64;
+                let likelihood: f64 = known
+                    .iter()
+                    .map(|f| ((counts.get(*f).copied().unwrap_or_default() as f64 + 1.0) / (total + vocabulary)).ln())
+                    .sum();
+                (tag, (*examples as f64 / all_examples as f64).ln() + likelihood)
+            })
+            .collect();
+
+        let max = scores.iter().map(|(_, s)| *s).fold(f64::NEG_INFINITY, f64::max);
+        let sum: f64 = scores.iter().map(|(_, s)| (s - max).exp()).sum();
+        scores
+            .into_iter()
+            .map(|(tag, s)| (tag.clone(), (s - max).exp() / sum))
+            .sorted_by(|a, b| b.1.total_cmp(&a.1).then_with(|| a.0.cmp(&b.0)))
+            .collect()
+    }
+
+    /**
+     * The word of the features telling the most the tag (the first one on a tie), to write a rule from an activity
+     */
+    pub fn best_word(&self, features: &[String], tag: &str) -> Option<String> {
+        let counts = self.counts.get(tag)?;
+        let others = |feature: &String| -> usize {
+            self.counts.iter().filter(|(t, _)| *t != tag).map(|(_, c)| c.get(feature).copied().unwrap_or_default()).sum()
+        };
+        features
+            .iter()
+            .filter(|f| !f.starts_with(AMOUNT_PREFIX))
+            .filter_map(|f| counts.get(f).map(|count| (f, *count as f64 / (1 + others(f)) as f64)))
+            .rev()
+            .max_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(f, _)| f.clone())
+    }
+}
+
+#[test]
+fn test_features() -> anyhow::Result<()> {
+    assert_eq!(
+        features("PRLV SEPA EDF ECH/150321 X1234", "-74.00".parse()?),
+        vec!["PRLV", "SEPA", "EDF", "ECH", "amount:debit:100"]
+    );
+    assert_eq!(features("Virement de M. Dupont", "2500.00".parse()?), vec!["VIREMENT", "DE", "DUPONT", "amount:credit:5000"]);
+    assert_eq!(features("CARTE CARTE", "-9999.99".parse()?), vec!["CARTE", "amount:debit:more"]);
+    Ok(())
+}
+
+#[test]
+fn test_naive_bayes() -> anyhow::Result<()> {
+    let mut classifier = NaiveBayes::default();
+    for (statement, amount, tag) in [
+        ("PRLV SEPA EDF ECH/150321", "-74.00", "EDF"),
+        ("PRLV SEPA EDF ECH/150421", "-71.00", "EDF"),
+        ("PRLV SEPA FREE MOBILE", "-19.99", "FREEMOBILE"),
+        ("CARTE X1234 MONOPRIX PARIS", "-23.10", "COURSES"),
+        ("CARTE X1234 MONOPRIX LYON", "-8.20", "COURSES"),
+    ] {
+        classifier.train(&features(statement, amount.parse()?), tag);
+    }
+
+    let edf = features("PRLV SEPA EDF ECH/150521", "-80.00".parse()?);
+    let predicted = classifier.predict(&edf);
+    assert_eq!(predicted[0].0, "EDF", "Wrong tag predicted");
+    assert!(predicted[0].1 > 0.8, "EDF should be confident, got {}", predicted[0].1);
+    assert!((predicted.iter().map(|p| p.1).sum::<f64>() - 1.0).abs() < 1e-9, "Probabilities should add up to 1");
+    assert_eq!(classifier.predict(&features("CARTE X1234 MONOPRIX NICE", "-15.00".parse()?))[0].0, "COURSES");
+    assert!(classifier.predict(&features("VIREMENT DUPONT", "-19.99".parse()?)).is_empty(), "Unknown words should give no tag");
+    assert_eq!(classifier.best_word(&edf, "EDF"), Some("EDF".to_string()), "Wrong word for a rule");
+
+    Ok(())
+}
@@ -1,2 +1,5 @@
+pub mod archive;
 pub mod dir;
-pub mod parsing;
\ No newline at end of file
+pub mod encoding;
+pub mod parsing;
+pub mod profile;
@@ -0,0 +1,133 @@
+use std::io::{Cursor, Read};
+use std::path::Path;
+
+use flate2::read::GzDecoder;
+
+/**
+ * A file read from an archive, `name` being its path inside the archive
+ */
+pub struct ArchiveMember {
+    pub name: String,
+    pub bytes: Vec<u8>,
+}
+
+enum ArchiveKind {
+    Zip,
+    TarGz,
+}
+
+fn archive_kind<P: AsRef<Path>>(file_path: P) -> Option<ArchiveKind> {
+    let name = file_path.as_ref().file_name()?.to_string_lossy().to_lowercase();
+    if name.ends_with(".zip") {
+        Some(ArchiveKind::Zip)
+    } else if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+        Some(ArchiveKind::TarGz)
+    } else {
+        None
+    }
+}
+
+pub fn is_archive_file<P: AsRef<Path>>(file_path: P) -> bool {
+    archive_kind(file_path).is_some()
+}
+
+/**
+ * Read all the files of the content (`bytes`) of the .zip or .tar.gz archive `archive_path`
+ */
+pub fn read_archive_content<P: AsRef<Path>>(archive_path: P, bytes: &[u8]) -> anyhow::Result<Vec<ArchiveMember>> {
+    match archive_kind(archive_path.as_ref()) {
+        Some(ArchiveKind::Zip) => read_zip(bytes),
+        Some(ArchiveKind::TarGz) => read_tar_gz(bytes),
+        None => Err(anyhow::anyhow!("Unsupported archive {}", archive_path.as_ref().display())),
+    }
+}
+
+fn read_zip(bytes: &[u8]) -> anyhow::Result<Vec<ArchiveMember>> {
+    let mut archive = zip::ZipArchive::new(Cursor::new(bytes))?;
+    let mut members: Vec<ArchiveMember> = Vec::new();
+    for index in 0..archive.len() {
+        let mut member = archive.by_index(index)?;
+        if member.is_dir() {
+            continue;
+        }
+        let mut bytes: Vec<u8> = Vec::new();
+        member.read_to_end(&mut bytes)?;
+        members.push(ArchiveMember { name: member.name().to_string(), bytes });
+    }
+    Ok(members)
+}
+
+fn read_tar_gz(bytes: &[u8]) -> anyhow::Result<Vec<ArchiveMember>> {
+    let mut archive = tar::Archive::new(GzDecoder::new(bytes));
+    let mut members: Vec<ArchiveMember> = Vec::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().to_string();
+        let mut bytes: Vec<u8> = Vec::new();
+        entry.read_to_end(&mut bytes)?;
+        members.push(ArchiveMember { name, bytes });
+    }
+    Ok(members)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use std::io::Write;
+    use flate2::{write::GzEncoder, Compression};
+    use crate::csv::archive::{is_archive_file, read_archive_content};
+
+    #[test]
+    fn test_is_archive_file() {
+        assert!(is_archive_file("2021.zip"), "zip archive not detected");
+        assert!(is_archive_file("2021.TAR.GZ"), "tar.gz archive not detected");
+        assert!(is_archive_file("2021.tgz"), "tgz archive not detected");
+        assert!(!is_archive_file("releve.csv"), "statement is not an archive");
+        assert!(!is_archive_file("releve.gz"), "gz alone is not an archive");
+    }
+
+    #[test]
+    fn test_read_zip() -> anyhow::Result<()> {
+        let zip_path = std::env::temp_dir().join(format!("lpr-archive-{}.zip", std::process::id()));
+        {
+            let mut zip = zip::ZipWriter::new(std::fs::File::create(&zip_path)?);
+            zip.add_directory("2021/", Default::default())?;
+            zip.start_file("2021/janvier.csv", Default::default())?;
+            zip.write_all(b"Date;Libelle;Montant\n")?;
+            zip.finish()?;
+        }
+
+        let members = read_archive_content(&zip_path, &std::fs::read(&zip_path)?)?;
+        std::fs::remove_file(&zip_path)?;
+
+        assert_eq!(members.len(), 1, "Folders should not be members");
+        assert_eq!(members[0].name, "2021/janvier.csv", "Wrong member path");
+        assert_eq!(members[0].bytes, b"Date;Libelle;Montant\n", "Wrong member content");
+        Ok(())
+    }
+
+    #[test]
+    fn test_read_tar_gz() -> anyhow::Result<()> {
+        let tar_path = std::env::temp_dir().join(format!("lpr-archive-{}.tar.gz", std::process::id()));
+        {
+            let mut tar = tar::Builder::new(GzEncoder::new(std::fs::File::create(&tar_path)?, Compression::default()));
+            let content = b"OFXHEADER:100\n";
+            let mut header = tar::Header::new_gnu();
+            header.set_size(content.len() as u64);
+            header.set_cksum();
+            tar.append_data(&mut header, "2022/releve.ofx", &content[..])?;
+            tar.into_inner()?.finish()?;
+        }
+
+        let members = read_archive_content(&tar_path, &std::fs::read(&tar_path)?)?;
+        std::fs::remove_file(&tar_path)?;
+
+        assert_eq!(members.len(), 1, "Wrong count of members");
+        assert_eq!(members[0].name, "2022/releve.ofx", "Wrong member path");
+        Ok(())
+    }
+}
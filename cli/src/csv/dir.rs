@@ -1,56 +1,106 @@
 use anyhow;
-use std::{
-    path::{Path, PathBuf},
-    vec,
+use glob::{MatchOptions, Pattern};
+use std::path::{Path, PathBuf};
+
+/**
+ * `*` does not cross folders, `**` does
+ */
+const GLOB_OPTIONS: MatchOptions = MatchOptions {
+    case_sensitive: true,
+    require_literal_separator: true,
+    require_literal_leading_dot: false,
 };
 
+/**
+ * The path (relative to the folder searched) matches one of the `include` globs, or there is none,
+ * and none of the `exclude` globs
+ */
+pub fn is_included(relative_path: &Path, include: &[Pattern], exclude: &[Pattern]) -> bool {
+    let matches = |pattern: &Pattern| pattern.matches_path_with(relative_path, GLOB_OPTIONS);
+    (include.is_empty() || include.iter().any(matches)) && !exclude.iter().any(matches)
+}
+
+/**
+ * List the files of `file_path` and of all its sub folders, filtered by the `include` / `exclude` globs
+ * (ie. "*.csv") matched against the path relative to `file_path`
+ */
 pub fn list_files<P: AsRef<Path>>(
     file_path: P,
-    extension: Option<&str>,
+    include: &[Pattern],
+    exclude: &[Pattern],
 ) -> anyhow::Result<Vec<PathBuf>> {
-    let mut csv: Vec<PathBuf> = vec![];
-    for file in std::fs::read_dir(file_path.as_ref())? {
-        let f_path = file?.path();
-        if extension.is_some() {
-            match f_path.extension() {
-                Some(ex) if ex == extension.unwrap() => csv.push(f_path),
-                _ => continue,
+    let root = file_path.as_ref();
+    let mut files: Vec<PathBuf> = vec![];
+    let mut folders: Vec<PathBuf> = vec![root.to_path_buf()];
+
+    while let Some(folder) = folders.pop() {
+        for file in std::fs::read_dir(&folder)? {
+            let file = file?;
+            let f_path = file.path();
+            if file.file_type()?.is_dir() {
+                folders.push(f_path);
+            } else if is_included(f_path.strip_prefix(root)?, include, exclude) {
+                files.push(f_path);
             }
-        } else {
-            csv.push(f_path)
         }
     }
-    Ok(csv)
+    files.sort();
+    Ok(files)
 }
 
 #[cfg(test)]
 mod tests {
 
-    use std::path::PathBuf;
-    use crate::csv::dir::list_files;
+    use glob::Pattern;
+    use std::path::{Path, PathBuf};
+    use crate::csv::dir::{is_included, list_files};
 
     #[test]
     fn test_existing_folder() -> anyhow::Result<()> {
         // Tests run at the project level
-        let result: Vec<PathBuf> = list_files("./", Some("toml"))?;
+        let result: Vec<PathBuf> = list_files("./", &[Pattern::new("*.toml")?], &[])?;
 
-        assert_eq!(
+        assert!(
             result.contains(&PathBuf::from("./Cargo.toml")), // ./ because it is PathBuf
-            true,
             "Cargo.toml not found!"
         );
 
         Ok(())
     }
 
+    #[test]
+    fn test_sub_folders() -> anyhow::Result<()> {
+        let result: Vec<PathBuf> = list_files("./", &[Pattern::new("src/**/*.rs")?], &[Pattern::new("src/db/**")?])?;
+
+        assert!(result.contains(&PathBuf::from("./src/csv/dir.rs")), "Files of the sub folders should be listed");
+        assert!(!result.contains(&PathBuf::from("./src/db/sqlite.rs")), "Excluded folder should not be listed");
+        assert!(!result.contains(&PathBuf::from("./Cargo.toml")), "Files not included should not be listed");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_included() -> anyhow::Result<()> {
+        let include = [Pattern::new("*.csv")?, Pattern::new("2021/**/*.zip")?];
+        let exclude = [Pattern::new("**/old_*")?];
+
+        assert!(is_included(Path::new("releve.csv"), &include, &exclude), "Included file not matched");
+        assert!(!is_included(Path::new("2022/releve.csv"), &include, &exclude), "'*' should not cross folders");
+        assert!(is_included(Path::new("2021/03/releves.zip"), &include, &exclude), "'**' should cross folders");
+        assert!(!is_included(Path::new("old_releve.csv"), &include, &exclude), "Excluded file matched");
+        assert!(is_included(Path::new("2022/releve.csv"), &[], &[]), "Everything is included without glob");
+
+        Ok(())
+    }
+
     #[test]
     fn test_non_existing_folder() {
         // Tests run at the project level
         //The below path is probably invalid
-        match list_files("/hello", Some("toml")) {
-            Ok(_) => assert!(false, "Test failed: it looks like there is an existing /hello"),
+        match list_files("/hello", &[], &[]) {
+            Ok(_) => panic!("Test failed: it looks like there is an existing /hello"),
             Err(_) => {
-                assert!(true, "handle correctly non existing source of files");
+                // handle correctly non existing source of files
             }            
         }
     }
@@ -0,0 +1,89 @@
+use std::borrow::Cow;
+
+use encoding_rs::{Encoding, UTF_8, WINDOWS_1252};
+
+/**
+ * Detect the encoding of a statement:
+ * - a BOM wins (UTF-8, UTF-16)
+ * - valid UTF-8 is UTF-8
+ * - anything else is considered as Windows-1252, a superset of ISO-8859-1 (Latin-1) for all printable characters.
+ *   This is the encoding of La Banque Postale exports.
+ */
+pub fn detect_encoding(bytes: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(bytes) {
+        return encoding;
+    }
+    match std::str::from_utf8(bytes) {
+        Ok(_) => UTF_8,
+        Err(_) => WINDOWS_1252,
+    }
+}
+
+/**
+ * Transcode the statement content to UTF-8 (the BOM, if any, is removed)
+ */
+pub fn decode(bytes: &[u8]) -> Cow<'_, str> {
+    let (content, _) = detect_encoding(bytes).decode_with_bom_removal(bytes);
+    content
+}
+
+/**
+ * Transcode with the encoding named by `label` (ie. "windows-1252", "utf-8"), or the detected one without label
+ */
+pub fn decode_as<'a>(bytes: &'a [u8], label: Option<&str>) -> anyhow::Result<Cow<'a, str>> {
+    match label {
+        Some(label) => {
+            let encoding = Encoding::for_label(label.as_bytes())
+                .ok_or_else(|| anyhow::anyhow!("Unknown encoding '{}'", label))?;
+            let (content, _) = encoding.decode_with_bom_removal(bytes);
+            Ok(content)
+        }
+        None => Ok(decode(bytes)),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use encoding_rs::{UTF_8, WINDOWS_1252};
+    use crate::csv::encoding::{decode, decode_as, detect_encoding};
+
+    #[test]
+    fn test_utf8() {
+        let content = "Libellé;Montant".as_bytes();
+        assert_eq!(detect_encoding(content), UTF_8, "Wrong encoding detected");
+        assert_eq!(decode(content), "Libellé;Montant", "Wrong content decoded");
+    }
+
+    #[test]
+    fn test_utf8_with_bom() {
+        let content = [&[0xEF, 0xBB, 0xBF], "Libellé".as_bytes()].concat();
+        assert_eq!(detect_encoding(&content), UTF_8, "Wrong encoding detected");
+        assert_eq!(decode(&content), "Libellé", "BOM should be removed");
+    }
+
+    #[test]
+    fn test_latin1() {
+        // "Numéro Compte" in ISO-8859-1
+        let content = b"Num\xe9ro Compte";
+        assert_eq!(detect_encoding(content), WINDOWS_1252, "Wrong encoding detected");
+        assert_eq!(decode(content), "Numéro Compte", "Wrong content decoded");
+    }
+
+    #[test]
+    fn test_windows_1252() {
+        // "€" and "’" only exist in Windows-1252
+        let content = b"CARTE \x80 L\x92ATELIER";
+        assert_eq!(decode(content), "CARTE € L’ATELIER", "Wrong content decoded");
+    }
+
+    #[test]
+    fn test_forced_encoding() -> anyhow::Result<()> {
+        // Valid UTF-8 bytes, but declared as Latin-1 by the import profile
+        let content = "Libellé".as_bytes();
+        assert_eq!(decode_as(content, Some("latin1"))?, "LibellÃ©", "The declared encoding should win");
+        assert!(decode_as(content, Some("klingon")).is_err(), "Unknown encoding should fail");
+        Ok(())
+    }
+}
@@ -1,112 +1,405 @@
 use chrono::NaiveDate;
-use ordered_float::OrderedFloat;
-use std::collections::HashSet;
-use std::collections::HashMap;
 use std::path::Path;
 use anyhow;
 
-use crate::models::{AccountBalance, AccountActivity, BankingStatement};
+use csv::StringRecord;
 
+use crate::csv::encoding::{decode, decode_as};
+use crate::csv::profile::{Column, ColumnMapping, CsvProfile, SummaryFields, same_label, select_profile};
+use crate::models::{Account, AccountBalance, AccountActivity, ActivityDetails, BankingStatement};
+use crate::models::import::RejectedRow;
+use crate::money::Money;
 
-fn get_balance(stats: HashMap<String, String>) -> anyhow::Result<AccountBalance> {
-    let (date, balance) = (stats.get("Date"), stats.get("Solde (EUROS)"));
-    let mut err : Vec<&str> = vec!();
+/**
+ * Values of the "label;value" lines before the activities
+ */
+#[derive(Default)]
+struct Summary {
+    account_number: Option<String>,
+    account_type: Option<String>,
+    date: Option<String>,
+    balance: Option<String>,
+}
+
+impl Summary {
+    fn collect(&mut self, fields: &SummaryFields, label: &str, value: &str) {
+        let is = |field: &Option<String>| field.as_deref().map(|f| same_label(f, label)).unwrap_or(false);
+        let value = Some(value.to_string());
+        if is(&fields.account_number) {
+            self.account_number = value;
+        } else if is(&fields.account_type) {
+            self.account_type = value;
+        } else if is(&fields.date) {
+            self.date = value;
+        } else if is(&fields.balance) {
+            self.balance = value;
+        }
+    }
+}
+
+fn get_account(summary: &Summary, profile: &CsvProfile) -> anyhow::Result<Account> {
+    let number = match (&profile.summary, &summary.account_number, &profile.account_number) {
+        (_, Some(number), _) | (_, None, Some(number)) => number.to_string(),
+        (Some(fields), None, None) if fields.account_number.is_some() => return Err(anyhow::anyhow!("Missing account number")),
+        _ => profile.name.to_string(),
+    };
+
+    Ok(Account {
+        row_id: None,
+        number,
+        account_type: summary.account_type.clone().or_else(|| profile.account_type.clone()).unwrap_or_default()
+    })
+}
+
+fn get_balance(summary: &Summary, profile: &CsvProfile) -> anyhow::Result<Option<AccountBalance>> {
+    let fields = match &profile.summary {
+        Some(fields) if fields.date.is_some() && fields.balance.is_some() => fields,
+        _ => return Ok(None),
+    };
+    let (date, balance) = (summary.date.as_ref(), summary.balance.as_ref());
+    let mut err : Vec<String> = vec!();
     if date.is_none() {
-        err.push("Missing statement date");
+        err.push(format!("Missing statement date ({})", fields.date.as_deref().unwrap_or_default()));
     };
     if balance.is_none() {
-        err.push("Missing balance");
+        err.push(format!("Missing balance ({})", fields.balance.as_deref().unwrap_or_default()));
     };
     if ! err.is_empty() {
         return Err(anyhow::anyhow!(err.join(", ")));
     }
     
-    Ok(AccountBalance{ 
+    Ok(Some(AccountBalance{ 
+        row_id: None,
+        account_id: None,
+        date : NaiveDate::parse_from_str(date.unwrap(), &profile.date_format)? , 
+        balance_euro : parse_amount(balance.unwrap(), profile)?
+    }))
+}
+
+/**
+ * Amount written with the separators of the profile, ie. "-1 234,56"
+ */
+fn parse_amount(value: &str, profile: &CsvProfile) -> anyhow::Result<Money> {
+    Money::parse_with(value, profile.decimal_separator, profile.thousands_separator)
+}
+
+/**
+ * Position of the columns of the profile, named columns are looked for in the header
+ */
+struct ColumnIndexes {
+    date: usize,
+    statement: usize,
+    amount: Option<usize>,
+    debit: Option<usize>,
+    credit: Option<usize>,
+}
+
+impl ColumnIndexes {
+    fn resolve(columns: &ColumnMapping, header: Option<&StringRecord>) -> anyhow::Result<Self> {
+        let index = |column: &Column| -> anyhow::Result<usize> {
+            match column {
+                Column::Index(index) => Ok(*index),
+                Column::Name(name) => header
+                    .and_then(|h| h.iter().position(|cell| same_label(cell, name)))
+                    .ok_or_else(|| anyhow::anyhow!("Column '{}' not found in the header", name)),
+            }
+        };
+        let optional_index = |column: &Option<Column>| column.as_ref().map(index).transpose();
+
+        if columns.amount.is_none() && columns.debit.is_none() && columns.credit.is_none() {
+            return Err(anyhow::anyhow!("No amount, debit or credit column in the profile"));
+        }
+        Ok(Self {
+            date: index(&columns.date)?,
+            statement: index(&columns.statement)?,
+            amount: optional_index(&columns.amount)?,
+            debit: optional_index(&columns.debit)?,
+            credit: optional_index(&columns.credit)?,
+        })
+    }
+
+    fn amount(&self, record: &StringRecord, profile: &CsvProfile) -> anyhow::Result<Money> {
+        let cell = |index: Option<usize>| index.and_then(|i| record.get(i)).map(str::trim).filter(|v| !v.is_empty());
+
+        match (cell(self.amount), cell(self.debit), cell(self.credit)) {
+            (Some(amount), _, _) => parse_amount(amount, profile),
+            // Debits may be written with or without their sign
+            (None, Some(debit), _) => Ok(-parse_amount(debit, profile)?.abs()),
+            (None, None, Some(credit)) => parse_amount(credit, profile),
+            (None, None, None) => Err(anyhow::anyhow!("Missing amount")),
+        }
+    }
+}
+
+
+fn parse_activity(record: &StringRecord, columns: &ColumnIndexes, profile: &CsvProfile) -> anyhow::Result<AccountActivity> {
+    let date = record.get(columns.date).map(str::trim).unwrap_or_default();
+    let date = NaiveDate::parse_from_str(date, &profile.date_format)
+        .map_err(|err| anyhow::anyhow!("Invalid date '{}': {}", date, err))?;
+    let statement = record.get(columns.statement).map(str::trim).unwrap_or_default();
+
+    Ok(AccountActivity {
         row_id: None,
-        date : NaiveDate::parse_from_str(date.unwrap(), "%d/%m/%Y")? , 
-        balance_euro : balance.unwrap().replace(",", ".").parse::<OrderedFloat<f32>>()?
+        account_id: None,
+        date,
+        statement: statement.to_string(),
+        amount: columns.amount(record, profile)?,
+        tag_pattern_id: None,
+        tag_pattern_ids: Vec::new(),
+        tag_overrides: Vec::new(),
+        transaction_id: None,
+        details: ActivityDetails::default()
     })
 }
 
+/**
+ * Parse the content (`bytes`) of the statement `csv_path` with the profile of its folder or its header, La Banque Postale otherwise
+ */
+pub fn parse_csv_with_profiles<P: AsRef<Path>>(csv_path: P, bytes: &[u8], profiles: &[CsvProfile]) -> anyhow::Result<BankingStatement> {
+    let profile = select_profile(csv_path.as_ref(), &decode(bytes), profiles);
+    let content = decode_as(bytes, profile.encoding.as_deref())?;
+    parse_csv_content_with_profile(&content, &profile)
+}
 
-pub fn parse_csv<P: AsRef<Path>>(csv_path: P) -> anyhow::Result<BankingStatement> {
-    let mut stats: HashMap<String,String> = HashMap::new();
-    let mut activities: HashSet<AccountActivity> = HashSet::new();
+/**
+ * Parse a statement already transcoded to UTF-8.
+ * The header is the first row recognised by the profile (see `CsvProfile::is_header`), the lines of 2 cells before it are the summary.
+ * Activities that can not be read are rejected, with their line.
+ */
+pub fn parse_csv_content_with_profile(content: &str, profile: &CsvProfile) -> anyhow::Result<BankingStatement> {
+    let mut summary = Summary::default();
+    let mut activities: Vec<AccountActivity> = Vec::new();
+    let mut header: Option<StringRecord> = None;
+    let mut indexes: Option<ColumnIndexes> = None;
+    let mut rejected: Vec<RejectedRow> = Vec::new();
 
+    let delimiter = u8::try_from(profile.delimiter)
+        .map_err(|_| anyhow::anyhow!("The delimiter '{}' is not a single byte character", profile.delimiter))?;
     let mut reader = csv::ReaderBuilder::new()
         .flexible(true)
-        .delimiter(b';')
-        .from_path(csv_path.as_ref())?;        
-
-    let data = reader.records()
-        .filter(|s| s.is_ok())
-        .map(|s| s.unwrap());
-
-    for record in data {
-        // Balance
-        if  record.len() == 2 as usize {
-            match (record.get(0), record.get(1)) {
-                (Some(header), Some(value)) => {
-                    let header = str::trim(header);
-                    let value = str::trim(value);
-                    if header == "Date" || header == "Solde (EUROS)" {
-                        stats.insert(header.to_string(), value.to_string());
-                    }
-                },
+        .has_headers(false)
+        .delimiter(delimiter)
+        .from_reader(content.as_bytes());        
+
+    let lines: Vec<&str> = content.lines().collect();
+    let raw_line = |line: usize| lines.get(line.wrapping_sub(1)).map(|l| l.to_string()).unwrap_or_default();
+
+    for record in reader.records().skip(profile.skip_rows) {
+        let record = match record {
+            Ok(record) => record,
+            Err(err) => {
+                let line = err.position().map(|p| p.line() as usize).unwrap_or_default();
+                rejected.push(RejectedRow { line, content: raw_line(line), reason: err.to_string() });
+                continue;
+            }
+        };
+        let line = record.position().map(|p| p.line() as usize).unwrap_or_default();
+
+        // Summary (account, balance)
+        if  record.len() == 2 {
+            match (&profile.summary, record.get(0), record.get(1)) {
+                (Some(fields), Some(label), Some(value)) => summary.collect(fields, label, value.trim()),
                 _ => continue
             }
         } 
         // Activity
-        else if record.len() > 2 as usize {            
-            match (record.get(0), record.get(1), record.get(2)) {
-                (Some(date), Some(statement), Some(amount)) 
-                        if date == "Date" || statement.contains("Montant") || amount.contains("Montant") => {
-                    continue;
-                },
-                (Some(date), Some(statement), Some(amount)) => {                    
-                    let date = NaiveDate::parse_from_str(date.trim(), "%d/%m/%Y")?;
-                    let statement = statement.trim();
-                    let amount = amount.replace(",", ".").parse::<f32>()?;
-
-                    activities.insert(AccountActivity {
-                        row_id: None,
-                        date,
-                        statement: statement.to_string(),
-                        amount: OrderedFloat(amount),
-                        tag_pattern_id: None
-                    });
-                },
-                _ => continue
+        else if record.len() > 2 {
+            // Header row, the named columns are looked for in it
+            if header.is_none() && profile.is_header(&record.iter().collect::<Vec<&str>>().join(&profile.delimiter.to_string())) {
+                header = Some(record);
+                indexes = None;
+                continue;
+            }
+            if indexes.is_none() {
+                indexes = Some(ColumnIndexes::resolve(&profile.columns, header.as_ref())?);
+            }
+            let columns = indexes.as_ref().unwrap();
+
+            match parse_activity(&record, columns, profile) {
+                Ok(activity) => { activities.push(activity); },
+                Err(err) => rejected.push(RejectedRow { line, content: raw_line(line), reason: err.to_string() })
             }
         }
 
     }
    
-    let balance = get_balance(stats)?;
+    let account = get_account(&summary, profile)?;
+    let balance = get_balance(&summary, profile)?;
 
-    Ok(BankingStatement { row_id: None, balance, activities})
+    Ok(BankingStatement { row_id: None, account, balance, opening_balance: None, activities, rejected })
 }
 
 
 
-
 #[test]
 fn test() -> anyhow::Result<()> {
 
     // Tests run at the project level
-    let result = parse_csv("./data/input01.csv")?;
+    let result = parse_csv_with_profiles("./data/input01.csv", &std::fs::read("./data/input01.csv")?, &[])?;
     let expected_activity = AccountActivity {
         row_id: None,
+        account_id: None,
         date : NaiveDate::parse_from_str("12/03/2021", "%d/%m/%Y")?,
         statement : "BUY SOMETHING 03".to_string(),
-        amount : OrderedFloat(-15.00),
-        tag_pattern_id: None
+        amount : Money::from_cents(-1500),
+        tag_pattern_id: None,
+        tag_pattern_ids: Vec::new(),
+        tag_overrides: Vec::new(),
+        transaction_id: None,
+        details: ActivityDetails::default()
     };
 
-    assert_eq!(result.balance.balance_euro, 187.77, "Wrong balance found");
+    assert_eq!(result.account.number, "123456789", "Wrong account number found");
+    assert_eq!(result.account.account_type, "CCP", "Wrong account type found");
+    assert_eq!(result.balance.map(|b| b.balance_euro), Some(Money::from_cents(18777)), "Wrong balance found");
     assert_eq!(result.activities.len(), 9, "Wrong count of activities");
     assert!(result.activities.contains(&expected_activity),"Expected activity not found");
 
     Ok(())
 
-}
\ No newline at end of file
+}
+
+#[test]
+fn test_accented_statement() -> anyhow::Result<()> {
+    // La Banque Postale export, encoded in ISO-8859-1
+    let content = b"Num\xe9ro Compte   ;123456789\nType         ;CCP\nDate            ;27/03/2021\nSolde (EUROS)   ;187,77\n\nDate;Libell\xe9;Montant(EUROS);Montant(FRANCS)\n26/03/2021;\"CARTE X1234 PR\xc9L\xc8VEMENT CAF\xc9\";-20,00;\n";
+    let result = parse_csv_content_with_profile(&decode(content), &CsvProfile::default())?;
+
+    assert_eq!(result.activities.len(), 1, "Accented activity should not be dropped");
+    assert_eq!(
+        result.activities.first().map(|a| a.statement.as_str()), 
+        Some("CARTE X1234 PRÉLÈVEMENT CAFÉ"), 
+        "Wrong statement decoded"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_identical_activities() -> anyhow::Result<()> {
+    // 2 coffees at the same place on the same day
+    let content = "Numéro Compte   ;123456789\nType         ;CCP\nDate            ;27/03/2021\nSolde (EUROS)   ;187,77\n\nDate;Libellé;Montant(EUROS);Montant(FRANCS)\n26/03/2021;\"CARTE X1234 CAFE\";-2,50;\n26/03/2021;\"CARTE X1234 CAFE\";-2,50;\n";
+    let result = parse_csv_content_with_profile(content, &CsvProfile::default())?;
+
+    assert_eq!(result.activities.len(), 2, "Identical activities should both be kept");
+
+    Ok(())
+}
+
+#[test]
+fn test_profile_debit_credit() -> anyhow::Result<()> {
+    use crate::csv::profile::ColumnMapping;
+
+    // Crédit Mutuel layout: debit and credit columns, no summary
+    let profile = CsvProfile {
+        name: "creditmutuel".to_string(),
+        account_number: Some("CM-0001".to_string()),
+        thousands_separator: Some(' '),
+        summary: None,
+        columns: ColumnMapping {
+            date: Column::Name("Date".to_string()),
+            statement: Column::Name("Libellé".to_string()),
+            amount: None,
+            debit: Some(Column::Name("Débit".to_string())),
+            credit: Some(Column::Name("Crédit".to_string())),
+        },
+        ..CsvProfile::default()
+    };
+    let content = "Date;Date valeur;Débit;Crédit;Libellé;Solde\n\
+        02/04/2021;02/04/2021;-650,00;;LOYER AVRIL;1 350,00\n\
+        05/04/2021;05/04/2021;;1 500,00;VIR SALAIRE;2 850,00\n";
+    let result = parse_csv_content_with_profile(content, &profile)?;
+
+    assert_eq!(result.account.number, "CM-0001", "Wrong account number");
+    assert!(result.balance.is_none(), "No balance without summary");
+    assert_eq!(result.activities.len(), 2, "Wrong count of activities");
+
+    let amount_of = |statement: &str| result.activities.iter().find(|a| a.statement == statement).map(|a| a.amount);
+    assert_eq!(amount_of("LOYER AVRIL"), Some(Money::from_cents(-65000)), "Wrong debit amount");
+    assert_eq!(amount_of("VIR SALAIRE"), Some(Money::from_cents(150000)), "Wrong credit amount");
+
+    Ok(())
+}
+
+#[test]
+fn test_profile_separators() -> anyhow::Result<()> {
+    use crate::csv::profile::ColumnMapping;
+
+    // Comma separated, ISO dates, decimal point
+    let profile = CsvProfile {
+        name: "otherbank".to_string(),
+        delimiter: ',',
+        skip_rows: 1,
+        date_format: "%Y-%m-%d".to_string(),
+        decimal_separator: '.',
+        thousands_separator: Some(','),
+        summary: None,
+        columns: ColumnMapping {
+            date: Column::Index(0),
+            statement: Column::Index(2),
+            amount: Some(Column::Index(1)),
+            debit: None,
+            credit: None,
+        },
+        ..CsvProfile::default()
+    };
+    let content = "Export of account 42\nDate,Amount,Label\n2021-04-02,\"-1,234.50\",RENT\n";
+    let result = parse_csv_content_with_profile(content, &profile)?;
+
+    assert_eq!(result.account.number, "otherbank", "The profile name should be the default account");
+    assert_eq!(result.activities.first().map(|a| a.amount), Some(Money::from_cents(-123450)), "Wrong amount");
+    assert_eq!(result.activities.first().map(|a| a.date), Some(NaiveDate::from_ymd(2021, 4, 2)), "Wrong date");
+
+    Ok(())
+}
+
+#[test]
+fn test_rejected_rows() -> anyhow::Result<()> {
+    let content = "Numéro Compte;123456789\nType;CCP\nDate;27/03/2021\nSolde (EUROS);187,77\n\n\
+        Date;Libellé;Montant(EUROS);Montant(FRANCS)\n\
+        26/03/2021;\"BUY SOMETHING 01\";-20,00;\n\
+        31/02/2021;\"BAD DATE\";-10,00;\n\
+        12/03/2021;\"BAD AMOUNT\";-15,0O;\n";
+    let result = parse_csv_content_with_profile(content, &CsvProfile::default())?;
+
+    assert_eq!(result.activities.len(), 1, "Only the valid activity should be kept");
+    assert_eq!(result.rejected.len(), 2, "Wrong count of rejected rows");
+    assert_eq!(result.rejected[0].line, 8, "Wrong line of the rejected row");
+    assert_eq!(result.rejected[0].content, "31/02/2021;\"BAD DATE\";-10,00;", "Wrong content of the rejected row");
+    assert!(result.rejected[1].reason.contains("-15,0O"), "The reason should tell the invalid amount");
+
+    Ok(())
+}
+
+#[test]
+fn test_headerless_profile() -> anyhow::Result<()> {
+    use crate::csv::profile::ColumnMapping;
+
+    // No header: every row is an activity, the first one too
+    let profile = CsvProfile {
+        name: "headerless".to_string(),
+        summary: None,
+        columns: ColumnMapping {
+            date: Column::Index(0),
+            statement: Column::Index(1),
+            amount: Some(Column::Index(2)),
+            debit: None,
+            credit: None,
+        },
+        ..CsvProfile::default()
+    };
+    let content = "31/02/2021;BAD DATE;-10,00\n02/04/2021;LOYER AVRIL;-650,00\n";
+    let result = parse_csv_content_with_profile(content, &profile)?;
+
+    assert_eq!(result.activities.len(), 1, "Only the valid activity should be kept");
+    assert_eq!(result.rejected.len(), 1, "A bad first row should be rejected, not taken for the header");
+    assert_eq!(result.rejected[0].line, 1, "Wrong line of the rejected row");
+
+    // The header is recognised by the named columns
+    let content = "Date;Libellé;Montant(EUROS)\n31/02/2021;BAD DATE;-10,00\n";
+    let result = parse_csv_content_with_profile(content, &CsvProfile { summary: None, ..CsvProfile::default() })?;
+    assert_eq!(result.rejected.len(), 1, "The row after the header should be rejected");
+    assert_eq!(result.rejected[0].line, 2, "Wrong line of the rejected row");
+
+    Ok(())
+}
@@ -0,0 +1,299 @@
+use std::path::Path;
+
+use serde::{Deserialize, Serialize};
+
+/**
+ * Import profiles, loaded from the file given by `import_profiles_path` in config.toml
+ */
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct CsvProfiles {
+    #[serde(default)]
+    pub profiles: Vec<CsvProfile>,
+}
+
+/**
+ * A column given by its position (starting at 0) or by its header text
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(untagged)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+/**
+ * Where to find each field of an activity.
+ * The amount is either a single signed column or split between a debit and a credit column.
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ColumnMapping {
+    pub date: Column,
+    pub statement: Column,
+    pub amount: Option<Column>,
+    pub debit: Option<Column>,
+    pub credit: Option<Column>,
+}
+
+/**
+ * Labels of the "label;value" lines put before the activities (La Banque Postale)
+ */
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct SummaryFields {
+    pub account_number: Option<String>,
+    pub account_type: Option<String>,
+    pub date: Option<String>,
+    pub balance: Option<String>,
+}
+
+/**
+ * Layout of the CSV export of a bank
+ */
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CsvProfile {
+    pub name: String,
+    /**
+     * Statements in this folder are always read with this profile
+     */
+    pub folder: Option<String>,
+    /**
+     * Text of the header line identifying the layout. Without it, a header holding all the named columns is looked for.
+     */
+    pub detect: Option<String>,
+    #[serde(default = "default_delimiter")]
+    pub delimiter: char,
+    /**
+     * Encoding label (ie. "windows-1252"), detected when missing
+     */
+    pub encoding: Option<String>,
+    #[serde(default)]
+    pub skip_rows: usize,
+    #[serde(default = "default_date_format")]
+    pub date_format: String,
+    #[serde(default = "default_decimal_separator")]
+    pub decimal_separator: char,
+    pub thousands_separator: Option<char>,
+    /**
+     * Account of the statements when the file does not tell it, the profile name otherwise
+     */
+    pub account_number: Option<String>,
+    pub account_type: Option<String>,
+    pub summary: Option<SummaryFields>,
+    pub columns: ColumnMapping,
+}
+
+fn default_delimiter() -> char {
+    ';'
+}
+
+fn default_date_format() -> String {
+    "%d/%m/%Y".to_string()
+}
+
+fn default_decimal_separator() -> char {
+    ','
+}
+
+/**
+ * Lines looked at when auto-detecting the profile of a statement
+ */
+const DETECTION_LINES: usize = 20;
+
+/**
+ * The La Banque Postale export, used when no other profile applies
+ */
+impl Default for CsvProfile {
+    fn default() -> Self {
+        Self {
+            name: "labanquepostale".to_string(),
+            folder: None,
+            detect: None,
+            delimiter: default_delimiter(),
+            encoding: None,
+            skip_rows: 0,
+            date_format: default_date_format(),
+            decimal_separator: default_decimal_separator(),
+            thousands_separator: None,
+            account_number: None,
+            account_type: None,
+            summary: Some(SummaryFields {
+                account_number: Some("Numéro Compte".to_string()),
+                account_type: Some("Type".to_string()),
+                date: Some("Date".to_string()),
+                balance: Some("Solde (EUROS)".to_string()),
+            }),
+            columns: ColumnMapping {
+                date: Column::Name("Date".to_string()),
+                statement: Column::Name("Libellé".to_string()),
+                amount: Some(Column::Name("Montant(EUROS)".to_string())),
+                debit: None,
+                credit: None,
+            },
+        }
+    }
+}
+
+/**
+ * Compare labels of a CSV file, ignoring case and accents: some exports lose them (replaced by '?' or U+FFFD),
+ * so any non ASCII character matches any character
+ */
+pub fn same_label(left: &str, right: &str) -> bool {
+    let left: Vec<char> = left.trim().trim_matches('"').trim().chars().collect();
+    let right: Vec<char> = right.trim().trim_matches('"').trim().chars().collect();
+    let is_wildcard = |c: &char| !c.is_ascii() || *c == '?';
+
+    left.len() == right.len()
+        && left
+            .iter()
+            .zip(right.iter())
+            .all(|(l, r)| is_wildcard(l) || is_wildcard(r) || l.eq_ignore_ascii_case(r))
+}
+
+/**
+ * Load the import profiles, none without a profile file
+ */
+pub fn load_profiles(profiles_path: Option<&str>) -> anyhow::Result<Vec<CsvProfile>> {
+    match profiles_path {
+        Some(path) => {
+            let profiles: CsvProfiles = confy::load_path(path)?;
+            Ok(profiles.profiles)
+        }
+        None => Ok(Vec::new()),
+    }
+}
+
+impl CsvProfile {
+    fn column_names(&self) -> Vec<&str> {
+        let columns = &self.columns;
+        [Some(&columns.date), Some(&columns.statement), columns.amount.as_ref(), columns.debit.as_ref(), columns.credit.as_ref()]
+            .into_iter()
+            .flatten()
+            .filter_map(|c| match c {
+                Column::Name(name) => Some(name.as_str()),
+                Column::Index(_) => None,
+            })
+            .collect()
+    }
+
+    /**
+     * The statement is in the folder of this profile or in one of its sub folders (or archives)
+     */
+    fn is_in_folder(&self, file_path: &Path) -> bool {
+        match (&self.folder, file_path.parent()) {
+            (Some(folder), Some(parent)) => parent.starts_with(folder),
+            _ => false,
+        }
+    }
+
+    /**
+     * The header of the statement is the one of this profile
+     */
+    pub fn matches_header(&self, content: &str) -> bool {
+        content
+            .lines()
+            .skip(self.skip_rows)
+            .take(DETECTION_LINES)
+            .any(|line| self.is_header(line))
+    }
+
+    /**
+     * The line contains `detect`, or all the named columns without it.
+     * A profile with neither has no header to recognise, its header rows must be skipped with `skip_rows`.
+     */
+    pub fn is_header(&self, line: &str) -> bool {
+        let names = self.column_names();
+        match &self.detect {
+            Some(detect) => line.contains(detect.as_str()),
+            None if names.is_empty() => false,
+            None => {
+                let cells: Vec<&str> = line.split(self.delimiter).collect();
+                names.iter().all(|name| cells.iter().any(|cell| same_label(cell, name)))
+            }
+        }
+    }
+}
+
+/**
+ * Choose the profile of a statement: the one of its folder first, then the first one whose header matches.
+ * Statements matching no profile are La Banque Postale exports.
+ */
+pub fn select_profile<P: AsRef<Path>>(file_path: P, content: &str, profiles: &[CsvProfile]) -> CsvProfile {
+    profiles
+        .iter()
+        .find(|p| p.is_in_folder(file_path.as_ref()))
+        .or_else(|| profiles.iter().find(|p| p.matches_header(content)))
+        .cloned()
+        .unwrap_or_default()
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::csv::profile::{Column, CsvProfile, load_profiles, same_label, select_profile};
+
+    const PROFILES: &str = r#"
+        [[profiles]]
+        name = "creditmutuel"
+        folder = "./data/creditmutuel"
+        columns = { date = "Date", statement = "Libellé", debit = "Débit", credit = "Crédit" }
+
+        [[profiles]]
+        name = "boursorama"
+        detect = "dateOp;dateVal;label"
+        thousands_separator = " "
+        columns = { date = 0, statement = 2, amount = 6 }
+    "#;
+
+    fn profiles() -> anyhow::Result<Vec<CsvProfile>> {
+        let profiles_path = std::env::temp_dir().join(format!("lpr-profiles-{:?}.toml", std::thread::current().id()));
+        std::fs::write(&profiles_path, PROFILES)?;
+        let profiles = load_profiles(profiles_path.to_str());
+        std::fs::remove_file(&profiles_path)?;
+        profiles
+    }
+
+    #[test]
+    fn test_load_profiles() -> anyhow::Result<()> {
+        let profiles = profiles()?;
+        assert_eq!(profiles.len(), 2, "Wrong count of profiles");
+        assert!(load_profiles(None)?.is_empty(), "No profile expected without profile file");
+
+        let boursorama = &profiles[1];
+        assert_eq!(boursorama.delimiter, ';', "Default delimiter not applied");
+        assert_eq!(boursorama.date_format, "%d/%m/%Y", "Default date format not applied");
+        assert_eq!(boursorama.thousands_separator, Some(' '), "Wrong thousands separator");
+        assert_eq!(boursorama.columns.amount, Some(Column::Index(6)), "Wrong amount column");
+
+        let creditmutuel = &profiles[0];
+        assert_eq!(creditmutuel.columns.debit, Some(Column::Name("Débit".to_string())), "Wrong debit column");
+        Ok(())
+    }
+
+    #[test]
+    fn test_select_profile() -> anyhow::Result<()> {
+        let profiles = profiles()?;
+
+        let by_folder = select_profile("./data/creditmutuel/releve.csv", "", &profiles);
+        assert_eq!(by_folder.name, "creditmutuel", "Profile of the folder not selected");
+
+        let in_archive = select_profile("./data/creditmutuel/2021.zip/janvier.csv", "", &profiles);
+        assert_eq!(in_archive.name, "creditmutuel", "Profile of the folder not selected for its sub folders and archives");
+
+        let by_detect = select_profile("./data/export.csv", "dateOp;dateVal;label;category\n", &profiles);
+        assert_eq!(by_detect.name, "boursorama", "Profile not detected from the header");
+
+        let by_names = select_profile("./data/export.csv", "Date;Date valeur;D\u{FFFD}bit;Cr\u{FFFD}dit;Libell\u{FFFD};Solde\n", &profiles);
+        assert_eq!(by_names.name, "creditmutuel", "Profile not detected from the column names");
+
+        let fallback = select_profile("./data/input01.csv", "Numéro Compte;123456789\n", &profiles);
+        assert_eq!(fallback.name, "labanquepostale", "La Banque Postale should be the default profile");
+        Ok(())
+    }
+
+    #[test]
+    fn test_same_label() {
+        assert!(same_label("Num\u{FFFD}ro Compte   ", "Numéro Compte"), "Lost accent should be ignored");
+        assert!(same_label("\"LIBELLE\"", "Libellé"), "Case and quotes should be ignored");
+        assert!(!same_label("Débit", "Crédit"), "Different labels should not match");
+    }
+}
@@ -2,7 +2,7 @@ pub mod sqlite;
 
 use serde::{Serialize, Deserialize};
 use std::sync::{Arc, Mutex};
-use crate::models::{AccountActivity, AccountBalance, StatsAmountPerMonthByTag, StatsDetailedAmountPerMonthByTag, tagging::{ActivityToTags, TagsPattern}};
+use crate::models::{Account, AccountActivity, AccountBalance, DateRange, StatsAmountPerMonthByCategory, StatsAmountPerMonthByTag, StatsDetailedAmountPerMonthByTag, import::ImportedFile, tagging::{ActivityToTags, Condition, MatchKind, RuleOptions, SavedTagOverride, Tag, TagOverride, TaggedAmount, TagsPattern}};
 
 
 pub type ArcMutDB<T> = Arc<Mutex<T>>;
@@ -19,7 +19,7 @@ pub mod utils {
 
 }
 
-#[allow(unused)]
+#[allow(unused, clippy::upper_case_acronyms)]
 pub enum DBConfig {
     File { file_name: String},
     FileWithOverwrite { file_name: String},
@@ -35,14 +35,40 @@ pub trait DBActions {
     fn with_init_db_script(self, init_db_path: String) -> Self;
     fn clean_db(&self) -> anyhow::Result<()>;
     fn from_config(conf: DBConfig) -> Self;
+    fn in_transaction<R, F: FnOnce(&mut Self) -> anyhow::Result<R>>(&mut self, f: F) -> anyhow::Result<R>;
     fn create_table(&self) -> anyhow::Result<usize>;
-    fn insert_activities(&mut self,banking_statement: &[AccountActivity]) -> anyhow::Result<usize>;
+    fn is_initialized(&self) -> anyhow::Result<bool>;
+    fn check_schema(&self) -> anyhow::Result<()>;
+    fn insert_account(&self, account: &Account) -> anyhow::Result<u32>;
+    fn get_accounts(&self) -> anyhow::Result<Vec<Account>>;
+    fn insert_activities(&mut self,banking_statement: &[AccountActivity]) -> anyhow::Result<Vec<u32>>;
     fn insert_balance(&self,balance: AccountBalance) -> anyhow::Result<usize>;
-    fn get_activities(&self) -> anyhow::Result<Vec<AccountActivity>>;
-    fn get_balance(&self) -> anyhow::Result<AccountBalance>;
+    fn get_activities(&self, account_id: Option<u32>) -> anyhow::Result<Vec<AccountActivity>>;
+    fn get_activities_by_ids(&self, ids: &[u32]) -> anyhow::Result<Vec<AccountActivity>>;
+    fn find_import(&self, hash: &str) -> anyhow::Result<Option<ImportedFile>>;
+    fn insert_import(&self, imported_file: &ImportedFile) -> anyhow::Result<usize>;
+    fn get_balances(&self, account_id: Option<u32>) -> anyhow::Result<Vec<AccountBalance>>;
+    fn get_balance_history(&self, account_id: Option<u32>, range: &DateRange) -> anyhow::Result<Vec<AccountBalance>>;
     fn get_tag_patterns(&self) -> anyhow::Result<Vec<TagsPattern>>;
+    fn get_tags(&self) -> anyhow::Result<Vec<Tag>>;
+    fn insert_tag(&self, tag: &str) -> anyhow::Result<u32>;
+    fn create_tag(&self, tag: &str, parent_id: Option<u32>) -> anyhow::Result<u32>;
+    fn update_tag(&self, id: u32, tag: &str, parent_id: Option<u32>) -> anyhow::Result<()>;
+    fn delete_tag(&mut self, id: u32) -> anyhow::Result<()>;
+    fn insert_tag_pattern(&mut self, pattern: &str, match_kind: MatchKind, rule: Option<&Condition>, options: &RuleOptions, tag_ids: &[u32]) -> anyhow::Result<u32>;
+    fn update_tag_pattern(&mut self, id: u32, pattern: &str, match_kind: MatchKind, rule: Option<&Condition>, options: &RuleOptions, tag_ids: &[u32]) -> anyhow::Result<()>;
+    fn delete_tag_pattern(&mut self, id: u32) -> anyhow::Result<()>;
+    fn set_tag_pattern_tags(&mut self, id: u32, tag_ids: &[u32]) -> anyhow::Result<()>;
     fn insert_activity_tags(&mut self, activity_tags: &[ActivityToTags]) -> anyhow::Result<usize>;
-    fn get_stats_tag_per_month(&self, tags: &[String]) -> anyhow::Result<Vec<StatsAmountPerMonthByTag>>;
+    fn replace_activity_tags(&mut self, activity_ids: &[u32], activity_tags: &[ActivityToTags]) -> anyhow::Result<usize>;
+    fn set_tag_overrides(&mut self, activity_id: u32, tag_overrides: &[TagOverride]) -> anyhow::Result<usize>;
+    fn delete_tag_overrides(&mut self, activity_id: u32, tag: Option<&str>) -> anyhow::Result<usize>;
+    fn get_saved_tag_overrides(&self) -> anyhow::Result<Vec<SavedTagOverride>>;
+    fn restore_tag_overrides(&mut self, saved: &[SavedTagOverride]) -> anyhow::Result<usize>;
+    fn get_stats_tag_per_month(&self, tags: &[String], account_id: Option<u32>) -> anyhow::Result<Vec<StatsAmountPerMonthByTag>>;
+    fn get_tagged_amounts(&self, account_id: Option<u32>, range: &DateRange) -> anyhow::Result<Vec<TaggedAmount>>;
+    fn get_stats_category_per_month(&self, account_id: Option<u32>) -> anyhow::Result<Vec<StatsAmountPerMonthByCategory>>;
+    #[allow(unused)]
     fn get_stats_detailed_amount_per_month(&self, tags: &[String]) -> anyhow::Result<Vec<StatsDetailedAmountPerMonthByTag>>;
 }
 
@@ -50,12 +76,16 @@ pub trait DBActions {
 
 #[derive(Default, Debug, Serialize, Deserialize)]
 struct InitTables {
+    table_accounts: String,
     table_activities: String,
+    index_activities: String,
     table_balance: String,
+    table_imports: String,
     table_tags: String,
     table_tags_pattern: String,
     table_tags_pattern_to_tags: String,
     table_activities_tags: String,
+    table_activities_tags_manual: String,
     predefined_tags: String,
     predefined_tags_pattern: String,
     predefined_tags_pattern_to_tags: String
@@ -1,17 +1,96 @@
-use std::path::Path;
+use std::{collections::HashMap, path::Path};
 
+use chrono::NaiveDate;
 use itertools::Itertools;
-use ordered_float::OrderedFloat;
-use rusqlite::{Connection, OpenFlags, named_params, params_from_iter};
-use crate::{db::InitTables, models::{AccountActivity, AccountBalance, StatsAmountPerMonthByTag, StatsDetailedAmountPerMonthByTag, tagging::{ActivityToTags, TagsPattern}}};
+use rusqlite::{Connection, OpenFlags, Row, named_params, params_from_iter, types::Value};
+use crate::{db::InitTables, models::{Account, AccountActivity, AccountBalance, ActivityDetails, DateRange, StatsAmountPerMonthByCategory, StatsAmountPerMonthByTag, StatsDetailedAmountPerMonthByTag, import::ImportedFile, tagging::{ActivityToTags, Condition, MatchKind, PATH_SEPARATOR, RuleOptions, SavedTagOverride, Tag, TagOverride, TaggedAmount, TagsPattern, compile_rule}}};
+use crate::errors::RequestError;
+use crate::money::Money;
 use super::{DBActions, DBConfig, utils::remove_db_if_exist};
 
 
+/**
+ * Version of the tables created by the init script, stored in `PRAGMA user_version` (0 before it was set).
+ * To change with the tables, ie. the amounts stored as cents instead of euros.
+ */
+const SCHEMA_VERSION: u32 = 2;
+
 pub struct SqliteDB {
     conn: Connection,
     init_db_path: Option<String>,
 }
 
+/**
+ * Columns read by `activity_from_row`, the primary tag pattern id and the list of the tag pattern ids must be selected right after them
+ */
+const ACTIVITY_COLUMNS: &str = "a.rowid, a.account_id, a.date, a.statement, a.amount, a.transaction_id, a.category, a.counterparty, a.end_to_end_id, a.value_date, a.operation_type, a.card_number, a.operation_date, a.merchant, a.city";
+
+fn activity_from_row(row: &Row) -> rusqlite::Result<AccountActivity> {
+    Ok(AccountActivity {
+        row_id : row.get(0)?,
+        account_id : row.get(1)?,
+        date : row.get(2)?,
+        statement : row.get(3)?,
+        amount : row.get(4)?,
+        transaction_id: row.get(5)?,
+        details: ActivityDetails {
+            category: row.get(6)?,
+            counterparty: row.get(7)?,
+            end_to_end_id: row.get(8)?,
+            value_date: row.get(9)?,
+            operation_type: row.get(10)?,
+            card_number: row.get(11)?,
+            operation_date: row.get(12)?,
+            merchant: row.get(13)?,
+            city: row.get(14)?,
+        },
+        tag_pattern_id: row.get(15)?,
+        tag_pattern_ids: row
+            .get::<_, Option<String>>(16)?
+            .map(|ids| ids.split(',').filter_map(|id| id.parse().ok()).sorted().collect())
+            .unwrap_or_default(),
+        tag_overrides: Vec::new(),
+    })
+}
+
+/**
+ * The tags of each activity (activity_id, tags_id): the tags of the rules applied to it and the ones their regex captured, 
+ * without the ones removed by hand, and the ones given by hand
+ */
+const ACTIVITY_TAGS: &str = "
+    SELECT rt.activity_id, rt.tags_id
+    FROM (
+        SELECT at.activity_id, tptt.tags_id
+        FROM activities_tags at
+        JOIN tags_pattern_to_tags tptt ON tptt.tags_pattern_id = at.tags_pattern_id
+        UNION
+        SELECT at.activity_id, at.captured_tags_id FROM activities_tags at WHERE at.captured_tags_id IS NOT NULL
+    ) rt
+    WHERE NOT EXISTS (
+        SELECT 1 FROM activities_tags_manual atm 
+        WHERE atm.activity_id = rt.activity_id AND atm.tags_id = rt.tags_id AND atm.assigned = 0
+    )
+    UNION
+    SELECT atm.activity_id, atm.tags_id FROM activities_tags_manual atm WHERE atm.assigned = 1
+";
+
+/**
+ * Same as `ACTIVITY_TAGS` with the parents of the tags: an activity tagged EDF is tagged ENERGIE and LOGEMENT too
+ */
+fn activity_tags_with_parents() -> String {
+    format!("
+    WITH RECURSIVE activity_tags(activity_id, tags_id) AS (
+        SELECT activity_id, tags_id FROM ({})
+        UNION
+        SELECT activity_tags.activity_id, t.parent_id
+        FROM activity_tags
+        JOIN tags t ON t.id = activity_tags.tags_id
+        WHERE t.parent_id IS NOT NULL
+    )
+    SELECT activity_id, tags_id FROM activity_tags
+    ", ACTIVITY_TAGS)
+}
+
 impl SqliteDB {
 
     fn from_file<P: AsRef<Path>>(file_db: P) -> Self {
@@ -42,6 +121,160 @@ impl SqliteDB {
         self.conn.close().map_err(|err| anyhow::anyhow!(err.1))
     }
 
+    fn get_tag_overrides(&self) -> anyhow::Result<HashMap<u32, Vec<TagOverride>>> {
+        let mut stmt = self.conn.prepare("
+        SELECT atm.activity_id, t.tag, atm.assigned
+        FROM activities_tags_manual atm
+        JOIN tags t ON t.id = atm.tags_id
+        ORDER BY atm.activity_id, t.tag
+        ")?;
+        let mut rows = stmt.query([])?;
+        let mut tag_overrides: HashMap<u32, Vec<TagOverride>> = HashMap::new();
+        while let Some(row) = rows.next()? {
+            tag_overrides
+                .entry(row.get(0)?)
+                .or_default()
+                .push(TagOverride { tag: row.get(1)?, assigned: row.get(2)? });
+        }
+        Ok(tag_overrides)
+    }
+
+    /**
+     * A tag name must be unique (whatever the case, as in the tag paths) and its parent must exist,
+     * without being the tag itself or one of its sub tags
+     */
+    fn check_tag(&self, id: Option<u32>, tag: &str, parent_id: Option<u32>) -> anyhow::Result<()> {
+        if tag.is_empty() {
+            return Err(RequestError::Invalid("Empty tag".to_string()).into());
+        }
+        if tag.contains(PATH_SEPARATOR) {
+            return Err(RequestError::Invalid(format!("Tag '{}' can not contain '{}'", tag, PATH_SEPARATOR)).into());
+        }
+        let tags = self.get_tags()?;
+        if let Some(other) = tags.iter().find(|t| Some(t.id) != id && t.tag.to_lowercase() == tag.to_lowercase()) {
+            return Err(RequestError::Conflict(format!("Tag '{}' already exists ({})", other.tag, other.id)).into());
+        }
+
+        let parent = match parent_id {
+            Some(parent_id) => tags.iter().find(|t| t.id == parent_id).ok_or_else(|| RequestError::NotFound(format!("Unknown parent tag {}", parent_id)))?,
+            None => return Ok(()),
+        };
+        // Up from the parent until a tag without parent, at most through all the tags
+        let mut ancestor = Some(parent);
+        for _ in 0..tags.len() {
+            let current = match ancestor {
+                Some(current) => current,
+                None => break,
+            };
+            if Some(current.id) == id {
+                return Err(RequestError::Invalid(format!("Tag '{}' can not be a sub tag of itself", tag)).into());
+            }
+            ancestor = current.parent_id.and_then(|p| tags.iter().find(|t| t.id == p));
+        }
+        Ok(())
+    }
+
+    /**
+     * Id of the tag, found whatever its case as the tag names are unique
+     */
+    fn find_tag_id(conn: &Connection, tag: &str) -> anyhow::Result<Option<u32>> {
+        let mut stmt = conn.prepare("SELECT id, tag FROM tags")?;
+        let tags = stmt.query_map([], |row| Ok((row.get::<_, u32>(0)?, row.get::<_, String>(1)?)))?.collect::<rusqlite::Result<Vec<(u32, String)>>>()?;
+        Ok(tags.into_iter().find(|(_, name)| name.to_lowercase() == tag.to_lowercase()).map(|(id, _)| id))
+    }
+
+    /**
+     * Id of the tag, created without parent when missing. The name must have been checked.
+     */
+    fn find_or_add_tag(conn: &Connection, tag: &str) -> anyhow::Result<u32> {
+        if let Some(tag_id) = Self::find_tag_id(conn, tag)? {
+            return Ok(tag_id);
+        }
+        let tag_id: u32 = conn.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM tags", [], |row| row.get(0))?;
+        conn.execute("INSERT INTO tags (id, tag) VALUES (:id, :t)", named_params! { ":id" : tag_id, ":t" : tag })?;
+        Ok(tag_id)
+    }
+
+    /**
+     * Save the rules applied to the activities, with the tags their regex captured (created when missing)
+     */
+    fn insert_activity_tags_rows(conn: &Connection, activity_tags: &[ActivityToTags]) -> anyhow::Result<usize> {
+        let mut result : usize = 0;
+        let mut stmt = conn.prepare("
+            INSERT INTO activities_tags (activity_id, tags_pattern_id, is_primary, captured_tags_id) VALUES (:aid, :tpid, :prim, :ctid) 
+            ON CONFLICT(activity_id, tags_pattern_id) DO NOTHING 
+        ")?;
+        for activity_tag in activity_tags {
+            let captured_tag_id = activity_tag.captured_tag.as_deref().map(|tag| Self::find_or_add_tag(conn, tag)).transpose()?;
+            result += stmt.execute(named_params! { 
+                ":aid" : activity_tag.activity_id, ":tpid" : activity_tag.tags_pattern_id, ":prim" : activity_tag.primary, ":ctid" : captured_tag_id 
+            })?;
+        }
+        Ok(result)
+    }
+
+    fn check_tag_ids(conn: &Connection, tag_ids: &[u32]) -> anyhow::Result<()> {
+        let mut stmt = conn.prepare("SELECT COUNT(1) > 0 FROM tags WHERE id = :id")?;
+        for tag_id in tag_ids {
+            let exists: bool = stmt.query_row(named_params! { ":id" : tag_id }, |row| row.get(0))?;
+            if !exists {
+                return Err(RequestError::NotFound(format!("Unknown tag {}", tag_id)).into());
+            }
+        }
+        Ok(())
+    }
+
+    /**
+     * The tags given replace the previous ones of the tag pattern
+     */
+    fn replace_tag_pattern_tags(conn: &Connection, id: u32, tag_ids: &[u32]) -> anyhow::Result<()> {
+        conn.execute("DELETE FROM tags_pattern_to_tags WHERE tags_pattern_id = :id", named_params! { ":id" : id })?;
+        let mut stmt = conn.prepare("INSERT INTO tags_pattern_to_tags (tags_pattern_id, tags_id) VALUES (:tpid, :tid)")?;
+        for tag_id in tag_ids.iter().unique() {
+            stmt.execute(named_params! { ":tpid" : id, ":tid" : tag_id })?;
+        }
+        Ok(())
+    }
+
+    /**
+     * A tag pattern must exist and give at least one tag, all of them existing
+     */
+    fn check_tag_pattern(conn: &Connection, id: u32, tag_ids: &[u32]) -> anyhow::Result<()> {
+        if tag_ids.is_empty() {
+            return Err(RequestError::Invalid("A rule needs at least one tag".to_string()).into());
+        }
+        Self::check_tag_ids(conn, tag_ids)?;
+        let exists: bool = conn.query_row(
+            "SELECT COUNT(1) > 0 FROM tags_pattern WHERE id = :id", 
+            named_params! { ":id" : id }, 
+            |row| row.get(0)
+        )?;
+        if !exists {
+            return Err(RequestError::NotFound(format!("Unknown tag pattern {}", id)).into());
+        }
+        Ok(())
+    }
+
+    /**
+     * The changes of `f` are kept only when it succeeds. Unlike a transaction, a savepoint can be nested
+     * in the one of `in_transaction`.
+     */
+    fn in_savepoint<R, F: FnOnce(&Connection) -> anyhow::Result<R>>(conn: &mut Connection, f: F) -> anyhow::Result<R> {
+        let mut savepoint = conn.savepoint()?;
+        match f(&savepoint) {
+            Ok(result) => {
+                savepoint.commit()?;
+                Ok(result)
+            },
+            Err(err) => {
+                // A savepoint dropped is only rolled back, it must be released too to end the transaction
+                savepoint.rollback()?;
+                savepoint.commit()?;
+                Err(err)
+            }
+        }
+    }
+
     #[cfg(test)]
     pub fn connection(&self) -> &Connection {
         &self.conn
@@ -74,104 +307,316 @@ impl DBActions for SqliteDB {
         }
     }
     
+    /**
+     * The changes of the closure are kept only when it succeeds. The methods writing several statements
+     * use savepoints, so that they can run inside.
+     */
+    fn in_transaction<R, F: FnOnce(&mut Self) -> anyhow::Result<R>>(&mut self, f: F) -> anyhow::Result<R> {
+        self.conn.execute_batch("SAVEPOINT in_transaction")?;
+        match f(self) {
+            Ok(result) => {
+                self.conn.execute_batch("RELEASE in_transaction")?;
+                Ok(result)
+            },
+            Err(err) => {
+                self.conn.execute_batch("ROLLBACK TO in_transaction; RELEASE in_transaction")?;
+                Err(err)
+            }
+        }
+    }
+
     fn create_table(&self) -> anyhow::Result<usize> {
         let init_db_script = self.init_db_path.as_ref().ok_or(anyhow::anyhow!("Missing DB int script path"))?;
         let init_tables: InitTables = confy::load_path(init_db_script.as_str())?;
         let scripts = vec!(
+            init_tables.table_accounts.as_str(),
             init_tables.table_activities.as_str(),
+            init_tables.index_activities.as_str(),
             init_tables.table_balance.as_str(),
+            init_tables.table_imports.as_str(),
             init_tables.table_tags.as_str(),
             init_tables.table_tags_pattern.as_str(),
             init_tables.table_tags_pattern_to_tags.as_str(),
             init_tables.table_activities_tags.as_str(),
+            init_tables.table_activities_tags_manual.as_str(),
             init_tables.predefined_tags.as_str(),
             init_tables.predefined_tags_pattern.as_str(),
             init_tables.predefined_tags_pattern_to_tags.as_str()
         );
 
-        let mut update = 0 as usize;
+        let mut update: usize = 0;
         for script in scripts {
             let n = self.conn.execute(script,[],)
             .map_err(|err| anyhow::anyhow!("Fail executing init db script : {:?}", err))?;
             update += n;
         }
+        self.conn.pragma_update(None, "user_version", SCHEMA_VERSION)?;
 
         Ok(update)        
     }
 
-    fn insert_activities(&mut self, banking_activites: &[AccountActivity]) -> anyhow::Result<usize> {
-        let mut result : usize = 0;
-        let tx = self.conn.transaction()?;
-        {
+    /**
+     * A database created by another version can not be read (ie. its amounts are euros and not cents), it must be rebuilt.
+     * A database without tables is fine: they are created by the first import.
+     */
+    fn check_schema(&self) -> anyhow::Result<()> {
+        let tables: usize = self.conn.query_row("SELECT COUNT(*) FROM sqlite_master WHERE type = 'table'", [], |row| row.get(0))?;
+        let version: u32 = self.conn.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        if tables > 0 && version != SCHEMA_VERSION {
+            return Err(anyhow::anyhow!(
+                "The database was created by another version (schema {} instead of {}) : rebuild it with --db", version, SCHEMA_VERSION
+            ));
+        }
+        Ok(())
+    }
+
+    /**
+     * The tables are created by the first import. A database with the activities but without the import ledger
+     * was created by an older version: the ledger can not be filled back, the database must be rebuilt.
+     */
+    fn is_initialized(&self) -> anyhow::Result<bool> {
+        let count: usize = self.conn.query_row(
+            "SELECT COUNT(*) FROM sqlite_master WHERE type = 'table' AND name IN ('activities', 'imports')",
+            [],
+            |row| row.get(0)
+        )?;
+        match count {
+            0 => Ok(false),
+            2 => Ok(true),
+            _ => Err(anyhow::anyhow!("The database has no import ledger, it was created by an older version : rebuild it with --db")),
+        }
+    }
+
+    fn insert_account(&self, account: &Account) -> anyhow::Result<u32> {
+        let mut stmt = self.conn.prepare("
+            INSERT INTO accounts (number, type) VALUES (:n, :t) ON CONFLICT(number) DO UPDATE SET type = excluded.type
+        ")?;
+        stmt.execute(named_params! { ":n" : account.number, ":t" : account.account_type })
+            .map_err(|err| anyhow::anyhow!(err))?;
+
+        let account_id = self.conn.query_row(
+            "SELECT id FROM accounts WHERE number = :n",
+            named_params! { ":n" : account.number },
+            |row| row.get(0)
+        )?;
+        Ok(account_id)
+    }
+
+    fn get_accounts(&self) -> anyhow::Result<Vec<Account>> {
+        let mut stmt = self.conn.prepare("SELECT id, number, type FROM accounts ORDER BY id")?;
+        let mut rows = stmt.query([])?;
+        let mut accounts = Vec::new();
+        while let Some(row) = rows.next()? {
+            accounts.push(Account {
+                row_id: row.get(0)?,
+                number: row.get(1)?,
+                account_type: row.get(2)?
+            });
+        }
+        Ok(accounts)
+    }
+
+    /**
+     * Insert the activities of one statement.
+     * Identical activities (same account, date, statement and amount) are numbered by their occurrence in the statement:
+     * 2 identical purchases of a statement are both kept, while the same purchases of an overlapping statement are skipped.
+     */
+    fn insert_activities(&mut self, banking_activites: &[AccountActivity]) -> anyhow::Result<Vec<u32>> {
+        let mut result : Vec<u32> = Vec::new();
+        let mut occurrences: HashMap<(Option<u32>, NaiveDate, &str, Money), u32> = HashMap::new();
+        Self::in_savepoint(&mut self.conn, |tx| {
             let mut stmt = tx.prepare("
-                INSERT INTO activities (date, statement, amount) VALUES (:d, :s, :a) ON CONFLICT(date, statement, amount) DO NOTHING 
+                INSERT INTO activities (account_id, date, statement, amount, occurrence, transaction_id, category, counterparty, end_to_end_id, value_date, 
+                                        operation_type, card_number, operation_date, merchant, city) 
+                VALUES (:acc, :d, :s, :a, :occ, :tid, :cat, :cp, :e2e, :vd, :ot, :card, :od, :m, :city) ON CONFLICT DO NOTHING 
             ")?;
 
             for activity in banking_activites {            
-                result += 
+                let occurrence = occurrences
+                    .entry((activity.account_id, activity.date, activity.statement.as_str(), activity.amount))
+                    .or_insert(0);
+                let inserted = 
                     stmt.execute(
-                        named_params! { ":d" : activity.date, ":s" : activity.statement, ":a" : activity.amount.to_string()}
+                        named_params! { 
+                            ":acc" : activity.account_id, 
+                            ":d" : activity.date, 
+                            ":s" : activity.statement, 
+                            ":a" : activity.amount, 
+                            ":occ" : *occurrence,
+                            ":tid" : activity.transaction_id,
+                            ":cat" : activity.details.category,
+                            ":cp" : activity.details.counterparty,
+                            ":e2e" : activity.details.end_to_end_id,
+                            ":vd" : activity.details.value_date,
+                            ":ot" : activity.details.operation_type,
+                            ":card" : activity.details.card_number,
+                            ":od" : activity.details.operation_date,
+                            ":m" : activity.details.merchant,
+                            ":city" : activity.details.city
+                        }
                     )
                     .map_err(|err| anyhow::anyhow!(err))?;
+                *occurrence += 1;
+                if inserted > 0 {
+                    result.push(tx.last_insert_rowid() as u32);
+                }
             }
-        }
-        tx.commit()?;
-        Ok(result)
+            Ok(result)
+        })
     }
 
     fn insert_balance(&self, balance: AccountBalance) -> anyhow::Result<usize> {
         let mut stmt = self.conn.prepare("
-            INSERT INTO balance (date, amount) VALUES (:d, :a) ON CONFLICT(date, amount) DO NOTHING 
+            INSERT INTO balance (account_id, date, amount) VALUES (:acc, :d, :a) 
+            ON CONFLICT(account_id, date) DO UPDATE SET amount = excluded.amount
         ")?;
 
         let result =
                 stmt.execute(
-                    named_params! { ":d" : balance.date, ":a" : balance.balance_euro.to_string()}
+                    named_params! { ":acc" : balance.account_id, ":d" : balance.date, ":a" : balance.balance_euro}
                 )
                 .map_err(|err| anyhow::anyhow!(err))?;    
 
         Ok(result)
     }
 
-    fn get_activities(&self) -> anyhow::Result<Vec<AccountActivity>> {
-        let mut stmt = self.conn.prepare("
-        SELECT a.rowid, a.date, a.statement, a.amount, at.tags_pattern_id
+    fn get_activities(&self, account_id: Option<u32>) -> anyhow::Result<Vec<AccountActivity>> {
+        let sql = format!("
+        SELECT {}, 
+            (SELECT at.tags_pattern_id FROM activities_tags at WHERE at.activity_id = a.rowid AND at.is_primary = 1),
+            (SELECT GROUP_CONCAT(at.tags_pattern_id) FROM activities_tags at WHERE at.activity_id = a.rowid)
         FROM activities a
-        LEFT JOIN activities_tags at ON at.activity_id = a.rowid
+        WHERE (:acc IS NULL OR a.account_id = :acc)
         ORDER BY date DESC
-        ")?;
-        let activities = stmt.query_map([], |row|
-            Ok(AccountActivity {
-                row_id : row.get(0)?,
-                date : row.get(1)?,
-                statement : row.get(2)?,
-                amount : row.get(3).map(|e| OrderedFloat(e))?,
-                tag_pattern_id: row.get(4).unwrap_or(None)
-            })
-        )?;
+        ", ACTIVITY_COLUMNS);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let activities = stmt.query_map(named_params! { ":acc" : account_id }, activity_from_row)?;
+        let mut tag_overrides = self.get_tag_overrides()?;
 
         let mut result:Vec<AccountActivity> = Vec::new();
         for activity in activities {
-            result.push(activity.unwrap());
+            let mut activity = activity?;
+            activity.tag_overrides = activity.row_id.and_then(|id| tag_overrides.remove(&id)).unwrap_or_default();
+            result.push(activity);
         }
         Ok(result)
     }
 
-    fn get_balance(&self) -> anyhow::Result<AccountBalance> {
-        let mut stmt = self.conn.prepare("SELECT rowid, date, amount  FROM balance")?;
-        let mut row = stmt.raw_query();
-        let row = row.next()?.unwrap();
+    fn get_activities_by_ids(&self, ids: &[u32]) -> anyhow::Result<Vec<AccountActivity>> {
+        let in_clause = ids
+            .iter()
+            .map(|_| "?")
+            .join(", ");
 
-        Ok(AccountBalance {                
-            row_id : row.get(0)?,
-            date : row.get(1)?,
-            balance_euro : row.get(2).map(|e| OrderedFloat(e))?,
-        })
+        let sql = format!("
+        SELECT {}, NULL, NULL
+        FROM activities a
+        WHERE a.rowid in ({})
+        ORDER BY date DESC
+        ", ACTIVITY_COLUMNS, in_clause);
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let activities = stmt.query_map(params_from_iter(ids), activity_from_row)?;
+
+        let mut result:Vec<AccountActivity> = Vec::new();
+        for activity in activities {
+            result.push(activity?);
+        }
+        Ok(result)
+    }
+
+    fn get_balances(&self, account_id: Option<u32>) -> anyhow::Result<Vec<AccountBalance>> {
+        // Latest balance of each account
+        let mut stmt = self.conn.prepare("
+        SELECT b.rowid, b.account_id, b.date, b.amount
+        FROM balance b
+        WHERE (:acc IS NULL OR b.account_id = :acc)
+        AND b.date = (SELECT MAX(date) FROM balance WHERE account_id = b.account_id)
+        ORDER BY b.account_id
+        ")?;
+        let mut rows = stmt.query(named_params! { ":acc" : account_id })?;
+        let mut balances = Vec::new();
+        while let Some(row) = rows.next()? {
+            balances.push(AccountBalance {                
+                row_id : row.get(0)?,
+                account_id : row.get(1)?,
+                date : row.get(2)?,
+                balance_euro : row.get(3)?,
+            });
+        }
+        Ok(balances)
+    }
+
+    fn get_balance_history(&self, account_id: Option<u32>, range: &DateRange) -> anyhow::Result<Vec<AccountBalance>> {
+        let mut stmt = self.conn.prepare("
+        SELECT b.rowid, b.account_id, b.date, b.amount
+        FROM balance b
+        WHERE (:acc IS NULL OR b.account_id = :acc)
+        AND (:from IS NULL OR b.date >= :from)
+        AND (:to IS NULL OR b.date <= :to)
+        ORDER BY b.date, b.account_id
+        ")?;
+        let mut rows = stmt.query(named_params! { ":acc" : account_id, ":from" : range.from, ":to" : range.to })?;
+        let mut balances = Vec::new();
+        while let Some(row) = rows.next()? {
+            balances.push(AccountBalance {                
+                row_id : row.get(0)?,
+                account_id : row.get(1)?,
+                date : row.get(2)?,
+                balance_euro : row.get(3)?,
+            });
+        }
+        Ok(balances)
+    }
+
+    fn find_import(&self, hash: &str) -> anyhow::Result<Option<ImportedFile>> {
+        let mut stmt = self.conn.prepare("
+        SELECT path, member, hash, statement_date, rows_inserted, rows_skipped, imported_at
+        FROM imports
+        WHERE hash = :h
+        ")?;
+        let mut rows = stmt.query(named_params! { ":h" : hash })?;
+        match rows.next()? {
+            Some(row) => Ok(Some(ImportedFile {
+                path: row.get(0)?,
+                member: row.get(1)?,
+                hash: row.get(2)?,
+                statement_date: row.get(3)?,
+                rows_inserted: row.get(4)?,
+                rows_skipped: row.get(5)?,
+                imported_at: row.get(6)?
+            })),
+            None => Ok(None)
+        }
+    }
+
+    fn insert_import(&self, imported_file: &ImportedFile) -> anyhow::Result<usize> {
+        let mut stmt = self.conn.prepare("
+            INSERT INTO imports (path, member, hash, statement_date, rows_inserted, rows_skipped, imported_at) 
+            VALUES (:p, :m, :h, :sd, :ri, :rs, :ia) ON CONFLICT(hash) DO NOTHING 
+        ")?;
+
+        let result = 
+            stmt.execute(
+                named_params! { 
+                    ":p" : imported_file.path, 
+                    ":m" : imported_file.member, 
+                    ":h" : imported_file.hash, 
+                    ":sd" : imported_file.statement_date, 
+                    ":ri" : imported_file.rows_inserted, 
+                    ":rs" : imported_file.rows_skipped, 
+                    ":ia" : imported_file.imported_at
+                }
+            )
+            .map_err(|err| anyhow::anyhow!(err))?;
+
+        Ok(result)
     }
 
     fn get_tag_patterns(&self) -> anyhow::Result<Vec<TagsPattern>> {
         let mut stmt = self.conn.prepare("
-        SELECT tp.id, tp.tags_pattern, t.tag
+        SELECT tp.id, tp.tags_pattern, tp.match_kind, tp.rule, tp.priority, tp.stop, tp.category, t.tag
         FROM tags_pattern tp, tags_pattern_to_tags tptt, tags t
         WHERE tp.id = tptt.tags_pattern_id and t.id = tptt.tags_id
         ")?;
@@ -181,66 +626,366 @@ impl DBActions for SqliteDB {
             tags_patterns.push(TagsPattern {
                 id: row.get(0)?, 
                 pattern: row.get(1)?,
-                tag: row.get(2)?
+                match_kind: row.get(2)?,
+                rule: row.get(3)?,
+                options: RuleOptions {
+                    priority: row.get(4)?,
+                    stop: row.get(5)?,
+                    category: row.get(6)?,
+                },
+                tag: row.get(7)?
             });
         }    
         Ok(tags_patterns)
     }
 
+    fn get_tags(&self) -> anyhow::Result<Vec<Tag>> {
+        let mut stmt = self.conn.prepare("SELECT id, tag, parent_id FROM tags ORDER BY id")?;
+        let mut rows = stmt.query([])?;
+        let mut tags = Vec::new();
+        while let Some(row) = rows.next()? {
+            tags.push(Tag { id: row.get(0)?, tag: row.get(1)?, parent_id: row.get(2)? });
+        }
+        Ok(tags)
+    }
+
+    fn insert_tag(&self, tag: &str) -> anyhow::Result<u32> {
+        // An existing tag keeps its id, whatever the case it is given with
+        if let Some(tag_id) = Self::find_tag_id(&self.conn, tag)? {
+            return Ok(tag_id);
+        }
+        self.check_tag(None, tag, None)?;
+        Self::find_or_add_tag(&self.conn, tag)
+    }
+
+    fn create_tag(&self, tag: &str, parent_id: Option<u32>) -> anyhow::Result<u32> {
+        let tag = tag.trim();
+        self.check_tag(None, tag, parent_id)?;
+        self.conn.execute("
+            INSERT INTO tags (id, tag, parent_id)
+            SELECT COALESCE((SELECT MAX(id) FROM tags), 0) + 1, :t, :pid
+        ", named_params! { ":t" : tag, ":pid" : parent_id })?;
+
+        let tag_id = self.conn.query_row(
+            "SELECT id FROM tags WHERE tag = :t",
+            named_params! { ":t" : tag },
+            |row| row.get(0)
+        )?;
+        Ok(tag_id)
+    }
+
+    fn update_tag(&self, id: u32, tag: &str, parent_id: Option<u32>) -> anyhow::Result<()> {
+        let tag = tag.trim();
+        if !self.get_tags()?.iter().any(|t| t.id == id) {
+            return Err(RequestError::NotFound(format!("Unknown tag {}", id)).into());
+        }
+        self.check_tag(Some(id), tag, parent_id)?;
+        self.conn.execute(
+            "UPDATE tags SET tag = :t, parent_id = :pid WHERE id = :id", 
+            named_params! { ":id" : id, ":t" : tag, ":pid" : parent_id }
+        )?;
+        Ok(())
+    }
+
+    fn delete_tag(&mut self, id: u32) -> anyhow::Result<()> {
+        // Nothing may be left pointing to the tag, but the manual tags of the activities go with it
+        Self::in_savepoint(&mut self.conn, |tx| {
+            let ids = |sql: &str| -> anyhow::Result<Vec<u32>> {
+                let mut stmt = tx.prepare(sql)?;
+                let ids = stmt.query_map(named_params! { ":id" : id }, |row| row.get(0))?.collect::<rusqlite::Result<Vec<u32>>>()?;
+                Ok(ids)
+            };
+            let patterns = ids("SELECT DISTINCT tags_pattern_id FROM tags_pattern_to_tags WHERE tags_id = :id ORDER BY 1")?;
+            if !patterns.is_empty() {
+                return Err(RequestError::Conflict(format!("Tag {} is given by the tag patterns {}", id, patterns.iter().join(", "))).into());
+            }
+            let children = ids("SELECT id FROM tags WHERE parent_id = :id ORDER BY 1")?;
+            if !children.is_empty() {
+                return Err(RequestError::Conflict(format!("Tag {} is the parent of the tags {}", id, children.iter().join(", "))).into());
+            }
+
+            if tx.execute("DELETE FROM tags WHERE id = :id", named_params! { ":id" : id })? == 0 {
+                return Err(RequestError::NotFound(format!("Unknown tag {}", id)).into());
+            }
+            tx.execute("DELETE FROM activities_tags_manual WHERE tags_id = :id", named_params! { ":id" : id })?;
+            // Captured again on the next retagging
+            tx.execute("UPDATE activities_tags SET captured_tags_id = NULL WHERE captured_tags_id = :id", named_params! { ":id" : id })?;
+            Ok(())
+        })
+    }
+
+    fn insert_tag_pattern(&mut self, pattern: &str, match_kind: MatchKind, rule: Option<&Condition>, options: &RuleOptions, tag_ids: &[u32]) -> anyhow::Result<u32> {
+        compile_rule(pattern, match_kind, rule).map_err(|err| RequestError::Invalid(err.to_string()))?;
+
+        Self::in_savepoint(&mut self.conn, |tx| {
+            Self::check_tag_ids(tx, tag_ids)?;
+            // Two rules with the same pattern and conditions would be the same rule
+            let other: Option<u32> = tx.query_row(
+                "SELECT MIN(id) FROM tags_pattern WHERE tags_pattern = :p AND match_kind = :k AND rule IS :r",
+                named_params! { ":p" : pattern, ":k" : match_kind, ":r" : rule },
+                |row| row.get(0)
+            )?;
+            if let Some(other) = other {
+                return Err(RequestError::Conflict(format!("Tag pattern {} has the same pattern and conditions", other)).into());
+            }
+
+            let pattern_id: u32 = tx.query_row("SELECT COALESCE(MAX(id), 0) + 1 FROM tags_pattern", [], |row| row.get(0))?;
+            tx.execute("
+                INSERT INTO tags_pattern (id, tags_pattern, match_kind, rule, priority, stop, category)
+                VALUES (:id, :p, :k, :r, :prio, :stop, :cat)
+            ", named_params! { 
+                ":id" : pattern_id, ":p" : pattern, ":k" : match_kind, ":r" : rule, 
+                ":prio" : options.priority, ":stop" : options.stop, ":cat" : options.category 
+            })?;
+            Self::replace_tag_pattern_tags(tx, pattern_id, tag_ids)?;
+            Ok(pattern_id)
+        })
+    }
+
+    fn update_tag_pattern(&mut self, id: u32, pattern: &str, match_kind: MatchKind, rule: Option<&Condition>, options: &RuleOptions, tag_ids: &[u32]) -> anyhow::Result<()> {
+        compile_rule(pattern, match_kind, rule).map_err(|err| RequestError::Invalid(err.to_string()))?;
+
+        Self::in_savepoint(&mut self.conn, |tx| {
+            Self::check_tag_pattern(tx, id, tag_ids)?;
+            // Two rules with the same pattern and conditions would be the same rule
+            let other: Option<u32> = tx.query_row(
+                "SELECT MIN(id) FROM tags_pattern WHERE tags_pattern = :p AND match_kind = :k AND rule IS :r AND id <> :id",
+                named_params! { ":id" : id, ":p" : pattern, ":k" : match_kind, ":r" : rule },
+                |row| row.get(0)
+            )?;
+            if let Some(other) = other {
+                return Err(RequestError::Conflict(format!("Tag pattern {} has the same pattern and conditions", other)).into());
+            }
+
+            tx.execute("
+                UPDATE tags_pattern SET tags_pattern = :p, match_kind = :k, rule = :r, priority = :prio, stop = :stop, category = :cat
+                WHERE id = :id
+            ", named_params! { 
+                ":id" : id, ":p" : pattern, ":k" : match_kind, ":r" : rule, 
+                ":prio" : options.priority, ":stop" : options.stop, ":cat" : options.category 
+            })?;
+            Self::replace_tag_pattern_tags(tx, id, tag_ids)?;
+            Ok(())
+        })
+    }
+
+    fn delete_tag_pattern(&mut self, id: u32) -> anyhow::Result<()> {
+        // The activities it tagged are left to the retagging
+        Self::in_savepoint(&mut self.conn, |tx| {
+            let deleted = tx.execute("DELETE FROM tags_pattern WHERE id = :id", named_params! { ":id" : id })?;
+            if deleted == 0 {
+                return Err(RequestError::NotFound(format!("Unknown tag pattern {}", id)).into());
+            }
+            tx.execute("DELETE FROM tags_pattern_to_tags WHERE tags_pattern_id = :id", named_params! { ":id" : id })?;
+            Ok(())
+        })
+    }
+
+    fn set_tag_pattern_tags(&mut self, id: u32, tag_ids: &[u32]) -> anyhow::Result<()> {
+        Self::in_savepoint(&mut self.conn, |tx| {
+            Self::check_tag_pattern(tx, id, tag_ids)?;
+            Self::replace_tag_pattern_tags(tx, id, tag_ids)?;
+            Ok(())
+        })
+    }
+
     fn insert_activity_tags(&mut self, activity_tags: &[ActivityToTags]) -> anyhow::Result<usize> {
+        Self::in_savepoint(&mut self.conn, |tx| Self::insert_activity_tags_rows(tx, activity_tags))
+    }
+
+    fn replace_activity_tags(&mut self, activity_ids: &[u32], activity_tags: &[ActivityToTags]) -> anyhow::Result<usize> {
+        Self::in_savepoint(&mut self.conn, |tx| {
+            let mut delete = tx.prepare("DELETE FROM activities_tags WHERE activity_id = :aid")?;
+            for activity_id in activity_ids {
+                delete.execute(named_params! { ":aid" : activity_id })?;
+            }
+            Self::insert_activity_tags_rows(tx, activity_tags)
+        })
+    }
+
+    fn set_tag_overrides(&mut self, activity_id: u32, tag_overrides: &[TagOverride]) -> anyhow::Result<usize> {
         let mut result : usize = 0;
-        let tx = self.conn.transaction()?;
-        {
-            let mut stmt = tx.prepare("
-                INSERT INTO activities_tags (activity_id, tags_pattern_id) VALUES (:aid, :tpid) ON CONFLICT(activity_id, tags_pattern_id) DO NOTHING 
-            ")?;
+        Self::in_savepoint(&mut self.conn, |tx| {
+            let exists: bool = tx.query_row(
+                "SELECT COUNT(1) > 0 FROM activities WHERE rowid = :aid", 
+                named_params! { ":aid" : activity_id }, 
+                |row| row.get(0)
+            )?;
+            if !exists {
+                return Err(RequestError::NotFound(format!("Unknown activity {}", activity_id)).into());
+            }
 
-            for activity_tag in activity_tags {            
-                result += 
-                    stmt.execute(
-                        named_params! { ":aid" : activity_tag.activity_id, ":tpid" : activity_tag.tags_pattern_id}
-                    )
-                    .map_err(|err| anyhow::anyhow!(err))?;
+            // The tags given replace the previous ones
+            tx.execute("DELETE FROM activities_tags_manual WHERE activity_id = :aid", named_params! { ":aid" : activity_id })?;
+            let mut stmt = tx.prepare("INSERT INTO activities_tags_manual (activity_id, tags_id, assigned) VALUES (:aid, :tid, :as)")?;
+            for tag_override in tag_overrides {
+                let tag_id = Self::find_tag_id(tx, &tag_override.tag)?
+                    .ok_or_else(|| RequestError::NotFound(format!("Unknown tag '{}'", tag_override.tag)))?;
+                result += stmt.execute(named_params! { ":aid" : activity_id, ":tid" : tag_id, ":as" : tag_override.assigned })?;
             }
+            Ok(result)
+        })
+    }
+
+    fn delete_tag_overrides(&mut self, activity_id: u32, tag: Option<&str>) -> anyhow::Result<usize> {
+        Self::in_savepoint(&mut self.conn, |tx| {
+            let exists: bool = tx.query_row(
+                "SELECT COUNT(1) > 0 FROM activities WHERE rowid = :aid", 
+                named_params! { ":aid" : activity_id }, 
+                |row| row.get(0)
+            )?;
+            if !exists {
+                return Err(RequestError::NotFound(format!("Unknown activity {}", activity_id)).into());
+            }
+            let tag_id = match tag {
+                Some(tag) => Some(Self::find_tag_id(tx, tag)?.ok_or_else(|| RequestError::NotFound(format!("Unknown tag '{}'", tag)))?),
+                None => None,
+            };
+
+            let deleted = tx.execute("
+                DELETE FROM activities_tags_manual 
+                WHERE activity_id = :aid AND (:tid IS NULL OR tags_id = :tid)
+            ", named_params! { ":aid" : activity_id, ":tid" : tag_id })?;
+            Ok(deleted)
+        })
+    }
+
+    fn get_saved_tag_overrides(&self) -> anyhow::Result<Vec<SavedTagOverride>> {
+        let mut stmt = self.conn.prepare("
+        SELECT acc.number, a.transaction_id, a.date, a.statement, a.amount, a.occurrence, t.tag, atm.assigned
+        FROM activities_tags_manual atm
+        JOIN activities a ON a.rowid = atm.activity_id
+        JOIN accounts acc ON acc.id = a.account_id
+        JOIN tags t ON t.id = atm.tags_id
+        ")?;
+        let mut rows = stmt.query([])?;
+        let mut saved = Vec::new();
+        while let Some(row) = rows.next()? {
+            saved.push(SavedTagOverride {
+                account_number: row.get(0)?,
+                transaction_id: row.get(1)?,
+                date: row.get(2)?,
+                statement: row.get(3)?,
+                amount: row.get(4)?,
+                occurrence: row.get(5)?,
+                tag_override: TagOverride { tag: row.get(6)?, assigned: row.get(7)? },
+            });
         }
-        tx.commit()?;
-        Ok(result)
+        Ok(saved)
     }
 
-    fn get_stats_tag_per_month(&self, tags: &[String]) -> anyhow::Result<Vec<StatsAmountPerMonthByTag>> {
+    fn restore_tag_overrides(&mut self, saved: &[SavedTagOverride]) -> anyhow::Result<usize> {
+        let mut result : usize = 0;
+        Self::in_savepoint(&mut self.conn, |tx| {
+            // Same identity as when importing: the transaction id, or the content and its occurrence in the statement
+            let mut stmt = tx.prepare("
+                INSERT INTO activities_tags_manual (activity_id, tags_id, assigned)
+                SELECT a.rowid, t.id, :as 
+                FROM activities a
+                JOIN accounts acc ON acc.id = a.account_id
+                JOIN tags t ON t.tag = :t
+                WHERE acc.number = :n 
+                AND (
+                    a.transaction_id = :tid
+                    OR (:tid IS NULL AND a.transaction_id IS NULL AND a.date = :d AND a.statement = :s AND a.amount = :amt AND a.occurrence = :o)
+                )
+                ON CONFLICT(activity_id, tags_id) DO NOTHING
+            ")?;
+            for tag_override in saved {
+                result += stmt.execute(named_params! { 
+                    ":n" : tag_override.account_number, ":tid" : tag_override.transaction_id, ":d" : tag_override.date,
+                    ":s" : tag_override.statement, ":amt" : tag_override.amount, ":o" : tag_override.occurrence,
+                    ":t" : tag_override.tag_override.tag, ":as" : tag_override.tag_override.assigned
+                })?;
+            }
+            Ok(result)
+        })
+    }
+
+    fn get_stats_tag_per_month(&self, tags: &[String], account_id: Option<u32>) -> anyhow::Result<Vec<StatsAmountPerMonthByTag>> {
         let where_clause = tags
             .iter()
             .map(|_| " tag = ?")
             .join(" or ");
 
+        // An activity with all the tags (given by rules or by hand, or their sub tags) is counted once
         let sql = format!("
         SELECT ABS(SUM(a.amount)), cast(strftime('%m', a.date) as integer)
         FROM activities a
-        LEFT JOIN activities_tags at ON at.activity_id = a.rowid
-        WHERE at.tags_pattern_id in (
-            select tptt.tags_pattern_id
-            from tags_pattern_to_tags tptt
-            left join tags t on tptt.tags_id = t.id
+        WHERE a.rowid in (
+            select activity_tags.activity_id
+            from ({}) activity_tags
+            join tags t on t.id = activity_tags.tags_id
             where {}
-            group by tptt.tags_pattern_id
-            HAVING COUNT(tags_pattern_id) = {}
+            group by activity_tags.activity_id
+            HAVING COUNT(DISTINCT t.tag) = {}
         )
+        and (?{acc} IS NULL or a.account_id = ?{acc})
         group by strftime('%m-%Y', a.date)
         ORDER BY date ASC 
-        ", where_clause, tags.len()) ;
+        ", activity_tags_with_parents(), where_clause, tags.len(), acc = tags.len() + 1) ;
+
+        let mut params: Vec<Value> = tags.iter().map(|t| Value::Text(t.to_string())).collect();
+        params.push(account_id.map_or(Value::Null, |id| Value::Integer(id.into())));
 
         let mut stmt = self.conn.prepare(&sql)?;        
-        let mut rows = stmt.query(params_from_iter(tags))?;
+        let mut rows = stmt.query(params_from_iter(params))?;
         let mut stats = Vec::new();
         while let Some(row) = rows.next()? {
             stats.push(StatsAmountPerMonthByTag {
-                amount: row.get(0).map(|e| OrderedFloat(e))?,
+                amount: row.get(0)?,
                 month: row.get(1)?
             });
         }    
         Ok(stats)
     }
 
+    fn get_tagged_amounts(&self, account_id: Option<u32>, range: &DateRange) -> anyhow::Result<Vec<TaggedAmount>> {
+        let sql = format!("
+        SELECT activity_tags.activity_id, activity_tags.tags_id, a.amount, cast(strftime('%Y%m', a.date) as integer)
+        FROM ({}) activity_tags
+        JOIN activities a ON a.rowid = activity_tags.activity_id
+        WHERE (:acc IS NULL OR a.account_id = :acc)
+        AND (:from IS NULL OR a.date >= :from)
+        AND (:to IS NULL OR a.date <= :to)
+        ", ACTIVITY_TAGS);
+        let mut stmt = self.conn.prepare(&sql)?;
+        let mut rows = stmt.query(named_params! { ":acc" : account_id, ":from" : range.from, ":to" : range.to })?;
+        let mut tagged_amounts = Vec::new();
+        while let Some(row) = rows.next()? {
+            tagged_amounts.push(TaggedAmount {
+                activity_id: row.get(0)?,
+                tag_id: row.get(1)?,
+                amount: row.get(2)?,
+                month_year: row.get(3)?
+            });
+        }
+        Ok(tagged_amounts)
+    }
+
+    fn get_stats_category_per_month(&self, account_id: Option<u32>) -> anyhow::Result<Vec<StatsAmountPerMonthByCategory>> {
+        // At most one primary rule per activity: each activity is counted once
+        let mut stmt = self.conn.prepare("
+        SELECT tp.category, SUM(a.amount), cast(strftime('%Y%m', a.date) as integer) AS month_year
+        FROM activities a
+        LEFT JOIN activities_tags at ON at.activity_id = a.rowid AND at.is_primary = 1
+        LEFT JOIN tags_pattern tp ON tp.id = at.tags_pattern_id
+        WHERE (:acc IS NULL OR a.account_id = :acc)
+        GROUP BY month_year, tp.category
+        ORDER BY month_year ASC, tp.category ASC
+        ")?;
+        let mut rows = stmt.query(named_params! { ":acc" : account_id })?;
+        let mut stats = Vec::new();
+        while let Some(row) = rows.next()? {
+            stats.push(StatsAmountPerMonthByCategory {
+                category: row.get(0)?,
+                amount: row.get(1)?,
+                month_year: row.get(2)?
+            });
+        }
+        Ok(stats)
+    }
+
     fn get_stats_detailed_amount_per_month(&self, tags: &[String]) -> anyhow::Result<Vec<StatsDetailedAmountPerMonthByTag>> {
         let inner_where_clause = tags
             .iter()
@@ -255,18 +1000,16 @@ impl DBActions for SqliteDB {
         let sql = format!("
        SELECT t.tag, ABS(a.amount), cast(strftime('%m', a.date) as integer), cast(strftime('%Y%m', a.date) as integer)
        FROM activities a
-       LEFT JOIN activities_tags at ON at.activity_id = a.rowid
-       LEFT JOIN tags_pattern_to_tags tptt ON tptt.tags_pattern_id = at.tags_pattern_id
-       LEFT JOIN tags t ON tptt.tags_id = t.id
-       WHERE at.tags_pattern_id in (
-           select distinct(tptt.tags_pattern_id)
-           from tags_pattern_to_tags tptt
-           left join tags t on tptt.tags_id = t.id
+       JOIN ({tags}) activity_tags ON activity_tags.activity_id = a.rowid
+       JOIN tags t ON activity_tags.tags_id = t.id
+       WHERE a.rowid in (
+           select activity_tags.activity_id
+           from ({tags}) activity_tags
+           join tags t on activity_tags.tags_id = t.id
            where {}
-           group by tptt.tags_pattern_id
        ) and {}
        ORDER BY date ASC 
-        ", inner_where_clause, where_clause) ;
+        ", inner_where_clause, where_clause, tags = ACTIVITY_TAGS) ;
 
         let mut stmt = self.conn.prepare(&sql)?;
         let tags = [tags,tags].concat();
@@ -275,7 +1018,7 @@ impl DBActions for SqliteDB {
         while let Some(row) = rows.next()? {
             stats.push(StatsDetailedAmountPerMonthByTag {
                 tag: row.get(0)?,
-                amount: row.get(1).map(|e| OrderedFloat(e))?,
+                amount: row.get(1)?,
                 month: row.get(2)?,
                 month_year: row.get(3)?
             });
@@ -288,11 +1031,17 @@ impl DBActions for SqliteDB {
 
 
 #[cfg(test)]
-mod tests {
+pub(crate) mod tests {
 
-    use crate::{db::{DBActions, DBConfig, sqlite::SqliteDB}, models::{AccountActivity, AccountBalance, tagging::TagsPattern}};
-    use ordered_float::OrderedFloat;
+    use crate::{db::{DBActions, DBConfig, sqlite::SqliteDB}, models::{Account, AccountActivity, AccountBalance, ActivityDetails, DateRange, tagging::{Condition, MatchKind, RuleOptions, Sign, Tag, TagsPattern}}};
+    use itertools::Itertools;
+    use crate::money::Money;
     use chrono::NaiveDate;
+    use std::sync::{Arc, Mutex};
+    use crate::actions::csv2db::csv2db;
+    use crate::actions::tagging::tagging;
+    use crate::db::ArcMutDB;
+    use crate::models::import::ImportOptions;
 
     fn create_db() -> anyhow::Result<SqliteDB> {
         Ok(
@@ -301,6 +1050,35 @@ mod tests {
         )
     }
 
+    /**
+     * A database in memory with the statements of `./data/` imported and tagged, shared by the tests of the actions
+     */
+    pub(crate) fn tagged_test_db() -> anyhow::Result<ArcMutDB<SqliteDB>> {
+        let arc_db = Arc::new(Mutex::new(create_db()?));
+        csv2db("./data/", arc_db.clone(), &ImportOptions::default())?;
+        tagging(arc_db.clone())?;
+        Ok(arc_db)
+    }
+
+    #[test]
+    fn test_initialized() -> anyhow::Result<()> {
+
+        let db = create_db()?;
+        assert!(!db.is_initialized()?, "A new database has no tables");
+        db.create_table()?;
+        assert!(db.is_initialized()?, "Tables not created");
+
+        db.check_schema()?;
+
+        let db = create_db()?;
+        db.check_schema()?;
+        db.connection().execute("CREATE TABLE activities (statement TEXT)", [])?;
+        assert!(db.is_initialized().is_err(), "A database without the import ledger should be rebuilt");
+        assert!(db.check_schema().is_err(), "A database of an older version should be rebuilt");
+
+        Ok(())
+    }
+
     #[test]
     fn test_balance() -> anyhow::Result<()> {
 
@@ -309,20 +1087,21 @@ mod tests {
 
         let balance = AccountBalance {
             row_id: None,
-            balance_euro: OrderedFloat(132.23),
+            account_id: Some(1),
+            balance_euro: Money::from_cents(13223),
             date : NaiveDate::from_ymd(2021, 11, 12)
         };
         db.insert_balance(balance)?;
 
            
-        let test_balance: f64 = db.conn
+        let test_balance: i64 = db.conn
         .query_row(
             "SELECT amount FROM balance", 
             [],
             |row| row.get(0)
         )?;
 
-        assert_eq!(test_balance, 132.23, "Wrong amount found in balance");
+        assert_eq!(test_balance, 13223, "Amounts should be stored in cents");
 
         db.close_cnx()?;
 
@@ -336,33 +1115,49 @@ mod tests {
         db.create_table()?;
 
 
-        let mut activities = vec!();
-        activities.push(AccountActivity {
-            row_id: None,
-            date: NaiveDate::from_ymd(2021, 11, 01),
-            statement: "I BOUGHT THIS".to_string(),
-            amount: OrderedFloat(102.32),
-            tag_pattern_id: None
-        });
-        activities.push(AccountActivity {
-            row_id: None,
-            date: NaiveDate::from_ymd(2021, 11, 02),
-            statement: "I BOUGHT THAT with 'VIREMENT'".to_string(),
-            amount: OrderedFloat(15.68),
-            tag_pattern_id: None
-        });
-        activities.push(AccountActivity {
-            row_id: None,
-            date: NaiveDate::from_ymd(2021, 11, 02),
-            statement: "I BOUGHT THAT with 'VIREMENT'".to_string(),
-            amount: OrderedFloat(15.68),
-            tag_pattern_id: None
-        });
+        let activities = vec!(
+            AccountActivity {
+                row_id: None,
+                account_id: Some(1),
+                date: NaiveDate::from_ymd(2021, 11, 1),
+                statement: "I BOUGHT THIS".to_string(),
+                amount: Money::from_cents(10232),
+                tag_pattern_id: None,
+                tag_pattern_ids: Vec::new(),
+                tag_overrides: Vec::new(),
+                transaction_id: None,
+                details: ActivityDetails::default()
+            },
+            AccountActivity {
+                row_id: None,
+                account_id: Some(1),
+                date: NaiveDate::from_ymd(2021, 11, 2),
+                statement: "I BOUGHT THAT with 'VIREMENT'".to_string(),
+                amount: Money::from_cents(1568),
+                tag_pattern_id: None,
+                tag_pattern_ids: Vec::new(),
+                tag_overrides: Vec::new(),
+                transaction_id: None,
+                details: ActivityDetails::default()
+            },
+            AccountActivity {
+                row_id: None,
+                account_id: Some(1),
+                date: NaiveDate::from_ymd(2021, 11, 2),
+                statement: "I BOUGHT THAT with 'VIREMENT'".to_string(),
+                amount: Money::from_cents(1568),
+                tag_pattern_id: None,
+                tag_pattern_ids: Vec::new(),
+                tag_overrides: Vec::new(),
+                transaction_id: None,
+                details: ActivityDetails::default()
+            }
+        );
 
         db.insert_activities(&activities)?;
         
         
-        let test_activity: f64 = db.conn
+        let test_activity: i64 = db.conn
             .query_row(
                 "SELECT amount FROM activities WHERE statement LIKE ?1 ", 
                 [ "%I BOUGHT THAT%" ],
@@ -376,14 +1171,133 @@ mod tests {
                 |row| row.get(0)
             )?;
 
-        assert_eq!(test_activity, 15.68, "Wrong amount found in activities");
-        assert_eq!(test_activity_count, 2, "Wrong number of activities (count)");
+        assert_eq!(test_activity, 1568, "Amounts should be stored in cents");
+        assert_eq!(test_activity_count, 3, "Identical activities of a statement should all be kept");
         
         db.close_cnx()?;
 
         Ok(())
     }
 
+    #[test]
+    fn test_activity_with_transaction_id() -> anyhow::Result<()> {
+
+        let mut db = create_db()?;
+        db.create_table()?;
+
+        let coffee = |transaction_id: &str| AccountActivity {
+            row_id: None,
+            account_id: Some(1),
+            date: NaiveDate::from_ymd(2021, 11, 2),
+            statement: "CARTE X1234 CAFE".to_string(),
+            amount: Money::from_cents(-250),
+            tag_pattern_id: None,
+            tag_pattern_ids: Vec::new(),
+            tag_overrides: Vec::new(),
+            transaction_id: Some(transaction_id.to_string()),
+            details: ActivityDetails::default()
+        };
+
+        let inserted = db.insert_activities(&[coffee("0001"), coffee("0002")])?;
+        assert_eq!(inserted.len(), 2, "Identical activities with distinct transaction ids should be kept");
+
+        let inserted = db.insert_activities(&[coffee("0002"), coffee("0003")])?;
+        assert_eq!(inserted.len(), 1, "An already imported transaction id should be skipped");
+
+        db.close_cnx()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_identical_activities_of_overlapping_statements() -> anyhow::Result<()> {
+
+        let mut db = create_db()?;
+        db.create_table()?;
+
+        let activity = |day: u32, statement: &str| AccountActivity {
+            row_id: None,
+            account_id: Some(1),
+            date: NaiveDate::from_ymd(2021, 11, day),
+            statement: statement.to_string(),
+            amount: Money::from_cents(-250),
+            tag_pattern_id: None,
+            tag_pattern_ids: Vec::new(),
+            tag_overrides: Vec::new(),
+            transaction_id: None,
+            details: ActivityDetails::default()
+        };
+
+        // 2 coffees on the 2nd in the first statement
+        let inserted = db.insert_activities(&[activity(1, "BAKERY"), activity(2, "CAFE"), activity(2, "CAFE")])?;
+        assert_eq!(inserted.len(), 3, "Both coffees should be kept");
+
+        // The next statement overlaps the 2nd and has a 3rd coffee
+        let inserted = db.insert_activities(&[activity(2, "CAFE"), activity(2, "CAFE"), activity(2, "CAFE"), activity(3, "CAFE")])?;
+        assert_eq!(inserted.len(), 2, "Only the 3rd coffee of the 2nd and the coffee of the 3rd are new");
+
+        // A statement only covering part of the 2nd
+        let inserted = db.insert_activities(&[activity(2, "CAFE")])?;
+        assert!(inserted.is_empty(), "An overlapping statement should not double count");
+
+        let count: usize = db.conn.query_row("SELECT COUNT(*) FROM activities", [], |row| row.get(0))?;
+        assert_eq!(count, 5, "Wrong number of activities (count)");
+
+        db.close_cnx()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_accounts() -> anyhow::Result<()> {
+
+        let db = create_db()?;
+        db.create_table()?;
+
+        let checking = db.insert_account(&Account { row_id: None, number: "123456789".to_string(), account_type: "CCP".to_string() })?;
+        let savings = db.insert_account(&Account { row_id: None, number: "987654321".to_string(), account_type: "Livret A".to_string() })?;
+        let checking_again = db.insert_account(&Account { row_id: None, number: "123456789".to_string(), account_type: "CCP".to_string() })?;
+
+        assert_ne!(checking, savings, "Each account should get its own id");
+        assert_eq!(checking, checking_again, "An existing account should keep its id");
+        assert_eq!(db.get_accounts()?.len(), 2, "Wrong number of accounts");
+
+        for (account_id, day, amount) in [(checking, 1, 10000), (checking, 15, 15000), (savings, 10, 200000)] {
+            db.insert_balance(AccountBalance {
+                row_id: None,
+                account_id: Some(account_id),
+                date: NaiveDate::from_ymd(2021, 11, day),
+                balance_euro: Money::from_cents(amount)
+            })?;
+        }
+
+        let balances = db.get_balances(None)?;
+        assert_eq!(balances.len(), 2, "Expected one latest balance per account");
+
+        let checking_balance = db.get_balances(Some(checking))?;
+        assert_eq!(checking_balance.len(), 1, "Wrong number of balances for the account");
+        assert_eq!(checking_balance[0].balance_euro, Money::from_cents(15000), "Wrong latest balance for the account");
+
+        let history = db.get_balance_history(None, &DateRange::default())?;
+        assert_eq!(history.len(), 3, "Every balance should be kept");
+        assert_eq!(history[0].date, NaiveDate::from_ymd(2021, 11, 1), "History should be sorted by date");
+
+        let range = DateRange { from: Some(NaiveDate::from_ymd(2021, 11, 5)), to: Some(NaiveDate::from_ymd(2021, 11, 12)) };
+        let history = db.get_balance_history(None, &range)?;
+        assert_eq!(history.len(), 1, "Wrong number of balances in the range");
+        assert_eq!(history[0].account_id, Some(savings), "Wrong balance in the range");
+
+        // The same statement date imported again keeps a single balance
+        db.insert_balance(AccountBalance { row_id: None, account_id: Some(checking), date: NaiveDate::from_ymd(2021, 11, 15), balance_euro: Money::from_cents(17500) })?;
+        let checking_history = db.get_balance_history(Some(checking), &DateRange::default())?;
+        assert_eq!(checking_history.len(), 2, "One balance per account and date expected");
+        assert_eq!(checking_history[1].balance_euro, Money::from_cents(17500), "The balance imported last should be kept");
+
+        db.close_cnx()?;
+
+        Ok(())
+    }
+
     #[test]
     fn test_tags_pattern() -> anyhow::Result<()> {
 
@@ -393,7 +1307,7 @@ mod tests {
         let tags_pattern = db.get_tag_patterns()?;
 
         assert!(
-            tags_pattern.contains(&TagsPattern { id : 5, pattern : "FREE MOBILE".to_string(), tag: "FREEMOBILE".to_string()}), 
+            tags_pattern.contains(&TagsPattern { id : 5, pattern : "FREE MOBILE".to_string(), match_kind: MatchKind::Substring, rule: None, options: RuleOptions { priority: 0, stop: false, category: Some("TELEPHONE".to_string()) }, tag: "FREEMOBILE".to_string()}), 
             "Tag Pattern not found"
         );
 
@@ -403,5 +1317,115 @@ mod tests {
 
     }
 
+    #[test]
+    fn test_insert_tag_pattern() -> anyhow::Result<()> {
+
+        let mut db = create_db()?;
+        db.create_table()?;
+
+        let err = db.insert_tag_pattern("^PRLV SEPA (FREE", MatchKind::Regex, None, &RuleOptions::default(), &[2]).unwrap_err();
+        assert!(err.to_string().starts_with("Invalid regex '^PRLV SEPA (FREE'"), "Invalid regex should be reported, got {}", err);
+
+        let id = db.insert_tag_pattern("^PRLV SEPA FREE MOBILE", MatchKind::Regex, None, &RuleOptions::default(), &[2])?;
+        let same_text = db.insert_tag_pattern("FREE MOBILE", MatchKind::Prefix, None, &RuleOptions::default(), &[2])?;
+        assert_ne!(same_text, 5, "The same text with another match kind is another pattern");
+
+        let tags_pattern = db.get_tag_patterns()?;
+        assert!(
+            tags_pattern.contains(&TagsPattern { id, pattern : "^PRLV SEPA FREE MOBILE".to_string(), match_kind: MatchKind::Regex, rule: None, options: RuleOptions::default(), tag: "FREEMOBILE".to_string()}), 
+            "Regex pattern not saved"
+        );
+
+        let rent = Condition::And(vec![Condition::Sign(Sign::Debit), Condition::DayOfMonth { from: 1, to: 5 }]);
+        let rule_id = db.insert_tag_pattern("", MatchKind::Substring, Some(&rent), &RuleOptions::default(), &[3])?;
+        let err = db.insert_tag_pattern("", MatchKind::Substring, Some(&rent), &RuleOptions { priority: 10, ..RuleOptions::default() }, &[4]).unwrap_err();
+        assert_eq!(err.to_string(), format!("Tag pattern {} has the same pattern and conditions", rule_id), "The same rule should not be inserted again");
+        assert!(db.connection().is_autocommit(), "The transaction of a failed insert should be ended");
+        assert_eq!(db.insert_tag("LOYER")?, 3, "The same tag should keep its id");
+        let count = |table: &str| -> rusqlite::Result<usize> { db.connection().query_row(&format!("SELECT COUNT(1) FROM {}", table), [], |row| row.get(0)) };
+        assert_eq!((count("tags")?, count("tags_pattern")?), (8, 9), "Existing tags and patterns should not be inserted again");
+        assert!(db.insert_tag_pattern("", MatchKind::Substring, None, &RuleOptions::default(), &[3]).is_err(), "A rule needs a pattern or conditions");
+        assert!(
+            db.insert_tag_pattern("", MatchKind::Substring, Some(&Condition::DayOfMonth { from: 5, to: 1 }), &RuleOptions::default(), &[3]).is_err(), 
+            "Invalid conditions should be reported"
+        );
+        let saved = db.get_tag_patterns()?;
+        assert_eq!(saved.iter().filter(|p| p.id == rule_id && p.rule.as_ref() == Some(&rent)).count(), 1, "Rule not read back");
+
+        db.close_cnx()?;
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_edit_tags() -> anyhow::Result<()> {
+
+        let mut db = create_db()?;
+        db.create_table()?;
+
+        let groceries = db.create_tag(" COURSES ", Some(7))?;
+        assert!(db.get_tags()?.contains(&Tag { id: groceries, tag: "COURSES".to_string(), parent_id: Some(7) }), "Tag not created");
+        assert!(db.create_tag("loyer", None).is_err(), "Tag names should be unique whatever the case");
+        assert!(db.create_tag("LOGEMENT/COURSES", None).is_err(), "A tag can not contain the path separator");
+        assert!(db.create_tag("SANTE", Some(100)).is_err(), "Unknown parent should fail");
+
+        let err = db.update_tag(7, "LOGEMENT", Some(1)).unwrap_err();
+        assert_eq!(err.to_string(), "Tag 'LOGEMENT' can not be a sub tag of itself", "A tag can not be under its sub tags");
+        assert!(db.update_tag(7, "LOGEMENT", Some(7)).is_err(), "A tag can not be its own parent");
+        assert!(db.update_tag(groceries, "EDF", None).is_err(), "A tag can not be renamed as another one");
+        assert!(db.update_tag(100, "SANTE", None).is_err(), "Unknown tag should fail");
+        db.update_tag(groceries, "ALIMENTATION", None)?;
+        assert!(db.get_tags()?.contains(&Tag { id: groceries, tag: "ALIMENTATION".to_string(), parent_id: None }), "Tag not updated");
+
+        assert_eq!(db.delete_tag(3).unwrap_err().to_string(), "Tag 3 is given by the tag patterns 4, 6", "A tag given by a rule should be kept");
+        assert_eq!(db.delete_tag(8).unwrap_err().to_string(), "Tag 8 is the parent of the tags 1", "A tag with sub tags should be kept");
+        db.delete_tag(groceries)?;
+        assert!(db.delete_tag(groceries).is_err(), "Tag not deleted");
+
+        assert!(db.insert_tag_pattern("SNCF", MatchKind::Word, None, &RuleOptions::default(), &[100]).is_err(), "A rule can not give an unknown tag");
+        assert!(db.set_tag_pattern_tags(5, &[]).is_err(), "A rule needs at least one tag");
+        assert!(db.set_tag_pattern_tags(100, &[2]).is_err(), "Unknown tag pattern should fail");
+        assert!(db.connection().is_autocommit(), "The transaction of a failed change should be ended");
+        assert!(db.set_tag_pattern_tags(5, &[2, 100]).is_err(), "A rule can not give an unknown tag");
+        let tags_of = |db: &SqliteDB, id: u32| -> anyhow::Result<Vec<String>> { 
+            Ok(db.get_tag_patterns()?.into_iter().filter(|p| p.id == id).map(|p| p.tag).sorted().collect()) 
+        };
+        assert_eq!(tags_of(&db, 5)?, vec!["FREEMOBILE", "PARIS"], "Tags of a rule changed by a failed update");
+        db.set_tag_pattern_tags(5, &[2, 2])?;
+        assert_eq!(tags_of(&db, 5)?, vec!["FREEMOBILE"], "Tags of the rule not replaced");
+
+        let pattern_of = |db: &SqliteDB, id: u32| -> anyhow::Result<Vec<(String, MatchKind)>> { 
+            Ok(db.get_tag_patterns()?.into_iter().filter(|p| p.id == id).map(|p| (p.pattern, p.match_kind)).unique().collect()) 
+        };
+        assert!(
+            db.update_tag_pattern(5, "FREE MOBILE", MatchKind::Word, None, &RuleOptions::default(), &[100]).is_err(), 
+            "A rule can not be changed to give an unknown tag"
+        );
+        assert!(
+            db.update_tag_pattern(5, "FREE MOBILE", MatchKind::Word, None, &RuleOptions::default(), &[]).is_err(), 
+            "A rule can not be changed to give no tag"
+        );
+        assert_eq!(
+            db.update_tag_pattern(5, "EDF", MatchKind::Word, None, &RuleOptions::default(), &[1]).unwrap_err().to_string(),
+            "Tag pattern 1 has the same pattern and conditions",
+            "A rule can not be changed into another one"
+        );
+        assert_eq!(pattern_of(&db, 5)?, vec![("FREE MOBILE".to_string(), MatchKind::Substring)], "Rule changed by a failed update");
+        assert_eq!(tags_of(&db, 5)?, vec!["FREEMOBILE"], "Tags of a rule changed by a failed update");
+        db.update_tag_pattern(5, "FREE MOBILE", MatchKind::Word, None, &RuleOptions::default(), &[2, 4])?;
+        assert_eq!(pattern_of(&db, 5)?, vec![("FREE MOBILE".to_string(), MatchKind::Word)], "Rule not updated");
+        assert_eq!(tags_of(&db, 5)?, vec!["FREEMOBILE", "PARIS"], "Tags of the rule not updated");
+
+        db.delete_tag_pattern(5)?;
+        assert!(pattern_of(&db, 5)?.is_empty(), "Rule not deleted");
+        let mappings: u32 = db.connection().query_row("SELECT COUNT(1) FROM tags_pattern_to_tags WHERE tags_pattern_id = 5", [], |row| row.get(0))?;
+        assert_eq!(mappings, 0, "Tags of the rule not deleted");
+        assert!(db.delete_tag_pattern(5).is_err(), "Unknown tag pattern should fail");
+
+        db.close_cnx()?;
+
+        Ok(())
+    }
+
   
 }
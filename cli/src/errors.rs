@@ -1,9 +1,76 @@
-
+use std::fmt;
+use warp::http::StatusCode;
 use warp::reject::Reject;
 
 #[derive(Debug)]
+#[allow(clippy::enum_variant_names)]
 pub enum Errors {
-    DBError(anyhow::Error)
+    DBError(anyhow::Error),
+    UploadError(anyhow::Error),
+    ImportError(anyhow::Error),
 }
 
 impl Reject for Errors {}
+
+/**
+ * An error caused by the request and not by the server, ie. an unknown id or an invalid rule.
+ * It is served with a client error status instead of a 500.
+ */
+#[derive(Debug)]
+pub enum RequestError {
+    /**
+     * The data sent is invalid, ie. an empty tag or an invalid regex
+     */
+    Invalid(String),
+    /**
+     * The item of the request does not exist
+     */
+    NotFound(String),
+    /**
+     * The data sent clashes with the stored data, ie. a tag name already taken or a tag still in use
+     */
+    Conflict(String),
+}
+
+impl RequestError {
+    pub fn status(&self) -> StatusCode {
+        match self {
+            RequestError::Invalid(_) => StatusCode::BAD_REQUEST,
+            RequestError::NotFound(_) => StatusCode::NOT_FOUND,
+            RequestError::Conflict(_) => StatusCode::CONFLICT,
+        }
+    }
+}
+
+impl fmt::Display for RequestError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RequestError::Invalid(message) | RequestError::NotFound(message) | RequestError::Conflict(message) => write!(f, "{}", message),
+        }
+    }
+}
+
+impl std::error::Error for RequestError {}
+
+impl Errors {
+    /**
+     * A `RequestError` anywhere in the chain of the error gives its status, any other error is a 500
+     * (but an upload that can not be read is a bad request)
+     */
+    pub fn status(&self) -> StatusCode {
+        match self {
+            Errors::UploadError(_) => StatusCode::BAD_REQUEST,
+            Errors::DBError(err) | Errors::ImportError(err) => err
+                .chain()
+                .find_map(|e| e.downcast_ref::<RequestError>())
+                .map(RequestError::status)
+                .unwrap_or(StatusCode::INTERNAL_SERVER_ERROR),
+        }
+    }
+
+    pub fn message(&self) -> String {
+        match self {
+            Errors::DBError(err) | Errors::UploadError(err) | Errors::ImportError(err) => format!("{:#}", err),
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use chrono::{Datelike, NaiveDate};
+
+use crate::models::{ActivityDetails, OperationType};
+
+/**
+ * What a La Banque Postale libellé tells beyond its text, ie. "CARTE X1234 12/03 MONOPRIX PARIS"
+ */
+#[derive(Debug, Default, PartialEq)]
+pub struct Label {
+    pub operation_type: Option<OperationType>,
+    pub card_number: Option<String>,
+    pub operation_date: Option<NaiveDate>,
+    pub merchant: Option<String>,
+    pub city: Option<String>,
+}
+
+/**
+ * Operations are dated without year: they are in the year before the given date, ie. 30/12 for a statement closed on 27/01
+ */
+pub fn operation_date(day: u32, month: u32, before: NaiveDate) -> Option<NaiveDate> {
+    let year = if (month, day) > (before.month(), before.day()) { before.year() - 1 } else { before.year() };
+    NaiveDate::from_ymd_opt(year, month, day)
+}
+
+/**
+ * Words starting the libellé of each kind of operation, the longest first
+ */
+const PREFIXES: [(&str, OperationType); 15] = [
+    ("CARTE", OperationType::Card),
+    ("ACHAT CB", OperationType::Card),
+    ("RETRAIT DAB", OperationType::Withdrawal),
+    ("RETRAIT", OperationType::Withdrawal),
+    ("PRLV SEPA", OperationType::DirectDebit),
+    ("PRELEVEMENT", OperationType::DirectDebit),
+    ("VIREMENT PERMANENT", OperationType::Transfer),
+    ("VIREMENT", OperationType::Transfer),
+    ("VIR SEPA", OperationType::Transfer),
+    ("CHEQUE", OperationType::Cheque),
+    ("CHQ", OperationType::Cheque),
+    ("FRAIS", OperationType::Fee),
+    ("COTISATION", OperationType::Fee),
+    ("COMMISSION", OperationType::Fee),
+    ("AGIOS", OperationType::Fee),
+];
+
+/**
+ * Words between the kind of a transfer and the name of the other party
+ */
+const TRANSFER_WORDS: [&str; 6] = ["DE", "POUR", "RECU", "EMIS", "INSTANTANE", "SEPA"];
+
+/**
+ * Masked card number, ie. "X1234" or "4974XXXXXXXX1234"
+ */
+fn is_card_number(word: &str) -> bool {
+    word.len() >= 4
+        && word.chars().any(|c| c.is_ascii_digit())
+        && word.chars().any(|c| c == 'X' || c == '*')
+        && word.chars().all(|c| c.is_ascii_digit() || c == 'X' || c == '*')
+}
+
+/**
+ * "12/03", "12.03" or "12/03/21": the operation date of a card payment or a withdrawal
+ */
+fn parse_day_month(word: &str, booking_date: NaiveDate) -> Option<NaiveDate> {
+    let parts: Vec<&str> = word.split(['/', '.']).collect();
+    if parts.iter().any(|p| p.len() != 2 || !p.chars().all(|c| c.is_ascii_digit())) {
+        return None;
+    }
+    match parts.as_slice() {
+        [day, month] => operation_date(day.parse().ok()?, month.parse().ok()?, booking_date),
+        [day, month, year] => NaiveDate::from_ymd_opt(2000 + year.parse::<i32>().ok()?, month.parse().ok()?, day.parse().ok()?),
+        _ => None,
+    }
+}
+
+/**
+ * First word of a text, with the text after it
+ */
+fn split_word(text: &str) -> (&str, &str) {
+    match text.split_once(' ') {
+        Some((word, tail)) => (word, tail.trim_start()),
+        None => (text, ""),
+    }
+}
+
+/**
+ * Where the name of a direct debit or a transfer stops: mandate, reference or any long number
+ */
+fn is_reference(word: &str) -> bool {
+    word.contains(['/', ':'])
+        || matches!(word, "REF" | "ECH" | "MDT" | "MOTIF" | "LIBELLE" | "NUM")
+        || (word.len() >= 6 && word.chars().filter(|c| c.is_ascii_digit()).count() >= 4)
+}
+
+/**
+ * The place of a card payment or a withdrawal: its last word is the city, or the last 2 for "PARIS 15".
+ * Fixed width libellés separate the city by a wide gap instead.
+ */
+fn split_merchant_city(text: &str) -> (Option<String>, Option<String>) {
+    let join = |words: &[&str]| Some(words.join(" ")).filter(|s| !s.is_empty());
+
+    let segments: Vec<&str> = text.split("  ").map(str::trim).filter(|s| !s.is_empty()).collect();
+    if let [merchant @ .., city] = segments.as_slice() {
+        if !merchant.is_empty() {
+            return (join(merchant), Some(city.to_string()));
+        }
+    }
+
+    let words: Vec<&str> = text.split_whitespace().collect();
+    let city_words = match words.as_slice() {
+        [.., city, district] if words.len() > 2 && district.len() <= 2 && district.chars().all(|c| c.is_ascii_digit()) && city.chars().all(char::is_alphabetic) => 2,
+        [_, _, ..] => 1,
+        _ => 0,
+    };
+    let (merchant, city) = words.split_at(words.len() - city_words);
+    (join(merchant), join(city))
+}
+
+/**
+ * Parse a libellé booked on `booking_date`. Libellés of other banks, or of unknown operations, give an empty label.
+ */
+pub fn parse_label(statement: &str, booking_date: NaiveDate) -> Label {
+    let upper = statement.trim().to_uppercase();
+    let (prefix, operation_type) = match PREFIXES.iter().find(|(prefix, _)| {
+        upper.strip_prefix(prefix).is_some_and(|rest| rest.is_empty() || rest.starts_with(' '))
+    }) {
+        Some((prefix, operation_type)) => (prefix, *operation_type),
+        None => return Label::default(),
+    };
+    let rest = upper[prefix.len()..].trim_start();
+    let mut label = Label { operation_type: Some(operation_type), ..Label::default() };
+
+    match operation_type {
+        OperationType::Card | OperationType::Withdrawal => {
+            let mut rest = rest;
+            // CARTE X1234 [RETRAIT DAB] 12/03 ..., ACHAT CB MONOPRIX 12.03.21 CARTE NUMERO 123
+            loop {
+                let (word, tail) = split_word(rest);
+                if word.is_empty() {
+                    break;
+                } else if label.card_number.is_none() && is_card_number(word) {
+                    label.card_number = Some(word.to_string());
+                } else if label.operation_date.is_none() && parse_day_month(word, booking_date).is_some() {
+                    label.operation_date = parse_day_month(word, booking_date);
+                } else if (word == "RETRAIT" || word == "DAB") && label.operation_date.is_none() {
+                    label.operation_type = Some(OperationType::Withdrawal);
+                } else {
+                    break;
+                }
+                rest = tail;
+            }
+            // The hour of a withdrawal, ie. "14H32"
+            let (word, tail) = split_word(rest);
+            if word.contains('H') && word.chars().filter(|c| c.is_ascii_digit()).count() >= 3 {
+                rest = tail;
+            }
+            let place = match rest.find(" CARTE NUMERO") {
+                Some(end) => rest[..end].trim(),
+                None => rest,
+            };
+            if *prefix == "ACHAT CB" {
+                // The date comes after the merchant
+                let words: Vec<&str> = place.split_whitespace().collect();
+                if let [merchant @ .., date] = words.as_slice() {
+                    if let Some(date) = parse_day_month(date, booking_date) {
+                        label.operation_date = Some(date);
+                        label.merchant = Some(merchant.join(" ")).filter(|s| !s.is_empty());
+                        return label;
+                    }
+                }
+            }
+            let (merchant, city) = split_merchant_city(place);
+            label.merchant = merchant;
+            label.city = city;
+        }
+        OperationType::DirectDebit | OperationType::Transfer => {
+            let name: Vec<&str> = rest
+                .split_whitespace()
+                .skip_while(|w| TRANSFER_WORDS.contains(w))
+                .take_while(|w| !is_reference(w))
+                .collect();
+            label.merchant = Some(name.join(" ")).filter(|s| !s.is_empty());
+        }
+        OperationType::Cheque | OperationType::Fee => (),
+    }
+    label
+}
+
+/**
+ * Complete the details of an activity with what its libellé tells, keeping the details given by the statement
+ */
+pub fn fill_details(statement: &str, booking_date: NaiveDate, details: &mut ActivityDetails) {
+    let label = parse_label(statement, booking_date);
+    details.operation_type = details.operation_type.or(label.operation_type);
+    details.card_number = details.card_number.take().or(label.card_number);
+    details.operation_date = details.operation_date.or(label.operation_date);
+    details.merchant = details.merchant.take().or(label.merchant);
+    details.city = details.city.take().or(label.city);
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+    use crate::label::parsing::{Label, operation_date, parse_label};
+    use crate::models::OperationType;
+
+    fn booked(statement: &str) -> Label {
+        parse_label(statement, NaiveDate::from_ymd(2021, 3, 15))
+    }
+
+    #[test]
+    fn test_card() {
+        let label = booked("CARTE X1234 12/03 MONOPRIX PARIS");
+        assert_eq!(label, Label {
+            operation_type: Some(OperationType::Card),
+            card_number: Some("X1234".to_string()),
+            operation_date: Some(NaiveDate::from_ymd(2021, 3, 12)),
+            merchant: Some("MONOPRIX".to_string()),
+            city: Some("PARIS".to_string()),
+        }, "Wrong card label");
+
+        let district = booked("CARTE X1234 12/03 BOULANGERIE DU MARCHE PARIS 15");
+        assert_eq!(district.merchant.as_deref(), Some("BOULANGERIE DU MARCHE"), "Wrong merchant");
+        assert_eq!(district.city.as_deref(), Some("PARIS 15"), "The district should be kept with the city");
+
+        let fixed_width = booked("CARTE X1234 12/03 SNCF INTERNET     PARIS");
+        assert_eq!(fixed_width.merchant.as_deref(), Some("SNCF INTERNET"), "Wrong merchant of a fixed width libellé");
+        assert_eq!(fixed_width.city.as_deref(), Some("PARIS"), "Wrong city of a fixed width libellé");
+
+        let old = booked("ACHAT CB MONOPRIX 12.03.21 CARTE NUMERO 123");
+        assert_eq!(old.operation_type, Some(OperationType::Card), "Wrong type");
+        assert_eq!(old.operation_date, Some(NaiveDate::from_ymd(2021, 3, 12)), "Wrong date after the merchant");
+        assert_eq!(old.merchant.as_deref(), Some("MONOPRIX"), "Wrong merchant");
+    }
+
+    #[test]
+    fn test_withdrawal() {
+        let label = booked("RETRAIT DAB 11/03 14H32 LA POSTE LYON");
+        assert_eq!(label.operation_type, Some(OperationType::Withdrawal), "Wrong type");
+        assert_eq!(label.operation_date, Some(NaiveDate::from_ymd(2021, 3, 11)), "Wrong operation date");
+        assert_eq!(label.merchant.as_deref(), Some("LA POSTE"), "Wrong place");
+        assert_eq!(label.city.as_deref(), Some("LYON"), "Wrong city");
+
+        let by_card = booked("CARTE X1234 RETRAIT DAB 28/12 PARIS");
+        assert_eq!(by_card.operation_type, Some(OperationType::Withdrawal), "A withdrawal by card is a withdrawal");
+        assert_eq!(by_card.card_number.as_deref(), Some("X1234"), "Wrong card number");
+        assert_eq!(by_card.operation_date, Some(NaiveDate::from_ymd(2020, 12, 28)), "Operation date should be in the previous year");
+    }
+
+    #[test]
+    fn test_direct_debit_and_transfer() {
+        let edf = booked("PRLV SEPA EDF SA ECH/150321 MDT/123456");
+        assert_eq!(edf.operation_type, Some(OperationType::DirectDebit), "Wrong type");
+        assert_eq!(edf.merchant.as_deref(), Some("EDF SA"), "Wrong creditor");
+
+        let transfer = booked("VIREMENT DE M. DUPONT");
+        assert_eq!(transfer.operation_type, Some(OperationType::Transfer), "Wrong type");
+        assert_eq!(transfer.merchant.as_deref(), Some("M. DUPONT"), "Wrong other party");
+        assert_eq!(transfer.operation_date, None, "A transfer has no operation date");
+    }
+
+    #[test]
+    fn test_other_labels() {
+        assert_eq!(booked("CHEQUE N° 1234567").operation_type, Some(OperationType::Cheque), "Wrong cheque type");
+        assert_eq!(booked("FRAIS TENUE DE COMPTE").operation_type, Some(OperationType::Fee), "Wrong fee type");
+        assert_eq!(booked("Cotisation Formule de compte").operation_type, Some(OperationType::Fee), "Case should be ignored");
+        assert_eq!(booked("CARTESIEN SARL"), Label::default(), "Prefixes are whole words");
+        assert_eq!(booked("AGENCE IMMO Loyer avril"), Label::default(), "Unknown libellés give an empty label");
+        assert_eq!(operation_date(2, 1, NaiveDate::from_ymd(2021, 1, 27)), Some(NaiveDate::from_ymd(2021, 1, 2)), "Wrong year");
+    }
+}
@@ -1,12 +1,26 @@
 mod actions;
+mod camt;
+mod classifier;
 mod csv;
 mod db;
 mod errors;
+mod label;
 mod models;
+mod money;
+mod ofx;
+mod pdf;
+mod qif;
 
-use crate::{actions::tagging::tagging, db::{DBActions, sqlite::SqliteDB}};
-use actions::csv2db::csv2db;
+use crate::{actions::tagging::{conflicts, print_conflicts, retag, tagging, tagging_activities}, db::{DBActions, sqlite::SqliteDB}};
+use actions::csv2db::{csv2db, print_report};
 use actions::http::http_server;
+use actions::manual_tags::{restore_manual_tags, save_manual_tags};
+use actions::reconciliation::{print_reconciliation, reconcile};
+use actions::suggestions::{print_suggestions, suggestions, MIN_CONFIDENCE};
+use actions::watch::watch;
+use csv::profile::load_profiles;
+use glob::Pattern;
+use models::import::{ImportMode, ImportOptions, QifDateOrder};
 use serde::{Deserialize, Serialize};
 use std::{
     env,
@@ -20,31 +34,158 @@ struct AppConfig {
     root_www: String,
     port_www: u16,
     init_db_path: String,
+    #[serde(default)]
+    qif_date_order: QifDateOrder,
+    #[serde(default)]
+    qif_seed_tags: bool,
+    #[serde(default)]
+    import_profiles_path: Option<String>,
+    #[serde(default)]
+    import_mode: ImportMode,
+    #[serde(default)]
+    import_include: Vec<String>,
+    #[serde(default)]
+    import_exclude: Vec<String>,
+}
+
+impl AppConfig {
+    fn import_options(&self) -> anyhow::Result<ImportOptions> {
+        Ok(ImportOptions {
+            qif_date_order: self.qif_date_order.clone(),
+            qif_seed_tags: self.qif_seed_tags,
+            csv_profiles: load_profiles(self.import_profiles_path.as_deref())?,
+            mode: self.import_mode.clone(),
+            include: globs(&self.import_include)?,
+            exclude: globs(&self.import_exclude)?,
+        })
+    }
+}
+
+fn globs(patterns: &[String]) -> anyhow::Result<Vec<Pattern>> {
+    patterns
+        .iter()
+        .map(|p| Pattern::new(p).map_err(|err| anyhow::anyhow!("Invalid glob '{}' : {}", p, err)))
+        .collect()
 }
 
 #[tokio::main]
 async fn main() -> anyhow::Result<()> {
     let args: Vec<String> = env::args().collect();
     let switch = args.get(1).map(|e| e.as_str());
+    // --http and --watch together, in any order
+    let with_watch = args.iter().skip(1).any(|e| e == "--watch");
+    let with_http = args.iter().skip(1).any(|e| e == "--http");
+    // Where to write the import report as JSON (--db, --import)
+    let report_path = args.iter().skip(2).skip_while(|e| *e != "--report").nth(1).map(|e| e.as_str());
 
     let cfg: AppConfig = confy::load_path("./config.toml")?;
     let db_path = cfg.db_path.as_str();
-
-    let sqlite_db = SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() });
-
+    let import_options = cfg.import_options()?;
 
     match switch {
+        Some("--http") | Some("--watch") if with_http && with_watch => {
+            // Serve the app and ingest the statements dropped in csv_source at the same time
+            let sqlite_db = 
+                SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() })
+                .with_init_db_script(cfg.init_db_path);
+            sqlite_db.check_schema()?;
+            let arc_db =  Arc::new(Mutex::new(sqlite_db));
+            tokio::try_join!(
+                http_server(cfg.root_www, cfg.port_www, arc_db.clone(), import_options.clone()),
+                watch(cfg.csv_source, arc_db, import_options)
+            ).map(|_| ())
+        }
         Some("--http") => {
+            // The init script creates the tables if the first statements are uploaded with /api/import
+            let sqlite_db = 
+                SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() })
+                .with_init_db_script(cfg.init_db_path);
+            sqlite_db.check_schema()?;
+            let arc_db =  Arc::new(Mutex::new(sqlite_db));
+            http_server(cfg.root_www, cfg.port_www, arc_db, import_options).await
+        }
+        Some("--watch") => {
+            let sqlite_db = 
+                SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() })
+                .with_init_db_script(cfg.init_db_path);
+            sqlite_db.check_schema()?;
             let arc_db =  Arc::new(Mutex::new(sqlite_db));
-            http_server(cfg.root_www, cfg.port_www, arc_db).await
+            watch(cfg.csv_source, arc_db, import_options).await
         }
         Some("--db") => {
-            let sqlite_db = sqlite_db.with_init_db_script(cfg.init_db_path);
+            // Rebuild the whole database from the statements, keeping the tags set by hand
+            let manual_tags = save_manual_tags::<SqliteDB>(db_path)?;
+            let sqlite_db = 
+                SqliteDB::from_config(db::DBConfig::FileWithOverwrite { file_name : db_path.to_string() })
+                .with_init_db_script(cfg.init_db_path);
             sqlite_db.clean_db()?;
             
             let arc_db =  Arc::new(Mutex::new(sqlite_db));
-            csv2db(cfg.csv_source, arc_db.clone())?;
-            tagging(arc_db).map(|_| ())
+            let report = csv2db(cfg.csv_source, arc_db.clone(), &import_options)?;
+            print_report(&report, report_path)?;
+            tagging(arc_db.clone())?;
+            let restored = restore_manual_tags(arc_db, &manual_tags)?;
+            if restored < manual_tags.len() {
+                eprintln!("{} manual tags lost : their activity was not imported again", manual_tags.len() - restored);
+            }
+            Ok(())
+        }
+        Some("--import") => {
+            // Only ingest and tag the statements not imported yet
+            let sqlite_db = 
+                SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() })
+                .with_init_db_script(cfg.init_db_path);
+            sqlite_db.check_schema()?;
+
+            let arc_db =  Arc::new(Mutex::new(sqlite_db));
+            let report = csv2db(cfg.csv_source, arc_db.clone(), &import_options)?;
+            print_report(&report, report_path)?;
+            tagging_activities(arc_db, &report.activity_ids).map(|_| ())
+        }
+        Some("--reconcile") => {
+            // Check the imported data is complete
+            let sqlite_db = SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() });
+            sqlite_db.check_schema()?;
+            let arc_db =  Arc::new(Mutex::new(sqlite_db));
+            print_reconciliation(&reconcile(arc_db, None)?);
+            Ok(())
+        }
+        Some("--conflicts") => {
+            // Activities matched by rules giving different categories
+            let sqlite_db = SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() });
+            sqlite_db.check_schema()?;
+            let arc_db =  Arc::new(Mutex::new(sqlite_db));
+            print_conflicts(&conflicts(arc_db, None)?);
+            Ok(())
+        }
+        Some("--suggest") => {
+            // Tags suggested for the activities without tag, with at least the given confidence (ie. --suggest 0.8)
+            let min_confidence = match args.get(2) {
+                Some(arg) => arg.parse().map_err(|_| anyhow::anyhow!("Invalid confidence '{}'", arg))?,
+                None => MIN_CONFIDENCE,
+            };
+            let sqlite_db = SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() });
+            sqlite_db.check_schema()?;
+            let arc_db =  Arc::new(Mutex::new(sqlite_db));
+            print_suggestions(&suggestions(arc_db, None, min_confidence)?);
+            Ok(())
+        }
+        Some("--retag") => {
+            // Recompute the tags of the activities of the given rules (ie. --retag 4 6), of all activities without rules
+            let rule_ids = args
+                .iter()
+                .skip(2)
+                .map(|e| e.parse().map_err(|_| anyhow::anyhow!("Invalid tag pattern id '{}'", e)))
+                .collect::<anyhow::Result<Vec<u32>>>()?;
+            let sqlite_db = SqliteDB::from_config(db::DBConfig::File { file_name : db_path.to_string() });
+            sqlite_db.check_schema()?;
+            let arc_db =  Arc::new(Mutex::new(sqlite_db));
+            let report = retag(arc_db, &rule_ids)?;
+            for invalid_rule in &report.invalid_rules {
+                println!("Tag pattern {} skipped : {}", invalid_rule.id, invalid_rule.error);
+            }
+            println!("{} activities retagged, {} tags applied", report.activities, report.activity_tags);
+            Ok(())
         }
         Some(arg) => Err(anyhow::anyhow!(format!("Invalid argument '{}'", arg))),
         _ => Err(anyhow::anyhow!("Missing argument")),
@@ -1,57 +1,321 @@
+use std::str::FromStr;
+
 use chrono::NaiveDate;
-use ordered_float::OrderedFloat;
-use serde::Serialize;
-use std::collections::HashSet;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+use crate::money::Money;
+use self::tagging::TagOverride;
+
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Account {
+    pub row_id: Option<u32>,
+    pub number: String,
+    pub account_type: String,
+}
 
 #[derive(Hash, Eq, PartialEq, Debug, Serialize)]
 pub struct AccountActivity {
     pub row_id: Option<u32>,
+    pub account_id: Option<u32>,
     pub date: NaiveDate,
     pub statement: String,
-    pub amount: OrderedFloat<f32>,
-    pub tag_pattern_id: Option<u8>,
+    pub amount: Money,
+    /**
+     * The rule giving the primary category of the activity
+     */
+    pub tag_pattern_id: Option<u32>,
+    /**
+     * All the rules tagging the activity
+     */
+    pub tag_pattern_ids: Vec<u32>,
+    /**
+     * The tags given or removed by hand, on top of the rules
+     */
+    pub tag_overrides: Vec<TagOverride>,
+    pub transaction_id: Option<String>,
+    #[serde(flatten)]
+    pub details: ActivityDetails,
+}
+
+/**
+ * Optional information only some statement formats carry
+ */
+#[derive(Default, Hash, Eq, PartialEq, Debug, Serialize)]
+pub struct ActivityDetails {
+    pub category: Option<String>,
+    pub counterparty: Option<String>,
+    pub end_to_end_id: Option<String>,
+    pub value_date: Option<NaiveDate>,
+    /**
+     * Read from the La Banque Postale libellé, see `label::parsing`
+     */
+    pub operation_type: Option<OperationType>,
+    pub card_number: Option<String>,
+    /**
+     * Date of a card payment or a withdrawal, the activity being booked a few days later
+     */
+    pub operation_date: Option<NaiveDate>,
+    pub merchant: Option<String>,
+    pub city: Option<String>,
+}
+
+/**
+ * Kind of operation, stored and served as its snake case name (ie. "direct_debit")
+ */
+#[derive(Clone, Copy, Hash, Eq, PartialEq, Debug, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OperationType {
+    Card,
+    DirectDebit,
+    Transfer,
+    Withdrawal,
+    Cheque,
+    Fee,
+}
+
+impl OperationType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            OperationType::Card => "card",
+            OperationType::DirectDebit => "direct_debit",
+            OperationType::Transfer => "transfer",
+            OperationType::Withdrawal => "withdrawal",
+            OperationType::Cheque => "cheque",
+            OperationType::Fee => "fee",
+        }
+    }
+}
+
+impl FromStr for OperationType {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        [OperationType::Card, OperationType::DirectDebit, OperationType::Transfer, OperationType::Withdrawal, OperationType::Cheque, OperationType::Fee]
+            .into_iter()
+            .find(|t| t.as_str() == value)
+            .ok_or_else(|| anyhow::anyhow!("Unknown operation type '{}'", value))
+    }
+}
+
+impl ToSql for OperationType {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for OperationType {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: anyhow::Error| FromSqlError::Other(err.into()))
+    }
 }
 
 #[derive(Debug, Hash, Eq, PartialEq)]
 pub struct AccountBalance {
     pub row_id: Option<u32>,
+    pub account_id: Option<u32>,
     pub date: NaiveDate,
-    pub balance_euro: OrderedFloat<f32>,
+    pub balance_euro: Money,
+}
+
+/**
+ * Dates from / to (both included), open when missing
+ */
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct DateRange {
+    pub from: Option<NaiveDate>,
+    pub to: Option<NaiveDate>,
 }
 
 #[derive(Debug)]
 pub struct BankingStatement {
+    #[allow(unused)]
     pub row_id: Option<u32>,
-    pub balance: AccountBalance,
-    pub activities: HashSet<AccountActivity>,
+    pub account: Account,
+    pub balance: Option<AccountBalance>,
+    /**
+     * Balance before the first activity, when the statement tells it
+     */
+    pub opening_balance: Option<AccountBalance>,
+    pub activities: Vec<AccountActivity>,
+    /**
+     * Rows of the statement that could not be read
+     */
+    pub rejected: Vec<import::RejectedRow>,
 }
 
 #[derive(Serialize, Debug)]
 pub struct StatsAmountPerMonthByTag {
-    pub amount: OrderedFloat<f32>,
+    pub amount: Money,
     pub month: u8
 }
 
+/**
+ * Amount of the activities of a month by primary category (None for the activities without category):
+ * the amounts of a month add up to the total of its activities
+ */
+#[derive(Serialize, Debug, PartialEq)]
+pub struct StatsAmountPerMonthByCategory {
+    pub category: Option<String>,
+    pub amount: Money,
+    pub month_year: u32,
+}
+
+#[allow(unused)]
 #[derive(Serialize, Eq, PartialEq, Hash)]
 pub struct StatsDetailedAmountPerMonthByTag {
     pub tag: String,
-    pub amount: OrderedFloat<f32>,
+    pub amount: Money,
     pub month: u32,
     pub month_year: u32
 }
 
-pub mod tagging {
+pub mod tagging;
+
+pub mod reconciliation {
+    use chrono::NaiveDate;
     use serde::Serialize;
 
+    use crate::money::Money;
+
+    /**
+     * Interval between 2 consecutive balances of an account: the balance delta should be the sum of its activities
+     */
     #[derive(PartialEq, Serialize, Debug)]
-    pub struct TagsPattern {
-        pub id: u8,
-        pub pattern: String,
-        pub tag: String,
+    pub struct ReconciliationInterval {
+        pub account_id: u32,
+        pub from: NaiveDate,
+        pub to: NaiveDate,
+        pub opening_balance: Money,
+        pub closing_balance: Money,
+        /**
+         * closing_balance - opening_balance
+         */
+        pub expected_delta: Money,
+        /**
+         * Sum of the activities in (from, to]
+         */
+        pub actual_delta: Money,
+        /**
+         * expected_delta - actual_delta: activities missing from the statements
+         */
+        pub discrepancy: Money,
+        pub activities: usize,
+        pub reconciled: bool,
+    }
+}
+
+pub mod import {
+    use chrono::{NaiveDate, NaiveDateTime};
+    use serde::{Deserialize, Serialize};
+
+    use glob::Pattern;
+    use crate::csv::profile::CsvProfile;
+
+    /**
+     * Order of the day and the month in QIF dates, which depends on the software that exported them
+     */
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum QifDateOrder {
+        #[default]
+        Dmy,
+        Mdy,
+    }
+
+    #[derive(Clone, Debug, Default)]
+    pub struct ImportOptions {
+        pub qif_date_order: QifDateOrder,
+        /**
+         * Give the QIF category of each activity imported as a manual tag (created if missing)
+         */
+        pub qif_seed_tags: bool,
+        /**
+         * Layouts of the CSV exports of other banks than La Banque Postale
+         */
+        pub csv_profiles: Vec<CsvProfile>,
+        pub mode: ImportMode,
+        /**
+         * Globs on the paths relative to the statements folder: only the files matching one of `include` (all when empty)
+         * and none of `exclude` are imported
+         */
+        pub include: Vec<Pattern>,
+        pub exclude: Vec<Pattern>,
+    }
+
+    /**
+     * One entry of the import ledger: a statement file that has already been ingested
+     */
+    #[derive(PartialEq, Serialize, Debug)]
+    pub struct ImportedFile {
+        pub path: String,
+        /**
+         * Path of the statement inside the archive `path`
+         */
+        pub member: Option<String>,
+        pub hash: String,
+        pub statement_date: Option<NaiveDate>,
+        pub rows_inserted: usize,
+        pub rows_skipped: usize,
+        pub imported_at: NaiveDateTime,
+    }
+
+    /**
+     * How to handle the rows that can not be read
+     */
+    #[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+    #[serde(rename_all = "lowercase")]
+    pub enum ImportMode {
+        /**
+         * Fail on the first invalid row or file
+         */
+        Strict,
+        /**
+         * Skip the invalid rows and files, and report them
+         */
+        #[default]
+        Lenient,
+    }
+
+    /**
+     * A row of a statement that could not be read
+     */
+    #[derive(Clone, PartialEq, Serialize, Debug)]
+    pub struct RejectedRow {
+        pub line: usize,
+        pub content: String,
+        pub reason: String,
+    }
+
+    /**
+     * An imported file with the rows accepted (`rows_inserted`), the duplicates skipped (`rows_skipped`) and the rows rejected
+     */
+    #[derive(Serialize, Debug)]
+    pub struct FileReport {
+        #[serde(flatten)]
+        pub file: ImportedFile,
+        pub rejected: Vec<RejectedRow>,
+    }
+
+    /**
+     * A file that could not be imported at all (lenient mode)
+     */
+    #[derive(Serialize, Debug)]
+    pub struct FailedFile {
+        pub path: String,
+        pub reason: String,
     }
 
-    pub struct ActivityToTags {
-        pub activity_id: u32,
-        pub tags_pattern_id: u8,
+    /**
+     * Outcome of an import run: the files ingested, skipped (already imported) or failed,
+     * and the row ids of the newly inserted activities
+     */
+    #[derive(Default, Serialize, Debug)]
+    pub struct ImportReport {
+        pub files: Vec<FileReport>,
+        pub skipped_files: Vec<String>,
+        pub failed_files: Vec<FailedFile>,
+        #[serde(skip)]
+        pub activity_ids: Vec<u32>,
     }
 }
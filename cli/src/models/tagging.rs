@@ -0,0 +1,531 @@
+use std::str::FromStr;
+
+use chrono::{Datelike, NaiveDate};
+use regex::{Regex, RegexBuilder};
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::{Deserialize, Serialize};
+
+use crate::models::AccountActivity;
+use crate::money::Money;
+
+/**
+ * A tagging rule: activities matching its pattern and its conditions (when given) get its tags.
+ * A rule with conditions may have an empty pattern, ie. to tag from the amount only.
+ */
+#[derive(PartialEq, Serialize, Debug)]
+pub struct TagsPattern {
+    pub id: u32,
+    pub pattern: String,
+    pub match_kind: MatchKind,
+    pub rule: Option<Condition>,
+    pub options: RuleOptions,
+    pub tag: String,
+}
+
+/**
+ * How a rule is applied among the others
+ */
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleOptions {
+    /**
+     * Rules of higher priority are applied first, the oldest first on a tie
+     */
+    #[serde(default)]
+    pub priority: i32,
+    /**
+     * Once this rule matched, the rules after it are not applied to the activity
+     */
+    #[serde(default)]
+    pub stop: bool,
+    /**
+     * Primary category given to the activities it matches. An activity has a single category:
+     * the one of the first matching rule, the other rules with a category matching it are conflicts.
+     */
+    #[serde(default)]
+    pub category: Option<String>,
+}
+
+impl TagsPattern {
+    pub fn compile(&self) -> anyhow::Result<Rule> {
+        compile_rule(&self.pattern, self.match_kind, self.rule.as_ref())
+    }
+}
+
+/**
+ * How a pattern is looked for in the statement of an activity, always ignoring case
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MatchKind {
+    /**
+     * Anywhere in the statement
+     */
+    #[default]
+    Substring,
+    /**
+     * As whole words, ie. "FREE" does not match "FREEBOX"
+     */
+    Word,
+    /**
+     * At the start of the statement
+     */
+    Prefix,
+    /**
+     * Regular expression, ie. "^PRLV SEPA (FREE|SFR) MOBILE". The text captured by a group named `tag`,
+     * ie. "^PRLV SEPA (?P<tag>FREE|SFR) MOBILE", is one more tag of the activity, see `Matcher::captured_tag`.
+     */
+    Regex,
+}
+
+impl MatchKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            MatchKind::Substring => "substring",
+            MatchKind::Word => "word",
+            MatchKind::Prefix => "prefix",
+            MatchKind::Regex => "regex",
+        }
+    }
+
+    /**
+     * Check the pattern once, when it is saved or before tagging: an invalid regex is an error
+     */
+    pub fn compile(&self, pattern: &str) -> anyhow::Result<Matcher> {
+        if pattern.trim().is_empty() {
+            return Err(anyhow::anyhow!("Empty tag pattern"));
+        }
+        let regex = |expression: &str| {
+            RegexBuilder::new(expression)
+                .case_insensitive(true)
+                .build()
+                .map_err(|err| anyhow::anyhow!("Invalid regex '{}' : {}", pattern, err))
+        };
+        Ok(match self {
+            MatchKind::Substring => Matcher::Substring(pattern.to_lowercase()),
+            MatchKind::Prefix => Matcher::Prefix(pattern.to_lowercase()),
+            MatchKind::Word => Matcher::Regex(regex(&format!(r"\b{}\b", regex::escape(pattern.trim())))?),
+            MatchKind::Regex => Matcher::Regex(regex(pattern)?),
+        })
+    }
+}
+
+impl FromStr for MatchKind {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> anyhow::Result<Self> {
+        [MatchKind::Substring, MatchKind::Word, MatchKind::Prefix, MatchKind::Regex]
+            .into_iter()
+            .find(|k| k.as_str() == value)
+            .ok_or_else(|| anyhow::anyhow!("Unknown match kind '{}'", value))
+    }
+}
+
+impl ToSql for MatchKind {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.as_str()))
+    }
+}
+
+impl FromSql for MatchKind {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        value.as_str()?.parse().map_err(|err: anyhow::Error| FromSqlError::Other(err.into()))
+    }
+}
+
+/**
+ * A pattern ready to be matched, see `MatchKind::compile`
+ */
+pub enum Matcher {
+    Substring(String),
+    Prefix(String),
+    Regex(Regex),
+}
+
+/**
+ * Name of the regex group capturing a tag
+ */
+pub const CAPTURED_TAG_GROUP: &str = "tag";
+
+impl Matcher {
+    pub fn is_match(&self, statement: &str) -> bool {
+        match self {
+            Matcher::Substring(pattern) => statement.to_lowercase().contains(pattern.as_str()),
+            Matcher::Prefix(pattern) => statement.trim_start().to_lowercase().starts_with(pattern.as_str()),
+            Matcher::Regex(regex) => regex.is_match(statement),
+        }
+    }
+
+    /**
+     * The tag named after the text captured by the group `tag` of a regex, in upper case.
+     * Nothing is captured when the text is blank or is not a valid tag name (it contains the path separator).
+     */
+    pub fn captured_tag(&self, statement: &str) -> Option<String> {
+        let regex = match self {
+            Matcher::Regex(regex) => regex,
+            _ => return None,
+        };
+        regex
+            .captures(statement)
+            .and_then(|captures| captures.name(CAPTURED_TAG_GROUP))
+            .map(|tag| tag.as_str().trim().to_uppercase())
+            .filter(|tag| !tag.is_empty() && !tag.contains(PATH_SEPARATOR))
+    }
+}
+
+/**
+ * Condition on an activity, stored as JSON in the `rule` column of `tags_pattern`, ie.
+ * `{"and": [{"amount": "-650.00"}, {"day_of_month": {"from": 1, "to": 5}}]}`
+ */
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", deny_unknown_fields)]
+pub enum Condition {
+    Label {
+        pattern: String,
+        #[serde(default)]
+        match_kind: MatchKind,
+    },
+    /**
+     * Exact amount, signed
+     */
+    Amount(Money),
+    /**
+     * Signed amount between min and max (both included), open when missing
+     */
+    AmountRange { min: Option<Money>, max: Option<Money> },
+    Sign(Sign),
+    /**
+     * Booking date between from and to (both included), open when missing
+     */
+    DateRange { from: Option<NaiveDate>, to: Option<NaiveDate> },
+    /**
+     * Day of the month of the booking date, between from and to (both included)
+     */
+    DayOfMonth { from: u32, to: u32 },
+    /**
+     * Id of the account, as given by `/api/accounts`
+     */
+    Account(u32),
+    And(Vec<Condition>),
+    Or(Vec<Condition>),
+    Not(Box<Condition>),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Sign {
+    Debit,
+    Credit,
+}
+
+impl Condition {
+    /**
+     * Check the condition once, when it is saved or before tagging
+     */
+    pub fn compile(&self) -> anyhow::Result<Rule> {
+        let all = |conditions: &[Condition]| -> anyhow::Result<Vec<Rule>> {
+            if conditions.is_empty() {
+                return Err(anyhow::anyhow!("Empty list of conditions"));
+            }
+            conditions.iter().map(Condition::compile).collect()
+        };
+        Ok(match self {
+            Condition::Label { pattern, match_kind } => Rule::Label(match_kind.compile(pattern)?),
+            Condition::Amount(amount) => Rule::AmountRange(Some(*amount), Some(*amount)),
+            Condition::AmountRange { min: None, max: None } => return Err(anyhow::anyhow!("Amount range without min nor max")),
+            Condition::AmountRange { min: Some(min), max: Some(max) } if min > max => {
+                return Err(anyhow::anyhow!("Amount range from {} to {} is empty", min, max))
+            }
+            Condition::AmountRange { min, max } => Rule::AmountRange(*min, *max),
+            Condition::Sign(sign) => Rule::Sign(*sign),
+            Condition::DateRange { from: None, to: None } => return Err(anyhow::anyhow!("Date range without from nor to")),
+            Condition::DateRange { from: Some(from), to: Some(to) } if from > to => {
+                return Err(anyhow::anyhow!("Date range from {} to {} is empty", from, to))
+            }
+            Condition::DateRange { from, to } => Rule::DateRange(*from, *to),
+            Condition::DayOfMonth { from, to } if *from < 1 || *to > 31 || from > to => {
+                return Err(anyhow::anyhow!("Invalid days of month {} to {}", from, to))
+            }
+            Condition::DayOfMonth { from, to } => Rule::DayOfMonth(*from, *to),
+            Condition::Account(account_id) => Rule::Account(*account_id),
+            Condition::And(conditions) => Rule::And(all(conditions)?),
+            Condition::Or(conditions) => Rule::Or(all(conditions)?),
+            Condition::Not(condition) => Rule::Not(Box::new(condition.compile()?)),
+        })
+    }
+}
+
+impl ToSql for Condition {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        serde_json::to_string(self)
+            .map(ToSqlOutput::from)
+            .map_err(|err| rusqlite::Error::ToSqlConversionFailure(err.into()))
+    }
+}
+
+impl FromSql for Condition {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        serde_json::from_str(value.as_str()?).map_err(|err| FromSqlError::Other(err.into()))
+    }
+}
+
+/**
+ * Pattern and conditions of a tagging rule ready to be evaluated, see `compile_rule`
+ */
+pub enum Rule {
+    Label(Matcher),
+    AmountRange(Option<Money>, Option<Money>),
+    Sign(Sign),
+    DateRange(Option<NaiveDate>, Option<NaiveDate>),
+    DayOfMonth(u32, u32),
+    Account(u32),
+    And(Vec<Rule>),
+    Or(Vec<Rule>),
+    Not(Box<Rule>),
+}
+
+impl Rule {
+    pub fn is_match(&self, activity: &AccountActivity) -> bool {
+        match self {
+            Rule::Label(matcher) => matcher.is_match(&activity.statement),
+            Rule::AmountRange(min, max) => {
+                min.is_none_or(|min| activity.amount >= min) && max.is_none_or(|max| activity.amount <= max)
+            }
+            Rule::Sign(Sign::Debit) => activity.amount.is_negative(),
+            Rule::Sign(Sign::Credit) => !activity.amount.is_negative(),
+            Rule::DateRange(from, to) => {
+                from.is_none_or(|from| activity.date >= from) && to.is_none_or(|to| activity.date <= to)
+            }
+            Rule::DayOfMonth(from, to) => (*from..=*to).contains(&activity.date.day()),
+            Rule::Account(account_id) => activity.account_id == Some(*account_id),
+            Rule::And(rules) => rules.iter().all(|r| r.is_match(activity)),
+            Rule::Or(rules) => rules.iter().any(|r| r.is_match(activity)),
+            Rule::Not(rule) => !rule.is_match(activity),
+        }
+    }
+
+    /**
+     * The tag captured by the first matching label of the rule, if any
+     */
+    pub fn captured_tag(&self, activity: &AccountActivity) -> Option<String> {
+        match self {
+            Rule::Label(matcher) => matcher.captured_tag(&activity.statement),
+            Rule::And(rules) | Rule::Or(rules) => rules.iter().filter(|r| r.is_match(activity)).find_map(|r| r.captured_tag(activity)),
+            _ => None,
+        }
+    }
+}
+
+/**
+ * The pattern (unless empty with conditions) and the conditions of a tagging rule must both match
+ */
+pub fn compile_rule(pattern: &str, match_kind: MatchKind, rule: Option<&Condition>) -> anyhow::Result<Rule> {
+    match rule {
+        None => Ok(Rule::Label(match_kind.compile(pattern)?)),
+        Some(rule) if pattern.trim().is_empty() => rule.compile(),
+        Some(rule) => Ok(Rule::And(vec![Rule::Label(match_kind.compile(pattern)?), rule.compile()?])),
+    }
+}
+
+pub struct ActivityToTags {
+    pub activity_id: u32,
+    pub tags_pattern_id: u32,
+    /**
+     * The rule gives the primary category of the activity
+     */
+    pub primary: bool,
+    /**
+     * Tag captured by the regex of the rule, given to the activity besides the tags of the rule
+     */
+    pub captured_tag: Option<String>,
+}
+
+/**
+ * A rule with a category matching an activity
+ */
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct CompetingRule {
+    pub id: u32,
+    pub priority: i32,
+    pub category: String,
+}
+
+/**
+ * An activity matched by rules giving different categories: the first one won
+ */
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TagConflict {
+    pub activity_id: u32,
+    pub date: NaiveDate,
+    pub statement: String,
+    pub amount: Money,
+    pub category: String,
+    /**
+     * By priority, the winner first
+     */
+    pub rules: Vec<CompetingRule>,
+    /**
+     * The winner has the priority of another rule: only its id decided
+     */
+    pub tie: bool,
+}
+
+/**
+ * A rule as sent to the API to add or edit it, the tags by their text (missing tags are created)
+ */
+#[derive(Debug, Deserialize)]
+pub struct RuleDefinition {
+    #[serde(default)]
+    pub pattern: String,
+    #[serde(default)]
+    pub match_kind: MatchKind,
+    #[serde(default)]
+    pub rule: Option<Condition>,
+    #[serde(flatten)]
+    pub options: RuleOptions,
+    pub tags: Vec<String>,
+}
+
+/**
+ * What a rule change recomputed: only the activities matched (before or after the change) by the rules changed
+ */
+#[derive(Debug, PartialEq, Serialize)]
+pub struct RetagReport {
+    pub rules: Vec<u32>,
+    pub activities: usize,
+    pub activity_tags: usize,
+    pub invalid_rules: Vec<InvalidRule>,
+}
+
+/**
+ * A rule saved without being validated (ie. written in the database by hand) that can not be applied
+ */
+#[derive(Debug, PartialEq, Serialize)]
+pub struct InvalidRule {
+    pub id: u32,
+    pub error: String,
+}
+
+/**
+ * A tag given to (or removed from) an activity by hand: it takes precedence over the rules
+ */
+#[derive(Clone, Debug, Hash, Eq, PartialEq, Serialize, Deserialize)]
+pub struct TagOverride {
+    pub tag: String,
+    pub assigned: bool,
+}
+
+/**
+ * A manual tag with what identifies its activity when the database is rebuilt (the row ids change)
+ */
+#[derive(Debug, PartialEq)]
+pub struct SavedTagOverride {
+    pub account_number: String,
+    pub transaction_id: Option<String>,
+    pub date: NaiveDate,
+    pub statement: String,
+    pub amount: Money,
+    pub occurrence: u32,
+    pub tag_override: TagOverride,
+}
+
+/**
+ * Separates the tags of a path, ie. "LOGEMENT/ENERGIE/EDF"
+ */
+pub const PATH_SEPARATOR: char = '/';
+
+/**
+ * A tag, a sub category of its parent if any (ie. LOGEMENT > ENERGIE > EDF)
+ */
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct Tag {
+    pub id: u32,
+    pub tag: String,
+    pub parent_id: Option<u32>,
+}
+
+/**
+ * A tag as sent to the API to create or edit it
+ */
+#[derive(Debug, Deserialize)]
+pub struct TagDefinition {
+    pub tag: String,
+    #[serde(default)]
+    pub parent_id: Option<u32>,
+}
+
+/**
+ * A tag of an activity (given by a rule or by hand) with the amount of the activity
+ */
+#[derive(Debug, PartialEq)]
+pub struct TaggedAmount {
+    pub activity_id: u32,
+    pub tag_id: u32,
+    pub amount: Money,
+    pub month_year: u32,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct MonthAmount {
+    pub month_year: u32,
+    pub amount: Money,
+}
+
+/**
+ * A tag with the amounts of the activities of its whole sub tree: an activity with several tags of the tree counts once
+ */
+#[derive(Debug, PartialEq, Serialize)]
+pub struct TagNode {
+    pub id: u32,
+    pub tag: String,
+    /**
+     * The tags from the root, ie. "LOGEMENT/ENERGIE/EDF"
+     */
+    pub path: String,
+    pub amount: Money,
+    pub months: Vec<MonthAmount>,
+    pub children: Vec<TagNode>,
+}
+
+/**
+ * A tag suggested for an activity without tag, with the probability given by the classifier
+ */
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Suggestion {
+    pub activity_id: u32,
+    pub date: NaiveDate,
+    pub statement: String,
+    pub amount: Money,
+    pub tag: String,
+    pub confidence: f64,
+}
+
+/**
+ * How a suggestion is accepted: the tag is given by hand to the activity, or a rule is written from it
+ */
+#[derive(Clone, Copy, Debug, Default, PartialEq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AcceptAs {
+    #[default]
+    Manual,
+    Rule,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SuggestionAcceptance {
+    pub activity_id: u32,
+    pub tag: String,
+    #[serde(default)]
+    pub accept_as: AcceptAs,
+    /**
+     * Pattern of the rule, by default the merchant of the activity or its word telling the most the tag
+     */
+    #[serde(default)]
+    pub pattern: Option<String>,
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Accepted {
+    ManualTags(Vec<TagOverride>),
+    Rule(RetagReport),
+}
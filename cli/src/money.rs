@@ -0,0 +1,237 @@
+use std::fmt;
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Neg, Sub};
+use std::str::FromStr;
+
+use rusqlite::types::{FromSql, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
+use serde::de::{self, Visitor};
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/**
+ * An amount of money, exact to the cent.
+ * Stored as an INTEGER number of cents and serialized as a decimal string, ie. "-1234.56".
+ */
+#[derive(Clone, Copy, Debug, Default, Hash, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Money(i64);
+
+impl Money {
+    pub const ZERO: Money = Money(0);
+
+    #[allow(unused)]
+    pub fn from_cents(cents: i64) -> Self {
+        Money(cents)
+    }
+
+    pub fn abs(&self) -> Self {
+        Money(self.0.abs())
+    }
+
+    pub fn is_negative(&self) -> bool {
+        self.0 < 0
+    }
+
+    /**
+     * Parse an amount written with the given separators, ie. "-1 234,56" with ',' and ' '
+     */
+    pub fn parse_with(value: &str, decimal_separator: char, thousands_separator: Option<char>) -> anyhow::Result<Self> {
+        let normalized: String = value
+            .trim()
+            .chars()
+            .filter(|c| Some(*c) != thousands_separator && !c.is_whitespace() && *c != '€')
+            .map(|c| if c == decimal_separator { '.' } else { c })
+            .collect();
+        normalized.parse::<Money>().map_err(|err| anyhow::anyhow!("Invalid amount '{}': {}", value, err))
+    }
+}
+
+/**
+ * Parse a decimal amount with a '.' separator, ie. "-1234.5", "+12", ".50".
+ * Decimals beyond the cent are only accepted when they are zeros.
+ */
+impl FromStr for Money {
+    type Err = anyhow::Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let value = value.trim();
+        let (negative, digits) = match value.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, value.strip_prefix('+').unwrap_or(value)),
+        };
+        let (units, decimals) = digits.split_once('.').unwrap_or((digits, ""));
+
+        let is_number = |part: &str| part.chars().all(|c| c.is_ascii_digit());
+        if (units.is_empty() && decimals.is_empty()) || !is_number(units) || !is_number(decimals) {
+            return Err(anyhow::anyhow!("'{}' is not a decimal number", value));
+        }
+        if decimals.len() > 2 && decimals[2..].chars().any(|c| c != '0') {
+            return Err(anyhow::anyhow!("'{}' has more than 2 decimals", value));
+        }
+
+        let units: i64 = if units.is_empty() { 0 } else { units.parse()? };
+        let cents: i64 = format!("{:0<2}", decimals.get(0..2).unwrap_or(decimals)).parse()?;
+        let amount = units
+            .checked_mul(100)
+            .and_then(|u| u.checked_add(cents))
+            .ok_or_else(|| anyhow::anyhow!("'{}' is too large", value))?;
+
+        Ok(Money(if negative { -amount } else { amount }))
+    }
+}
+
+impl fmt::Display for Money {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let sign = if self.0 < 0 { "-" } else { "" };
+        let cents = self.0.unsigned_abs();
+        write!(f, "{}{}.{:02}", sign, cents / 100, cents % 100)
+    }
+}
+
+impl Serialize for Money {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(self)
+    }
+}
+
+/**
+ * Read back from a decimal string, or from a JSON number written by hand (ie. in a tagging rule)
+ */
+impl<'de> Deserialize<'de> for Money {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct MoneyVisitor;
+
+        impl<'de> Visitor<'de> for MoneyVisitor {
+            type Value = Money;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a decimal amount, ie. \"-1234.56\"")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Money, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Money, E> {
+                value.checked_mul(100).map(Money).ok_or_else(|| E::custom("amount too large"))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Money, E> {
+                i64::try_from(value).map_err(E::custom).and_then(|value| self.visit_i64(value))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Money, E> {
+                self.visit_str(&value.to_string())
+            }
+        }
+
+        deserializer.deserialize_any(MoneyVisitor)
+    }
+}
+
+impl ToSql for Money {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        Ok(ToSqlOutput::from(self.0))
+    }
+}
+
+impl FromSql for Money {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        i64::column_result(value).map(Money)
+    }
+}
+
+impl Add for Money {
+    type Output = Money;
+
+    fn add(self, other: Money) -> Money {
+        Money(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Money {
+    fn add_assign(&mut self, other: Money) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Money {
+    type Output = Money;
+
+    fn sub(self, other: Money) -> Money {
+        Money(self.0 - other.0)
+    }
+}
+
+impl Neg for Money {
+    type Output = Money;
+
+    fn neg(self) -> Money {
+        Money(-self.0)
+    }
+}
+
+impl Sum for Money {
+    fn sum<I: Iterator<Item = Money>>(iter: I) -> Money {
+        iter.fold(Money::ZERO, Add::add)
+    }
+}
+
+impl<'a> Sum<&'a Money> for Money {
+    fn sum<I: Iterator<Item = &'a Money>>(iter: I) -> Money {
+        iter.copied().sum()
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::money::Money;
+
+    #[test]
+    fn test_parse() -> anyhow::Result<()> {
+        assert_eq!("-1234.56".parse::<Money>()?, Money::from_cents(-123456), "Wrong negative amount");
+        assert_eq!("+12".parse::<Money>()?, Money::from_cents(1200), "Wrong amount without decimals");
+        assert_eq!("0.5".parse::<Money>()?, Money::from_cents(50), "Wrong amount with 1 decimal");
+        assert_eq!(".05".parse::<Money>()?, Money::from_cents(5), "Wrong amount without units");
+        assert_eq!("2.500".parse::<Money>()?, Money::from_cents(250), "Trailing zeros should be accepted");
+        assert!("2.505".parse::<Money>().is_err(), "Fractions of cent should fail");
+        assert!("12,50".parse::<Money>().is_err(), "Decimal comma needs parse_with");
+        assert!("-".parse::<Money>().is_err(), "A sign is not a number");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_with() -> anyhow::Result<()> {
+        assert_eq!(Money::parse_with("-1 234,56", ',', Some(' '))?, Money::from_cents(-123456), "Wrong French amount");
+        assert_eq!(Money::parse_with("1,234.56 €", '.', Some(','))?, Money::from_cents(123456), "Wrong English amount");
+        Ok(())
+    }
+
+    #[test]
+    fn test_exact_sum() {
+        // 0.1 + 0.2 is not 0.3 with floats
+        let sum: Money = ["0.10", "0.20"].iter().map(|a| a.parse::<Money>().unwrap()).sum();
+        assert_eq!(sum, Money::from_cents(30), "Sum should be exact");
+
+        // f32 loses the cents above ~100k
+        let large: Money = ["123456.78", "0.01"].iter().map(|a| a.parse::<Money>().unwrap()).sum();
+        assert_eq!(large.to_string(), "123456.79", "Large amounts should keep their cents");
+    }
+
+    #[test]
+    fn test_display() -> anyhow::Result<()> {
+        assert_eq!(Money::from_cents(-5).to_string(), "-0.05", "Wrong small negative amount");
+        assert_eq!(Money::from_cents(18777).to_string(), "187.77", "Wrong amount");
+        assert_eq!(serde_json::to_string(&Money::from_cents(-2000))?, "\"-20.00\"", "Amounts should be JSON strings");
+        Ok(())
+    }
+
+    #[test]
+    fn test_deserialize() -> anyhow::Result<()> {
+        assert_eq!(serde_json::from_str::<Money>("\"-650.00\"")?, Money::from_cents(-65000), "Wrong amount from a string");
+        assert_eq!(serde_json::from_str::<Money>("-650.5")?, Money::from_cents(-65050), "Wrong amount from a number");
+        assert_eq!(serde_json::from_str::<Money>("12")?, Money::from_cents(1200), "Wrong amount from an integer");
+        assert!(serde_json::from_str::<Money>("0.123").is_err(), "Fractions of cent should fail");
+        Ok(())
+    }
+}
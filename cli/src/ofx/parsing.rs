@@ -0,0 +1,342 @@
+use chrono::NaiveDate;
+use anyhow;
+
+use crate::csv::encoding::decode;
+use crate::models::{Account, AccountBalance, AccountActivity, ActivityDetails, BankingStatement};
+use crate::models::import::RejectedRow;
+use crate::money::Money;
+
+/**
+ * A token of an OFX document.
+ * SGML (OFX 1.x) leaves have no closing tag, XML (OFX 2.x) ones have: both give a `Leaf`.
+ */
+#[derive(Debug, PartialEq)]
+enum Token<'a> {
+    Open(&'a str),
+    Close(&'a str),
+    Leaf(&'a str, &'a str),
+}
+
+fn tokenize(content: &str) -> Vec<Token<'_>> {
+    let mut tokens = Vec::new();
+    // Skip the SGML header / XML prolog
+    let body = match content.find("<OFX>") {
+        Some(start) => &content[start..],
+        None => content,
+    };
+
+    let mut rest = body;
+    while let Some(start) = rest.find('<') {
+        let end = match rest[start..].find('>') {
+            Some(end) => start + end,
+            None => break,
+        };
+        let tag = rest[start + 1..end].trim();
+        rest = &rest[end + 1..];
+
+        if let Some(name) = tag.strip_prefix('/') {
+            tokens.push(Token::Close(name));
+            continue;
+        }
+
+        let text_end = rest.find('<').unwrap_or(rest.len());
+        let text = rest[..text_end].trim();
+        if text.is_empty() {
+            tokens.push(Token::Open(tag));
+        } else {
+            tokens.push(Token::Leaf(tag, text));
+            // Skip the XML closing tag of the leaf
+            let closing = format!("</{}>", tag);
+            if rest[text_end..].starts_with(&closing) {
+                rest = &rest[text_end + closing.len()..];
+                continue;
+            }
+        }
+        rest = &rest[text_end..];
+    }
+    tokens
+}
+
+/**
+ * Position in `content` of one of its tokens
+ */
+fn offset_of(content: &str, token: &str) -> usize {
+    token.as_ptr() as usize - content.as_ptr() as usize
+}
+
+/**
+ * OFX dates are YYYYMMDD optionally followed by the time and the timezone: only the day is kept
+ */
+fn parse_date(value: &str) -> anyhow::Result<NaiveDate> {
+    let day = value.get(0..8).ok_or_else(|| anyhow::anyhow!("Invalid OFX date '{}'", value))?;
+    Ok(NaiveDate::parse_from_str(day, "%Y%m%d")?)
+}
+
+fn parse_amount(value: &str) -> anyhow::Result<Money> {
+    value.replace(",", ".").parse::<Money>()
+}
+
+#[derive(Default)]
+struct Transaction {
+    date: Option<NaiveDate>,
+    amount: Option<Money>,
+    name: Option<String>,
+    memo: Option<String>,
+    fitid: Option<String>,
+    /**
+     * Start of the transaction in the document
+     */
+    start: usize,
+    /**
+     * First value that could not be read
+     */
+    error: Option<String>,
+}
+
+impl Transaction {
+    fn set<T>(&mut self, value: anyhow::Result<T>) -> Option<T> {
+        match value {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.error.get_or_insert(err.to_string());
+                None
+            }
+        }
+    }
+
+    fn into_activity(self) -> anyhow::Result<AccountActivity> {
+        if let Some(error) = self.error {
+            return Err(anyhow::anyhow!(error));
+        }
+        let statement = match (self.name, self.memo) {
+            (Some(name), Some(memo)) if name != memo => format!("{} {}", name, memo),
+            (Some(name), _) => name,
+            (None, Some(memo)) => memo,
+            (None, None) => String::new(),
+        };
+        Ok(AccountActivity {
+            row_id: None,
+            account_id: None,
+            date: self.date.ok_or_else(|| anyhow::anyhow!("Missing DTPOSTED for transaction '{}'", statement))?,
+            amount: self.amount.ok_or_else(|| anyhow::anyhow!("Missing TRNAMT for transaction '{}'", statement))?,
+            statement,
+            tag_pattern_id: None,
+            tag_pattern_ids: Vec::new(),
+            tag_overrides: Vec::new(),
+            transaction_id: self.fitid,
+            details: ActivityDetails::default(),
+        })
+    }
+}
+
+#[derive(Default)]
+struct Statement {
+    account_number: Option<String>,
+    account_type: Option<String>,
+    balance: Option<Money>,
+    balance_date: Option<NaiveDate>,
+    activities: Vec<AccountActivity>,
+    rejected: Vec<RejectedRow>,
+}
+
+impl Statement {
+    fn into_banking_statement(self) -> anyhow::Result<BankingStatement> {
+        let number = self.account_number.ok_or_else(|| anyhow::anyhow!("Missing ACCTID"))?;
+        let balance_euro = self.balance.ok_or_else(|| anyhow::anyhow!("Missing LEDGERBAL for account {}", number))?;
+        let date = self.balance_date.ok_or_else(|| anyhow::anyhow!("Missing DTASOF for account {}", number))?;
+
+        Ok(BankingStatement {
+            row_id: None,
+            account: Account { row_id: None, number, account_type: self.account_type.unwrap_or_default() },
+            balance: Some(AccountBalance { row_id: None, account_id: None, date, balance_euro }),
+            opening_balance: None,
+            activities: self.activities,
+            rejected: self.rejected,
+        })
+    }
+}
+
+/**
+ * Parse the content of an OFX / QFX file, whatever its encoding
+ */
+pub fn parse_ofx(bytes: &[u8]) -> anyhow::Result<Vec<BankingStatement>> {
+    parse_ofx_content(&decode(bytes))
+}
+
+/**
+ * Parse an OFX / QFX document (SGML 1.x or XML 2.x): one banking statement per STMTRS / CCSTMTRS.
+ * Transactions that can not be read are rejected.
+ */
+pub fn parse_ofx_content(content: &str) -> anyhow::Result<Vec<BankingStatement>> {
+    let mut statements: Vec<BankingStatement> = Vec::new();
+    let mut statement: Option<Statement> = None;
+    let mut transaction: Option<Transaction> = None;
+    let mut in_ledger = false;
+
+    for token in tokenize(content) {
+        match token {
+            Token::Open("STMTRS") | Token::Open("CCSTMTRS") => {
+                statement = Some(Statement::default());
+            }
+            Token::Close("STMTRS") | Token::Close("CCSTMTRS") => {
+                if let Some(s) = statement.take() {
+                    statements.push(s.into_banking_statement()?);
+                }
+            }
+            Token::Open(tag @ "STMTTRN") => {
+                // Back to the '<' of the tag
+                transaction = Some(Transaction { start: offset_of(content, tag) - 1, ..Transaction::default() });
+            }
+            Token::Close(tag @ "STMTTRN") => {
+                if let (Some(s), Some(t)) = (statement.as_mut(), transaction.take()) {
+                    // Up to the '>' of the closing tag
+                    let (start, end) = (t.start, offset_of(content, tag) + tag.len() + 1);
+                    match t.into_activity() {
+                        Ok(activity) => { s.activities.push(activity); },
+                        Err(err) => s.rejected.push(RejectedRow {
+                            line: content[..start].matches('\n').count() + 1,
+                            content: content[start..end].to_string(),
+                            reason: err.to_string(),
+                        }),
+                    }
+                }
+            }
+            Token::Open("LEDGERBAL") => in_ledger = true,
+            Token::Close("LEDGERBAL") => in_ledger = false,
+            Token::Leaf(tag, value) => {
+                if let Some(t) = transaction.as_mut() {
+                    match tag {
+                        "DTPOSTED" => t.date = t.set(parse_date(value)),
+                        "TRNAMT" => t.amount = t.set(parse_amount(value)),
+                        "NAME" => t.name = Some(value.to_string()),
+                        "MEMO" => t.memo = Some(value.to_string()),
+                        "FITID" => t.fitid = Some(value.to_string()),
+                        _ => (),
+                    }
+                } else if let Some(s) = statement.as_mut() {
+                    match tag {
+                        "ACCTID" => s.account_number = Some(value.to_string()),
+                        "ACCTTYPE" => s.account_type = Some(value.to_string()),
+                        "BALAMT" if in_ledger => s.balance = Some(parse_amount(value)?),
+                        "DTASOF" if in_ledger => s.balance_date = Some(parse_date(value)?),
+                        _ => (),
+                    }
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if statements.is_empty() {
+        return Err(anyhow::anyhow!("No statement found in OFX document"));
+    }
+    Ok(statements)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+    use crate::money::Money;
+    use crate::ofx::parsing::parse_ofx_content;
+
+    const OFX_SGML: &str = "OFXHEADER:100
+DATA:OFXSGML
+VERSION:102
+CHARSET:1252
+
+<OFX>
+<BANKMSGSRSV1><STMTTRNRS><STMTRS>
+<CURDEF>EUR
+<BANKACCTFROM><BANKID>20041<BRANCHID>01005<ACCTID>123456789<ACCTTYPE>CHECKING</BANKACCTFROM>
+<BANKTRANLIST>
+<DTSTART>20210301<DTEND>20210327
+<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20210326<TRNAMT>-2,50<FITID>0001<NAME>CARTE X1234 CAFE</STMTTRN>
+<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>20210326<TRNAMT>-2,50<FITID>0002<NAME>CARTE X1234 CAFE</STMTTRN>
+<STMTTRN><TRNTYPE>CREDIT<DTPOSTED>20210315120000[+1:CET]<TRNAMT>1500.00<FITID>0003<NAME>VIREMENT DE M. DUPONT<MEMO>SALAIRE</STMTTRN>
+</BANKTRANLIST>
+<LEDGERBAL><BALAMT>187.77<DTASOF>20210327</LEDGERBAL>
+<AVAILBAL><BALAMT>100.00<DTASOF>20210327</AVAILBAL>
+</STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+";
+
+    const OFX_XML: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="no"?>
+<?OFX OFXHEADER="200" VERSION="220" SECURITY="NONE" OLDFILEUID="NONE" NEWFILEUID="NONE"?>
+<OFX>
+  <BANKMSGSRSV1><STMTTRNRS><STMTRS>
+    <BANKACCTFROM><ACCTID>987654321</ACCTID><ACCTTYPE>SAVINGS</ACCTTYPE></BANKACCTFROM>
+    <BANKTRANLIST>
+      <STMTTRN><TRNTYPE>INT</TRNTYPE><DTPOSTED>20211231</DTPOSTED><TRNAMT>12.34</TRNAMT><FITID>INT2021</FITID><NAME>INTERETS 2021</NAME></STMTTRN>
+    </BANKTRANLIST>
+    <LEDGERBAL><BALAMT>2012.34</BALAMT><DTASOF>20211231</DTASOF></LEDGERBAL>
+  </STMTRS></STMTTRNRS></BANKMSGSRSV1>
+</OFX>
+"#;
+
+    #[test]
+    fn test_sgml() -> anyhow::Result<()> {
+        let statements = parse_ofx_content(OFX_SGML)?;
+        assert_eq!(statements.len(), 1, "Wrong count of statements");
+
+        let statement = &statements[0];
+        assert_eq!(statement.account.number, "123456789", "Wrong account number");
+        assert_eq!(statement.account.account_type, "CHECKING", "Wrong account type");
+        let balance = statement.balance.as_ref().expect("Balance not found");
+        assert_eq!(balance.balance_euro, Money::from_cents(18777), "LEDGERBAL should be the balance");
+        assert_eq!(balance.date, NaiveDate::from_ymd(2021, 3, 27), "Wrong balance date");
+        assert_eq!(statement.activities.len(), 3, "Identical transactions with distinct FITID should be kept");
+
+        let salary = statement.activities.iter()
+            .find(|a| a.transaction_id.as_deref() == Some("0003"))
+            .expect("Transaction 0003 not found");
+        assert_eq!(salary.statement, "VIREMENT DE M. DUPONT SALAIRE", "Wrong statement");
+        assert_eq!(salary.date, NaiveDate::from_ymd(2021, 3, 15), "Wrong date");
+        assert_eq!(salary.amount, Money::from_cents(150000), "Wrong amount");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_xml() -> anyhow::Result<()> {
+        let statements = parse_ofx_content(OFX_XML)?;
+        assert_eq!(statements.len(), 1, "Wrong count of statements");
+
+        let statement = &statements[0];
+        assert_eq!(statement.account.number, "987654321", "Wrong account number");
+        assert_eq!(statement.balance.as_ref().map(|b| b.balance_euro), Some(Money::from_cents(201234)), "Wrong balance");
+        assert_eq!(statement.activities.len(), 1, "Wrong count of activities");
+        assert_eq!(
+            statement.activities.first().and_then(|a| a.transaction_id.clone()),
+            Some("INT2021".to_string()),
+            "Wrong FITID"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_transaction() -> anyhow::Result<()> {
+        let content = OFX_SGML.replace("<DTPOSTED>20210326<TRNAMT>-2,50<FITID>0002", "<DTPOSTED>2021<TRNAMT>-2,50<FITID>0002");
+        let statements = parse_ofx_content(&content)?;
+
+        let statement = &statements[0];
+        assert_eq!(statement.activities.len(), 2, "The other transactions should be kept");
+        assert_eq!(statement.rejected.len(), 1, "The invalid transaction should be rejected");
+        assert_eq!(statement.rejected[0].line, 13, "Wrong line of the rejected transaction");
+        assert_eq!(
+            statement.rejected[0].content,
+            "<STMTTRN><TRNTYPE>DEBIT<DTPOSTED>2021<TRNAMT>-2,50<FITID>0002<NAME>CARTE X1234 CAFE</STMTTRN>",
+            "Wrong content of the rejected transaction"
+        );
+        assert!(statement.rejected[0].reason.contains("2021"), "The reason should tell the invalid date");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_statement() {
+        assert!(parse_ofx_content("<OFX></OFX>").is_err(), "An OFX without statement should fail");
+    }
+}
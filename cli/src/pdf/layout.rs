@@ -0,0 +1,210 @@
+use pdf_extract::{Document, MediaBox, OutputDev, OutputError, Transform};
+
+/**
+ * Gap between 2 characters (in font size) starting a new word
+ */
+const WORD_GAP: f64 = 0.15;
+
+/**
+ * Gap between 2 characters (in font size) starting a new cell, ie. the next column of a table
+ */
+const CELL_GAP: f64 = 0.8;
+
+/**
+ * Text of a line between 2 wide gaps, with its horizontal position
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cell {
+    pub x: f64,
+    pub end: f64,
+    pub text: String,
+}
+
+/**
+ * A line of text of a page, read from left to right
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Line {
+    pub cells: Vec<Cell>,
+}
+
+impl Line {
+    pub fn text(&self) -> String {
+        self.cells.iter().map(|c| c.text.as_str()).collect::<Vec<&str>>().join(" ")
+    }
+
+    /**
+     * Build a line from a text laid out with spaces (ie. `pdftotext -layout`): the position is the column of a character,
+     * a single space separates words, more separate cells
+     */
+    #[cfg(test)]
+    pub fn from_text(text: &str) -> Self {
+        let mut cells: Vec<Cell> = Vec::new();
+        // Spaces before the current character, the first one starts a cell
+        let mut spaces: usize = 2;
+        for (column, c) in text.chars().enumerate() {
+            if c == ' ' {
+                spaces += 1;
+                continue;
+            }
+            match cells.last_mut() {
+                Some(cell) if spaces < 2 => {
+                    if spaces == 1 {
+                        cell.text.push(' ');
+                    }
+                    cell.text.push(c);
+                    cell.end = (column + 1) as f64;
+                }
+                _ => cells.push(Cell { x: column as f64, end: (column + 1) as f64, text: c.to_string() }),
+            }
+            spaces = 0;
+        }
+        Line { cells }
+    }
+}
+
+struct Glyph {
+    x: f64,
+    end: f64,
+    y: f64,
+    size: f64,
+    text: String,
+}
+
+/**
+ * Keeps the position of the characters of a PDF to rebuild its lines and table columns
+ */
+#[derive(Default)]
+struct TextLayout {
+    page_height: f64,
+    glyphs: Vec<Glyph>,
+    lines: Vec<Line>,
+}
+
+impl OutputDev for TextLayout {
+    fn begin_page(&mut self, _page_num: u32, media_box: &MediaBox, _art_box: Option<(f64, f64, f64, f64)>) -> Result<(), OutputError> {
+        self.page_height = media_box.ury - media_box.lly;
+        Ok(())
+    }
+
+    fn end_page(&mut self) -> Result<(), OutputError> {
+        let glyphs = std::mem::take(&mut self.glyphs);
+        self.lines.extend(group_lines(glyphs));
+        Ok(())
+    }
+
+    fn output_character(&mut self, trm: &Transform, width: f64, _spacing: f64, font_size: f64, char: &str) -> Result<(), OutputError> {
+        // Size of the font once scaled by the text matrix, y going down from the top of the page
+        let size = ((font_size * (trm.m11 + trm.m21)) * (font_size * (trm.m12 + trm.m22))).abs().sqrt();
+        let x = trm.m31;
+        self.glyphs.push(Glyph {
+            x,
+            end: x + width * size,
+            y: self.page_height - trm.m32,
+            size,
+            text: char.to_string(),
+        });
+        Ok(())
+    }
+
+    fn begin_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_word(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+
+    fn end_line(&mut self) -> Result<(), OutputError> {
+        Ok(())
+    }
+}
+
+/**
+ * Characters at the same height (half a font size) are on the same line, then split into cells by the wide gaps
+ */
+fn group_lines(mut glyphs: Vec<Glyph>) -> Vec<Line> {
+    glyphs.sort_by(|a, b| a.y.partial_cmp(&b.y).unwrap_or(std::cmp::Ordering::Equal));
+
+    let mut rows: Vec<Vec<Glyph>> = Vec::new();
+    for glyph in glyphs {
+        match rows.last_mut() {
+            Some(row) if (glyph.y - row[0].y).abs() <= row[0].size * 0.5 => row.push(glyph),
+            _ => rows.push(vec![glyph]),
+        }
+    }
+
+    rows.into_iter()
+        .map(|mut row| {
+            row.sort_by(|a, b| a.x.partial_cmp(&b.x).unwrap_or(std::cmp::Ordering::Equal));
+            let mut cells: Vec<Cell> = Vec::new();
+            let mut last_end = f64::MIN;
+            for glyph in row.into_iter().filter(|g| !g.text.trim().is_empty()) {
+                let gap = glyph.x - last_end;
+                match cells.last_mut() {
+                    Some(cell) if gap <= glyph.size * CELL_GAP => {
+                        if gap > glyph.size * WORD_GAP {
+                            cell.text.push(' ');
+                        }
+                        cell.text.push_str(&glyph.text);
+                        cell.end = glyph.end;
+                    }
+                    _ => cells.push(Cell { x: glyph.x, end: glyph.end, text: glyph.text.clone() }),
+                }
+                last_end = glyph.end;
+            }
+            Line { cells }
+        })
+        .filter(|line| !line.cells.is_empty())
+        .collect()
+}
+
+/**
+ * Lines of the text layer of a PDF, page after page
+ */
+pub fn extract_lines(bytes: &[u8]) -> anyhow::Result<Vec<Line>> {
+    let document = Document::load_mem(bytes)?;
+    if document.is_encrypted() {
+        return Err(anyhow::anyhow!("Encrypted PDF are not supported"));
+    }
+    let mut layout = TextLayout::default();
+    pdf_extract::output_doc(&document, &mut layout).map_err(|err| anyhow::anyhow!("Can not read the PDF text: {:?}", err))?;
+    Ok(layout.lines)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use crate::pdf::layout::{Cell, Glyph, Line, group_lines};
+
+    fn glyphs(text: &str, x: f64, y: f64) -> Vec<Glyph> {
+        // 10pt font, characters 5pt wide
+        text.chars()
+            .enumerate()
+            .map(|(i, c)| Glyph { x: x + 5.0 * i as f64, end: x + 5.0 * (i + 1) as f64, y, size: 10.0, text: c.to_string() })
+            .collect()
+    }
+
+    #[test]
+    fn test_group_lines() {
+        let mut all = glyphs("1 234,56", 300.0, 100.4);
+        all.extend(glyphs("Nouveau solde", 20.0, 100.0));
+        all.extend(glyphs("Total", 20.0, 120.0));
+
+        let lines = group_lines(all);
+        assert_eq!(lines.len(), 2, "Wrong count of lines");
+        assert_eq!(lines[0].cells.len(), 2, "The wide gap should split the line into cells");
+        assert_eq!(lines[0].cells[0].text, "Nouveau solde", "Wrong text of the first cell");
+        assert_eq!(lines[0].cells[1], Cell { x: 300.0, end: 340.0, text: "1 234,56".to_string() }, "Wrong amount cell");
+        assert_eq!(lines[1].text(), "Total", "Wrong second line");
+    }
+
+    #[test]
+    fn test_from_text() {
+        let line = Line::from_text("02/02  PRELEVEMENT DE EDF     45,00");
+        let texts: Vec<&str> = line.cells.iter().map(|c| c.text.as_str()).collect();
+        assert_eq!(texts, vec!["02/02", "PRELEVEMENT DE EDF", "45,00"], "Wrong cells");
+        assert_eq!(line.cells[2].end, 35.0, "Wrong end of the amount");
+    }
+}
@@ -0,0 +1,364 @@
+use chrono::NaiveDate;
+
+use crate::csv::profile::same_label;
+use crate::label::parsing::operation_date;
+use crate::models::{Account, AccountActivity, AccountBalance, ActivityDetails, BankingStatement};
+use crate::models::import::RejectedRow;
+use crate::money::Money;
+use crate::pdf::layout::{Cell, Line, extract_lines};
+
+/**
+ * Horizontal position of the amount columns, read from the header of the table of operations
+ */
+struct Columns {
+    statement_x: f64,
+    debit_end: f64,
+    credit_end: f64,
+}
+
+impl Columns {
+    /**
+     * Amounts are right aligned: the column is the one whose header ends the closest to the amount
+     */
+    fn signed_amount(&self, cell: &Cell) -> anyhow::Result<Money> {
+        let amount = parse_amount(&cell.text)?;
+        if (cell.end - self.debit_end).abs() <= (cell.end - self.credit_end).abs() {
+            Ok(-amount)
+        } else {
+            Ok(amount)
+        }
+    }
+}
+
+/**
+ * An operation of the table: the year of its date is only known at the end of the statement
+ */
+struct Operation {
+    line: usize,
+    content: String,
+    day: u32,
+    month: u32,
+    statement: String,
+    amount: Money,
+}
+
+/**
+ * The statement of an account being read, from its "Ancien solde" to its "Nouveau solde"
+ */
+struct Releve {
+    account: Account,
+    opening_balance: AccountBalance,
+    operations: Vec<Operation>,
+    rejected: Vec<RejectedRow>,
+}
+
+impl Releve {
+    fn into_banking_statement(self, closing_balance: AccountBalance, line: usize, content: String) -> BankingStatement {
+        let mut rejected = self.rejected;
+        let mut activities: Vec<AccountActivity> = Vec::new();
+        for operation in self.operations {
+            match operation_date(operation.day, operation.month, closing_balance.date) {
+                Some(date) => activities.push(AccountActivity {
+                    row_id: None,
+                    account_id: None,
+                    date,
+                    statement: operation.statement,
+                    amount: operation.amount,
+                    tag_pattern_id: None,
+                    tag_pattern_ids: Vec::new(),
+                    tag_overrides: Vec::new(),
+                    transaction_id: None,
+                    details: ActivityDetails::default(),
+                }),
+                None => rejected.push(RejectedRow {
+                    line: operation.line,
+                    content: operation.content,
+                    reason: format!("Invalid date {:02}/{:02}", operation.day, operation.month),
+                }),
+            }
+        }
+
+        // A debit read as a credit (or the other way) shows on the balance
+        let total: Money = activities.iter().map(|a| a.amount).sum();
+        let expected = closing_balance.balance_euro - self.opening_balance.balance_euro;
+        if rejected.is_empty() && total != expected {
+            rejected.push(RejectedRow {
+                line,
+                content,
+                reason: format!("Operations add up to {} instead of {}: an amount may be in the wrong column", total, expected),
+            });
+        }
+
+        BankingStatement {
+            row_id: None,
+            account: self.account,
+            balance: Some(closing_balance),
+            opening_balance: Some(self.opening_balance),
+            activities,
+            rejected,
+        }
+    }
+}
+
+/**
+ * Parse a French amount, ie. "1 234,56"
+ */
+fn parse_amount(value: &str) -> anyhow::Result<Money> {
+    Money::parse_with(value, ',', Some(' '))
+}
+
+fn is_amount(value: &str) -> bool {
+    value.contains(',') && value.chars().all(|c| c.is_ascii_digit() || c == ',' || c == ' ' || c == '.')
+}
+
+/**
+ * The text starts with the label, ignoring case and accents
+ */
+fn starts_with_label(text: &str, label: &str) -> bool {
+    let prefix: String = text.chars().take(label.chars().count()).collect();
+    same_label(&prefix, label)
+}
+
+/**
+ * The day and month of an operation ("dd/mm") at the start of a cell, with the rest of the cell
+ */
+fn split_day_month(text: &str) -> Option<(u32, u32, &str)> {
+    let (date, rest) = match text.split_once(' ') {
+        Some((date, rest)) => (date, rest.trim()),
+        None => (text, ""),
+    };
+    let (day, month) = date.split_once('/')?;
+    if day.len() != 2 || month.len() != 2 {
+        return None;
+    }
+    Some((day.parse().ok()?, month.parse().ok()?, rest))
+}
+
+/**
+ * "Ancien solde au 29/01/2021     1 234,56": date and amount of a balance line
+ */
+fn parse_balance(line: &Line, columns: &Columns) -> anyhow::Result<AccountBalance> {
+    let date = line.text()
+        .split(' ')
+        .find_map(|word| NaiveDate::parse_from_str(word, "%d/%m/%Y").ok())
+        .ok_or_else(|| anyhow::anyhow!("Missing date of the balance"))?;
+    let amount = line.cells.last()
+        .filter(|c| is_amount(&c.text))
+        .ok_or_else(|| anyhow::anyhow!("Missing amount of the balance"))?;
+    Ok(AccountBalance { row_id: None, account_id: None, date, balance_euro: columns.signed_amount(amount)? })
+}
+
+/**
+ * "CCP n° 12 345 67A 020": type and number (without spaces) of the account
+ */
+fn parse_account(text: &str) -> Option<Account> {
+    let (account_type, number) = text.split_once(" n° ").or_else(|| text.split_once(" N° "))?;
+    if starts_with_label(account_type, "Relevé") {
+        return None;
+    }
+
+    // The number is made of groups of digits and single letters (ie. the key), the owner may follow
+    let words: Vec<&str> = number.split(' ').collect();
+    let number: String = words
+        .iter()
+        .enumerate()
+        .take_while(|(i, w)| {
+            w.chars().any(|c| c.is_ascii_digit())
+                || (w.len() == 1 && words.get(i + 1).is_none_or(|next| next.chars().any(|c| c.is_ascii_digit())))
+        })
+        .map(|(_, w)| *w)
+        .collect();
+
+    if number.len() < 5 {
+        return None;
+    }
+    Some(Account { row_id: None, number, account_type: account_type.trim().to_string() })
+}
+
+/**
+ * Header of the table of operations: "Date  Opérations  Débit (¤)  Crédit (¤)"
+ */
+fn parse_columns(line: &Line) -> Option<Columns> {
+    let find = |label: &str| line.cells.iter().find(|c| starts_with_label(&c.text, label));
+    let debit = find("Débit")?;
+    let credit = find("Crédit")?;
+    let statement_x = find("Opérations").map_or(debit.x, |c| c.x);
+    Some(Columns { statement_x, debit_end: debit.end, credit_end: credit.end })
+}
+
+/**
+ * Parse the text lines of a La Banque Postale relevé: one banking statement per account.
+ * The operations are read between the "Ancien solde" and "Nouveau solde" lines, their sign from the Débit / Crédit column.
+ * Operations that can not be read are rejected, and so is a statement whose operations do not add up to its balances.
+ */
+pub fn parse_releve_lines(lines: &[Line]) -> anyhow::Result<Vec<BankingStatement>> {
+    let mut statements: Vec<BankingStatement> = Vec::new();
+    let mut account: Option<Account> = None;
+    let mut columns: Option<Columns> = None;
+    let mut releve: Option<Releve> = None;
+
+    for (index, line) in lines.iter().enumerate() {
+        let number = index + 1;
+        let text = line.text();
+
+        if let Some(header) = parse_columns(line) {
+            // Repeated on every page
+            columns = Some(header);
+            continue;
+        }
+
+        if starts_with_label(&text, "Ancien solde") {
+            let columns = columns.as_ref().ok_or_else(|| anyhow::anyhow!("Line {} : Débit / Crédit header not found", number))?;
+            let account = account.clone().ok_or_else(|| anyhow::anyhow!("Line {} : account number not found", number))?;
+            let opening_balance = parse_balance(line, columns).map_err(|err| anyhow::anyhow!("Line {} : {}", number, err))?;
+            releve = Some(Releve { account, opening_balance, operations: Vec::new(), rejected: Vec::new() });
+            continue;
+        }
+
+        if starts_with_label(&text, "Nouveau solde") {
+            if let (Some(current), Some(columns)) = (releve.take(), columns.as_ref()) {
+                let closing_balance = parse_balance(line, columns).map_err(|err| anyhow::anyhow!("Line {} : {}", number, err))?;
+                statements.push(current.into_banking_statement(closing_balance, number, text));
+            }
+            continue;
+        }
+
+        match (releve.as_mut(), columns.as_ref()) {
+            (Some(current), Some(columns)) => {
+                if starts_with_label(&text, "Total des opérations") {
+                    continue;
+                }
+                let first = match line.cells.first() {
+                    Some(first) => first,
+                    None => continue,
+                };
+
+                if let Some((day, month, rest)) = split_day_month(&first.text) {
+                    // The date and the statement may be close enough to be in the same cell
+                    let mut cells: Vec<&Cell> = line.cells.iter().skip(1).collect();
+                    let amount = match cells.last() {
+                        Some(cell) if is_amount(&cell.text) => cells.pop().map(|cell| columns.signed_amount(cell)),
+                        _ => None,
+                    };
+                    let statement = std::iter::once(rest)
+                        .chain(cells.iter().map(|c| c.text.as_str()))
+                        .filter(|s| !s.is_empty())
+                        .collect::<Vec<&str>>()
+                        .join(" ");
+
+                    match amount {
+                        Some(Ok(amount)) => current.operations.push(Operation { line: number, content: text, day, month, statement, amount }),
+                        Some(Err(err)) => current.rejected.push(RejectedRow { line: number, content: text, reason: err.to_string() }),
+                        None => current.rejected.push(RejectedRow { line: number, content: text, reason: "Missing amount".to_string() }),
+                    }
+                } else if first.x >= columns.statement_x - 1.0 && !line.cells.iter().any(|c| is_amount(&c.text)) {
+                    // The statement goes on the next lines
+                    if let Some(operation) = current.operations.last_mut() {
+                        operation.statement.push(' ');
+                        operation.statement.push_str(&text);
+                    }
+                }
+            }
+            _ => {
+                if let Some(found) = parse_account(&text) {
+                    account = Some(found);
+                }
+            }
+        }
+    }
+
+    if statements.is_empty() {
+        return Err(anyhow::anyhow!("No statement found: not a La Banque Postale relevé"));
+    }
+    Ok(statements)
+}
+
+/**
+ * Parse a La Banque Postale relevé from the text layer of the PDF
+ */
+pub fn parse_pdf(bytes: &[u8]) -> anyhow::Result<Vec<BankingStatement>> {
+    parse_releve_lines(&extract_lines(bytes)?)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+    use crate::money::Money;
+    use crate::pdf::layout::Line;
+    use crate::pdf::parsing::{parse_account, parse_releve_lines};
+
+    const RELEVE: &str = "
+Relevé n° 2 du 29 janvier 2021 au 26 février 2021
+CCP n° 12 345 67A 020   M JEAN DUPONT
+Vos opérations
+Date   Opérations                                        Débit (¤)      Crédit (¤)
+       Ancien solde au 29/01/2021                                         1 234,56
+02/02  PRELEVEMENT DE EDF                                    45,00
+       REF 0123456789
+03/02  VIREMENT DE M DUPONT                                                 1 500,00
+05/02  CARTE X1234 04/02 BOULANGERIE                           2,50
+05/02  CARTE X1234 04/02 BOULANGERIE                           2,50
+       Total des opérations                                  50,00         1 500,00
+       Nouveau solde au 26/02/2021                                          2 684,56
+Page 1/2
+Livret A n° 9876543210 K
+Date   Opérations                                        Débit (¤)      Crédit (¤)
+       Ancien solde au 31/12/2020                                           100,00
+31/12  INTERETS 2020                                                          0,75
+       Nouveau solde au 26/02/2021                                            100,75
+";
+
+    fn lines(text: &str) -> Vec<Line> {
+        text.lines().filter(|l| !l.trim().is_empty()).map(Line::from_text).collect()
+    }
+
+    #[test]
+    fn test_parse_releve() -> anyhow::Result<()> {
+        let statements = parse_releve_lines(&lines(RELEVE))?;
+        assert_eq!(statements.len(), 2, "One statement per account expected");
+
+        let ccp = &statements[0];
+        assert_eq!(ccp.account.number, "1234567A020", "Wrong account number");
+        assert_eq!(ccp.account.account_type, "CCP", "Wrong account type");
+        assert_eq!(ccp.opening_balance.as_ref().map(|b| b.balance_euro), Some(Money::from_cents(123456)), "Wrong opening balance");
+        assert_eq!(ccp.balance.as_ref().map(|b| b.date), Some(NaiveDate::from_ymd(2021, 2, 26)), "Wrong closing date");
+        assert!(ccp.rejected.is_empty(), "The operations should add up: {:?}", ccp.rejected);
+        assert_eq!(ccp.activities.len(), 4, "Identical operations should be kept");
+
+        let edf = &ccp.activities[0];
+        assert_eq!(edf.statement, "PRELEVEMENT DE EDF REF 0123456789", "The statement should go on the next line");
+        assert_eq!(edf.amount, Money::from_cents(-4500), "A debit should be negative");
+        assert_eq!(edf.date, NaiveDate::from_ymd(2021, 2, 2), "Wrong date");
+        assert_eq!(ccp.activities[1].amount, Money::from_cents(150000), "A credit should be positive");
+
+        let livret = &statements[1];
+        assert_eq!(livret.account.number, "9876543210K", "Wrong account number");
+        assert_eq!(livret.activities[0].date, NaiveDate::from_ymd(2020, 12, 31), "The operation is in the previous year");
+        Ok(())
+    }
+
+    #[test]
+    fn test_misread_column() -> anyhow::Result<()> {
+        // The credit is read in the debit column: the balance does not add up
+        let releve = RELEVE.replace(
+            "03/02  VIREMENT DE M DUPONT                                                 1 500,00",
+            "03/02  VIREMENT DE M DUPONT                              1 500,00"
+        );
+        let statements = parse_releve_lines(&lines(&releve))?;
+        assert_eq!(statements[0].rejected.len(), 1, "The statement should be rejected");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_account() {
+        assert!(parse_account("Relevé n° 2 du 29 janvier 2021").is_none(), "The relevé number is not an account");
+        assert_eq!(parse_account("CCP n° 12 345 67A 020   M JEAN DUPONT").map(|a| a.number), Some("1234567A020".to_string()), "The owner should not be in the number");
+    }
+
+    #[test]
+    fn test_not_a_releve() {
+        assert!(parse_releve_lines(&lines("Facture EDF\nMontant 45,00")).is_err(), "Other PDF should fail");
+    }
+}
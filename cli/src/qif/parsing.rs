@@ -0,0 +1,314 @@
+use chrono::NaiveDate;
+use std::path::Path;
+use anyhow;
+
+use crate::csv::encoding::decode;
+use crate::models::{Account, AccountActivity, ActivityDetails, BankingStatement};
+use crate::models::import::{QifDateOrder, RejectedRow};
+use crate::money::Money;
+
+/**
+ * QIF dates come as 26/03/2021, 26.03.21, 3/26'21 (the quote is used by Quicken for years >= 2000)...
+ * Whether the day or the month comes first can not be guessed, hence `date_order`.
+ */
+fn parse_date(value: &str, date_order: &QifDateOrder) -> anyhow::Result<NaiveDate> {
+    let parts: Vec<&str> = value
+        .split(['/', '.', '-', '\''])
+        .map(str::trim)
+        .collect();
+
+    let (first, second, year) = match parts.as_slice() {
+        [first, second, year] => (first.parse::<u32>()?, second.parse::<u32>()?, year.parse::<i32>()?),
+        _ => return Err(anyhow::anyhow!("Invalid QIF date '{}'", value)),
+    };
+    let year = match year {
+        y if y < 70 => 2000 + y,
+        y if y < 100 => 1900 + y,
+        y => y,
+    };
+    let (day, month) = match date_order {
+        QifDateOrder::Dmy => (first, second),
+        QifDateOrder::Mdy => (second, first),
+    };
+
+    NaiveDate::from_ymd_opt(year, month, day).ok_or_else(|| anyhow::anyhow!("Invalid QIF date '{}'", value))
+}
+
+/**
+ * Amounts are either 1,234.56 or 1 234,56 / 1.234,56 depending on the locale of the exporting software
+ */
+fn parse_amount(value: &str) -> anyhow::Result<Money> {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect();
+    let normalized = match (value.rfind(','), value.rfind('.')) {
+        (Some(comma), Some(dot)) if comma > dot => value.replace(".", "").replace(",", "."),
+        (Some(_), Some(_)) => value.replace(",", ""),
+        (Some(comma), None) if value.len() - comma <= 3 => value.replace(",", "."),
+        (Some(_), None) => value.replace(",", ""),
+        _ => value,
+    };
+    normalized.parse::<Money>()
+}
+
+#[derive(Default)]
+struct Record {
+    date: Option<NaiveDate>,
+    amount: Option<Money>,
+    payee: Option<String>,
+    memo: Option<String>,
+    category: Option<String>,
+    /**
+     * Index of the first line of the record
+     */
+    start: Option<usize>,
+    /**
+     * First value that could not be read
+     */
+    error: Option<String>,
+}
+
+impl Record {
+    fn set<T>(&mut self, value: anyhow::Result<T>) -> Option<T> {
+        match value {
+            Ok(value) => Some(value),
+            Err(err) => {
+                self.error.get_or_insert(err.to_string());
+                None
+            }
+        }
+    }
+
+    fn into_activity(self) -> anyhow::Result<AccountActivity> {
+        if let Some(error) = self.error {
+            return Err(anyhow::anyhow!(error));
+        }
+        let statement = match (self.payee, self.memo) {
+            (Some(payee), Some(memo)) if payee != memo => format!("{} {}", payee, memo),
+            (Some(payee), _) => payee,
+            (None, Some(memo)) => memo,
+            (None, None) => String::new(),
+        };
+        // [Account] categories are transfers between accounts, not a categorisation
+        let category = self.category.filter(|c| !c.starts_with('['));
+
+        Ok(AccountActivity {
+            row_id: None,
+            account_id: None,
+            date: self.date.ok_or_else(|| anyhow::anyhow!("Missing date for record '{}'", statement))?,
+            amount: self.amount.ok_or_else(|| anyhow::anyhow!("Missing amount for record '{}'", statement))?,
+            statement,
+            tag_pattern_id: None,
+            tag_pattern_ids: Vec::new(),
+            tag_overrides: Vec::new(),
+            transaction_id: None,
+            details: ActivityDetails { category, ..ActivityDetails::default() },
+        })
+    }
+}
+
+/**
+ * Parse the content (`bytes`) of the QIF file `qif_path`, whatever its encoding
+ */
+pub fn parse_qif<P: AsRef<Path>>(qif_path: P, bytes: &[u8], date_order: &QifDateOrder) -> anyhow::Result<Vec<BankingStatement>> {
+    let content = decode(bytes);
+    // Without !Account block, the file name is the only hint about the account
+    let default_account = qif_path.as_ref()
+        .file_stem()
+        .map(|s| s.to_string_lossy().to_string())
+        .unwrap_or_default();
+    parse_qif_content(&content, &default_account, date_order)
+}
+
+/**
+ * Parse the bank transactions (!Type:Bank, !Type:CCard, !Type:Cash) of a QIF document.
+ * QIF carries no balance: the statements returned have none.
+ * Records that can not be read are rejected.
+ */
+pub fn parse_qif_content(content: &str, default_account: &str, date_order: &QifDateOrder) -> anyhow::Result<Vec<BankingStatement>> {
+    let mut statements: Vec<BankingStatement> = Vec::new();
+    let mut account = Account { row_id: None, number: default_account.to_string(), account_type: String::new() };
+    let mut activities: Vec<AccountActivity> = Vec::new();
+    let mut rejected: Vec<RejectedRow> = Vec::new();
+    let mut record = Record::default();
+    let mut in_account_block = false;
+    let mut in_transactions = false;
+
+    let lines: Vec<&str> = content.lines().map(str::trim_end).collect();
+
+    for (index, line) in lines.iter().enumerate().filter(|(_, l)| !l.is_empty()) {
+        if line.starts_with('!') {
+            let header = line.to_lowercase();
+            if header == "!account" {
+                in_account_block = true;
+                in_transactions = false;
+            } else if let Some(kind) = header.strip_prefix("!type:") {
+                in_transactions = matches!(kind.trim(), "bank" | "ccard" | "cash");
+                if in_transactions && account.account_type.is_empty() {
+                    account.account_type = line[6..].trim().to_string();
+                }
+            }
+            continue;
+        }
+
+        let (code, value) = line.split_at(1);
+        let value = value.trim();
+
+        if in_account_block {
+            match code {
+                "N" => {
+                    // A new account: flush the transactions of the previous one
+                    if !activities.is_empty() || !rejected.is_empty() {
+                        statements.push(BankingStatement { 
+                            row_id: None, 
+                            account: account.clone(), 
+                            balance: None, 
+                            opening_balance: None, 
+                            activities: std::mem::take(&mut activities), 
+                            rejected: std::mem::take(&mut rejected) 
+                        });
+                    }
+                    account = Account { row_id: None, number: value.to_string(), account_type: String::new() };
+                }
+                "T" => account.account_type = value.to_string(),
+                "^" => in_account_block = false,
+                _ => (),
+            }
+            continue;
+        }
+
+        if !in_transactions {
+            continue;
+        }
+
+        record.start.get_or_insert(index);
+        match code {
+            "D" => record.date = record.set(parse_date(value, date_order)),
+            "T" | "U" => record.amount = record.set(parse_amount(value)),
+            "P" => record.payee = Some(value.to_string()),
+            "M" => record.memo = Some(value.to_string()),
+            "L" => record.category = Some(value.to_string()).filter(|c| !c.is_empty()),
+            "^" => {
+                let record = std::mem::take(&mut record);
+                let start = record.start.unwrap_or(index);
+                match record.into_activity() {
+                    Ok(activity) => { activities.push(activity); },
+                    Err(err) => rejected.push(RejectedRow {
+                        line: start + 1,
+                        content: lines[start..=index].join("\n"),
+                        reason: err.to_string(),
+                    }),
+                }
+            }
+            _ => (),
+        }
+    }
+
+    if !activities.is_empty() || !rejected.is_empty() {
+        statements.push(BankingStatement { row_id: None, account, balance: None, opening_balance: None, activities, rejected });
+    }
+    if statements.is_empty() {
+        return Err(anyhow::anyhow!("No bank transaction found in QIF document"));
+    }
+    Ok(statements)
+}
+
+
+#[cfg(test)]
+mod tests {
+
+    use chrono::NaiveDate;
+    use crate::money::Money;
+    use crate::models::import::QifDateOrder;
+    use crate::qif::parsing::{parse_amount, parse_date, parse_qif_content};
+
+    const QIF: &str = "!Type:Bank
+D26/03/2021
+T-20,00
+PFREE MOBILE
+LTelephone
+^
+D02/04/2021
+T-650.00
+PAGENCE IMMO
+MLoyer avril
+LLogement:Loyer
+^
+D03/04/2021
+T1,500.00
+PVIREMENT
+L[Livret A]
+^
+";
+
+    #[test]
+    fn test_parse_qif() -> anyhow::Result<()> {
+        let statements = parse_qif_content(QIF, "export", &QifDateOrder::Dmy)?;
+        assert_eq!(statements.len(), 1, "Wrong count of statements");
+
+        let statement = &statements[0];
+        assert_eq!(statement.account.number, "export", "Account should default to the file name");
+        assert!(statement.balance.is_none(), "QIF has no balance");
+        assert_eq!(statement.activities.len(), 3, "Wrong count of activities");
+
+        let rent = statement.activities.iter()
+            .find(|a| a.statement == "AGENCE IMMO Loyer avril")
+            .expect("Rent not found");
+        assert_eq!(rent.date, NaiveDate::from_ymd(2021, 4, 2), "Wrong date");
+        assert_eq!(rent.amount, Money::from_cents(-65000), "Wrong amount");
+        assert_eq!(rent.details.category.as_deref(), Some("Logement:Loyer"), "Wrong category");
+
+        let transfer = statement.activities.iter()
+            .find(|a| a.statement == "VIREMENT")
+            .expect("Transfer not found");
+        assert_eq!(transfer.details.category, None, "Transfers should not be used as category");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_qif_accounts() -> anyhow::Result<()> {
+        let content = "!Account\nN123456789\nTBank\n^\n!Type:Bank\nD01/02/2021\nT-10.00\nPA\n^\n!Account\nNLivret A\nTBank\n^\n!Type:Bank\nD01/02/2021\nT10.00\nPB\n^\n";
+        let statements = parse_qif_content(content, "export", &QifDateOrder::Dmy)?;
+
+        assert_eq!(statements.len(), 2, "One statement per account expected");
+        assert_eq!(statements[0].account.number, "123456789", "Wrong first account");
+        assert_eq!(statements[1].account.number, "Livret A", "Wrong second account");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_rejected_record() -> anyhow::Result<()> {
+        let content = QIF.replace("D02/04/2021", "D31/02/2021");
+        let statements = parse_qif_content(&content, "export", &QifDateOrder::Dmy)?;
+
+        let statement = &statements[0];
+        assert_eq!(statement.activities.len(), 2, "The other records should be kept");
+        assert_eq!(statement.rejected.len(), 1, "The invalid record should be rejected");
+        assert_eq!(statement.rejected[0].line, 7, "Wrong line of the rejected record");
+        assert_eq!(
+            statement.rejected[0].content,
+            "D31/02/2021\nT-650.00\nPAGENCE IMMO\nMLoyer avril\nLLogement:Loyer\n^",
+            "Wrong content of the rejected record"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_date_order() -> anyhow::Result<()> {
+        assert_eq!(parse_date("03/04/2021", &QifDateOrder::Dmy)?, NaiveDate::from_ymd(2021, 4, 3), "Wrong day/month date");
+        assert_eq!(parse_date("03/04/2021", &QifDateOrder::Mdy)?, NaiveDate::from_ymd(2021, 3, 4), "Wrong month/day date");
+        assert_eq!(parse_date("3/26'21", &QifDateOrder::Mdy)?, NaiveDate::from_ymd(2021, 3, 26), "Wrong Quicken date");
+        assert!(parse_date("26/03/2021", &QifDateOrder::Mdy).is_err(), "Invalid month should fail");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_amount() -> anyhow::Result<()> {
+        assert_eq!(parse_amount("-1,234.56")?, Money::from_cents(-123456), "Wrong amount with thousands comma");
+        assert_eq!(parse_amount("-1 234,56")?, Money::from_cents(-123456), "Wrong amount with decimal comma");
+        assert_eq!(parse_amount("1.234,56")?, Money::from_cents(123456), "Wrong amount with thousands dot");
+        assert_eq!(parse_amount("1,500")?, Money::from_cents(150000), "Wrong amount with thousands comma only");
+        Ok(())
+    }
+}
@@ -3,8 +3,8 @@ import ActivitiesTable from "./ActivitiesTable";
 
 
 type StatsJson = {
-    amount_plus: number,
-    amount_minus: number
+    amount_plus: string,
+    amount_minus: string
 }
 type ActivitiesPerMonthJson = {
     month_index : number,
@@ -16,13 +16,20 @@ type ActivityDetailsJson = {
     row_id: number,
     date: string,
     statement: string,
-    amount: number,
-    tag_pattern_id: number | null
+    amount: string,
+    tag_pattern_id: number | null,
+    tag_pattern_ids: number[],
+    tag_overrides: TagOverrideJson[]
+};
+
+type TagOverrideJson = {
+    tag: string,
+    assigned: boolean
 };
 
 type BalanceJson = {
     date: string,
-    amount: number
+    amount: string
 };
 
 type TagsJson = {
@@ -17,8 +17,8 @@ const monthByIndex = [
 type ActivitiesPerMonthJson = {
     month_index: number,
     stats: {
-        amount_plus: number,
-        amount_minus: number
+        amount_plus: string,
+        amount_minus: string
     },
     activities: ActivityDetailsJson[]
 }
@@ -27,13 +27,20 @@ type ActivityDetailsJson = {
     row_id: number,
     date: string,
     statement: string,
-    amount: number,
-    tag_pattern_id: number | null
+    amount: string,
+    tag_pattern_id: number | null,
+    tag_pattern_ids: number[],
+    tag_overrides: TagOverrideJson[]
+};
+
+type TagOverrideJson = {
+    tag: string,
+    assigned: boolean
 };
 
 type BalanceJson = {
     date: string,
-    amount: number
+    amount: string
 };
 
 type TagsJson = {
@@ -78,18 +85,21 @@ const ActivitiesTable = (props: ActivitiesTableProps) => {
         }
     }
 
+    // Tags of all the rules applied to an activity, then the ones given or removed by hand
+    const tagsOf = (activity: ActivityDetailsJson) => {
+        const ruleTags = tags ? activity.tag_pattern_ids.flatMap(id => id in tags ? tags[id] : []) : [];
+        const removed = activity.tag_overrides.filter(o => !o.assigned).map(o => o.tag);
+        const given = activity.tag_overrides.filter(o => o.assigned).map(o => o.tag);
+        return Array.from(new Set(ruleTags.filter(t => !removed.includes(t)).concat(given)));
+    }
+
     const updateSearchResult = (activitiesJson: ActivitiesPerMonthJson[]) => (e: React.FormEvent<HTMLInputElement>) => {
         const searchPattern = e.currentTarget.value.toLowerCase();
-        const checkPatternInTags = (tagPatternId: number | null, searchPattern: string) => {
-            if (tagPatternId == null && check_null_search(searchPattern)) {
-                return true;
-            } else if (tagPatternId == null) {
-                return false;
-            } else if (tags && tagPatternId in tags) {
-                return tags[tagPatternId].filter(e => e.toString().toLowerCase().includes(searchPattern)).length > 0;
-            } else {
-                return false;
+        const checkPatternInTags = (activity: ActivityDetailsJson, searchPattern: string) => {
+            if (tagsOf(activity).length === 0) {
+                return check_null_search(searchPattern);
             }
+            return tagsOf(activity).filter(e => e.toString().toLowerCase().includes(searchPattern)).length > 0;
         }
         const updated = activitiesJson.map(e => {
             const newActivities = e.activities
@@ -97,7 +107,7 @@ const ActivitiesTable = (props: ActivitiesTableProps) => {
                     a => a.amount.toString().toLowerCase().includes(searchPattern) ||
                         a.date.toString().toLowerCase().includes(searchPattern) ||
                         a.statement.toLowerCase().includes(searchPattern) ||
-                        checkPatternInTags(a.tag_pattern_id, searchPattern)
+                        checkPatternInTags(a, searchPattern)
                 )
 
             return { month_index: e.month_index, stats: e.stats, activities: newActivities }
@@ -144,14 +154,14 @@ const ActivitiesTable = (props: ActivitiesTableProps) => {
                                                 <ActivityDetailsMonthly key={activity.row_id} className={toggleRowStyle(activity.date)}
                                                     date={formatDate(activity.date)} statement={activity.statement}
                                                     amount={activity.amount}
-                                                    statsPlus={`+${activitiesPerMonth.stats.amount_plus.toFixed(2)}`}
-                                                    statsMinus={`${activitiesPerMonth.stats.amount_minus.toFixed(2)}`}
+                                                    statsPlus={`+${activitiesPerMonth.stats.amount_plus}`}
+                                                    statsMinus={`${activitiesPerMonth.stats.amount_minus}`}
                                                     month={monthByIndex[activitiesPerMonth.month_index - 1]}
-                                                    tags={tags && activity.tag_pattern_id && activity.tag_pattern_id in tags ? tags[activity.tag_pattern_id] : []} />
+                                                    tags={tagsOf(activity)} />
                                                 :
                                                 <ActivityDetails key={activity.row_id} className={toggleRowStyle(activity.date)}
                                                     date={formatDate(activity.date)} statement={activity.statement}
-                                                    amount={activity.amount} tags={tags && activity.tag_pattern_id && activity.tag_pattern_id in tags ? tags[activity.tag_pattern_id] : []} />
+                                                    amount={activity.amount} tags={tagsOf(activity)} />
                                         )
                                     )}
                             </tbody>
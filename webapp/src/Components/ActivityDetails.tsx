@@ -6,7 +6,7 @@ type ActivityDetailsProps = {
     className: string,
     date: string,
     statement: string,
-    amount: number,
+    amount: string,
     tags: string[]
 };
 
@@ -17,7 +17,7 @@ type ActivityDetailsMonthlyProps = {
     className: string,
     date: string,
     statement: string,
-    amount: number,
+    amount: string,
     tags: string[]
 };
 
@@ -29,7 +29,7 @@ const ActivityDetails: React.FC<ActivityDetailsProps> = ( activity : ActivityDet
             <td>{activity.date}</td>
             <td>{activity.statement}</td>
             <td>{activity.tags.join(', ')}</td>
-            <td  className={ activity.amount >= 0 ? index.amountPlus : index.amountMinus }>{activity.amount.toFixed(2)}</td>
+            <td  className={ Number(activity.amount) >= 0 ? index.amountPlus : index.amountMinus }>{activity.amount}</td>
         </tr>
     );
 };
@@ -41,7 +41,7 @@ const ActivityDetailsMonthly: React.FC<ActivityDetailsMonthlyProps> = ( activity
             <td>{activity.date}</td>
             <td>{activity.statement}</td>
             <td>{activity.tags.join(', ')}</td>
-            <td className={ activity.amount >= 0 ? index.amountPlus : index.amountMinus }>{activity.amount.toFixed(2)}</td>
+            <td className={ Number(activity.amount) >= 0 ? index.amountPlus : index.amountMinus }>{activity.amount}</td>
         </tr>
     );
 };
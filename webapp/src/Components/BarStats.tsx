@@ -4,7 +4,7 @@ import { monthByIndex } from "./ActivitiesTable";
 
  
 type StatsDataJson = {
-    amount: number,
+    amount: string,
     month: number
 }
 
@@ -34,7 +34,7 @@ const BarStats = (props: StatsProps) => {
                 labels: stats?.data.map(m => monthByIndex[m.month - 1]),
                 datasets: [{
                     label: `Depense en € pour tag ${stats?.tags.join(', ')}`,
-                    data: stats?.data.map(m => m.amount)
+                    data: stats?.data.map(m => Number(m.amount))
                 }]
             }}
             width={ 300 }
@@ -4,7 +4,7 @@ import index from "../index.css";
  
  
 type StatsDataJson = {
-    amount: number,
+    amount: string,
     month: number
 }
 
@@ -9,6 +9,7 @@ const Header = () => {
             <li><Link to="/">Activities</Link></li>
             <li><Link to="/stats/per_month">Stats Per Month</Link></li>
             <li><Link to="/tags/pattern">Tag Patterns</Link></li>
+            <li><Link to="/import">Import</Link></li>
           </ul>
         </div>
     )
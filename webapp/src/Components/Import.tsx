@@ -0,0 +1,94 @@
+import React, { useState } from "react";
+
+type RejectedRowJson = {
+    line: number,
+    content: string,
+    reason: string
+}
+
+type FileReportJson = {
+    path: string,
+    member: string | null,
+    rows_inserted: number,
+    rows_skipped: number,
+    rejected: RejectedRowJson[]
+}
+
+type FailedFileJson = {
+    path: string,
+    reason: string
+}
+
+type ImportReportJson = {
+    files: FileReportJson[],
+    skipped_files: string[],
+    failed_files: FailedFileJson[]
+}
+
+
+const Import = () => {
+
+    const [files, setFiles] = useState<FileList | null>(null)
+    const [report, setReport] = useState<ImportReportJson>(undefined)
+    const [error, setError] = useState<string>(undefined)
+
+    const upload = (event: React.FormEvent) => {
+        event.preventDefault()
+        if (files == null || files.length === 0) {
+            return
+        }
+
+        const form = new FormData()
+        Array.from(files).forEach(file => form.append("statements", file, file.name))
+
+        setError(undefined)
+        fetch("http://localhost:3030/api/import", { method: 'POST', mode: 'cors', body: form })
+            .then(response => response.ok ? response.json() : Promise.reject(response.statusText))
+            .then(data => setReport(data))
+            .catch(err => setError(`Import failed: ${err}`))
+    }
+
+    return (
+        <div>
+            <form onSubmit={upload}>
+                <input type="file" multiple accept=".csv,.ofx,.qfx,.qif,.xml,.pdf,.zip,.tar.gz,.tgz"
+                    onChange={e => setFiles(e.target.files)} />
+                <button type="submit">Import</button>
+            </form>
+            { error && <h3>{error}</h3> }
+            { report &&
+            <table>
+                <tbody>
+                    <tr>
+                        <th>File</th>
+                        <th>Inserted</th>
+                        <th>Duplicates</th>
+                        <th>Errors</th>
+                    </tr>
+                    { report.files.map(f =>
+                        <tr key={`${f.path}:${f.member}`}>
+                            <td>{ f.member ? `${f.path}:${f.member}` : f.path }</td>
+                            <td>{ f.rows_inserted }</td>
+                            <td>{ f.rows_skipped }</td>
+                            <td>{ f.rejected.map(r => `line ${r.line}: ${r.reason}`).join(", ") }</td>
+                        </tr>
+                    )}
+                    { report.skipped_files.map(f =>
+                        <tr key={f}>
+                            <td>{ f }</td>
+                            <td colSpan={3}>Already imported</td>
+                        </tr>
+                    )}
+                    { report.failed_files.map(f =>
+                        <tr key={f.path}>
+                            <td>{ f.path }</td>
+                            <td colSpan={3}>{ f.reason }</td>
+                        </tr>
+                    )}
+                </tbody>
+            </table>
+            }
+        </div>)
+};
+
+export default Import;
@@ -3,6 +3,7 @@ import { Route, HashRouter } from "react-router-dom";
 import Activities from "./Activities";
 import Graph from "./Graph";
 import Header from "./Header";
+import Import from "./Import";
 import TagPattern from "./TagPattern";
 
 const Layout = () => {
@@ -13,6 +14,7 @@ const Layout = () => {
             <Route exact path="/" component={Activities} />    
             <Route path="/stats/per_month" component={Graph} />    
             <Route path="/tags/pattern" component={TagPattern} /> 
+            <Route path="/import" component={Import} />
           </div>
         </div>
     )
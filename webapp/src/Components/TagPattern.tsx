@@ -1,34 +1,55 @@
 import React, { useState, useEffect } from "react";
-import TagPatternList from "./TagPatternList";
-
-type TagPatternJson = {
-    pattern: string,
-    tags: string[]
-}
+import TagPatternList, { TagPatternJson, TagPatternDefinition } from "./TagPatternList";
 
 
 const TagPattern = () => {
 
     let [tagPatterns, setTagPatterns] = useState<TagPatternJson[]>(undefined)
+    let [error, setError] = useState<string>(undefined)
 
-    useEffect(() => {
+    const load = () =>
         fetch("http://localhost:3030/api/tags/pattern", { mode: 'cors' })
             .then(response => response.json())
             .then(data => {
                 setTagPatterns(data)
             })
+
+    useEffect(() => {
+        load()
     }, [])
 
+    // The activities are retagged by the server, the patterns are read again
+    const send = (request: Promise<Response>) => {
+        setError(undefined)
+        request
+            .then(response => response.ok ? response.json() : response.text().then(text => Promise.reject(text)))
+            .then(() => load())
+            .catch(err => setError(`Saving failed: ${err}`))
+    }
+
+    const save = (id: number | undefined, definition: TagPatternDefinition) => send(
+        fetch(`http://localhost:3030/api/tags/pattern${id === undefined ? "" : `/${id}`}`, {
+            method: id === undefined ? 'POST' : 'PUT',
+            mode: 'cors',
+            headers: { 'content-type': 'application/json' },
+            body: JSON.stringify(definition)
+        })
+    )
+
+    const remove = (id: number) => send(
+        fetch(`http://localhost:3030/api/tags/pattern/${id}`, { method: 'DELETE', mode: 'cors' })
+    )
 
     return (
         <div>
+            { error && <h3>{error}</h3> }
             {tagPatterns
                 ?
-                <TagPatternList patterns={tagPatterns} />
+                <TagPatternList patterns={tagPatterns} onSave={save} onDelete={remove} />
                 :
                 <h3>Error loading page: can not reach data source</h3>
             }
         </div>)
 };
 
-export default TagPattern;
\ No newline at end of file
+export default TagPattern;
@@ -1,42 +1,150 @@
-import React, { useState, useEffect } from "react";
+import React, { useState } from "react";
 
 type TagPatternJson = {
+    id: number,
     pattern: string,
+    match_kind: string,
+    rule: object | null,
+    priority: number,
+    stop: boolean,
+    category: string | null,
     tags: string[]
 }
 
+type TagPatternDefinition = Omit<TagPatternJson, "id">
+
 type TagPatternProps = {
-    patterns : TagPatternJson[]
+    patterns : TagPatternJson[],
+    onSave? : (id: number | undefined, definition: TagPatternDefinition) => void,
+    onDelete? : (id: number) => void
+}
+
+type TagPatternFormProps = {
+    pattern : TagPatternDefinition,
+    onSave : (definition: TagPatternDefinition) => void,
+    onCancel : () => void
+}
+
+const MATCH_KINDS = ["substring", "word", "prefix", "regex"]
+
+const NEW_PATTERN: TagPatternDefinition = {
+    pattern: "",
+    match_kind: "substring",
+    rule: null,
+    priority: 0,
+    stop: false,
+    category: null,
+    tags: []
+}
+
+const TagPatternForm = (props: TagPatternFormProps) => {
+
+    const [pattern, setPattern] = useState(props.pattern.pattern)
+    const [matchKind, setMatchKind] = useState(props.pattern.match_kind)
+    const [rule, setRule] = useState(props.pattern.rule ? JSON.stringify(props.pattern.rule) : "")
+    const [tags, setTags] = useState(props.pattern.tags.join(", "))
+    const [category, setCategory] = useState(props.pattern.category ?? "")
+    const [priority, setPriority] = useState(props.pattern.priority)
+    const [stop, setStop] = useState(props.pattern.stop)
+    const [error, setError] = useState<string>(undefined)
+
+    const save = (event: React.FormEvent) => {
+        event.preventDefault()
+        let parsedRule: object | null = null
+        try {
+            parsedRule = rule.trim() ? JSON.parse(rule) : null
+        } catch (err) {
+            setError(`Invalid rule: ${err}`)
+            return
+        }
+        props.onSave({
+            pattern: pattern,
+            match_kind: matchKind,
+            rule: parsedRule,
+            priority: priority,
+            stop: stop,
+            category: category.trim() || null,
+            tags: tags.split(",").map(t => t.trim()).filter(Boolean)
+        })
+    }
+
+    return (
+        <tr>
+            <td colSpan={4}>
+                <form onSubmit={save}>
+                    <input placeholder="Pattern" value={pattern} onChange={e => setPattern(e.target.value)} />
+                    <select value={matchKind} onChange={e => setMatchKind(e.target.value)}>
+                        { MATCH_KINDS.map(kind => <option key={kind} value={kind}>{kind}</option>) }
+                    </select>
+                    <input placeholder="Rule (JSON)" value={rule} onChange={e => setRule(e.target.value)} />
+                    <input placeholder="Tags, comma separated" value={tags} onChange={e => setTags(e.target.value)} />
+                    <input placeholder="Category" value={category} onChange={e => setCategory(e.target.value)} />
+                    <input type="number" title="Priority" value={priority} onChange={e => setPriority(Number(e.target.value))} />
+                    <label>
+                        <input type="checkbox" checked={stop} onChange={e => setStop(e.target.checked)} /> stop
+                    </label>
+                    <button type="submit">Save</button>
+                    <button type="button" onClick={props.onCancel}>Cancel</button>
+                    { error && <span>{error}</span> }
+                </form>
+            </td>
+        </tr>
+    )
 }
 
 const TagPatternList = (props: TagPatternProps) => {
 
-    const [tagPatterns, setTagPatterns] = useState<TagPatternJson[]>(props.patterns)
+    // Id of the pattern edited, "new" for a pattern to add
+    const [editing, setEditing] = useState<number | "new">(undefined)
+
+    const save = (id: number | undefined) => (definition: TagPatternDefinition) => {
+        setEditing(undefined)
+        props.onSave && props.onSave(id, definition)
+    }
 
     return (
         <div>
-        {   tagPatterns 
-        
-        ?            
+        {   props.patterns
+
+        ?
             <table>
                 <tbody>
                     <tr>
                         <th>Pattern</th>
+                        <th>Match</th>
                         <th>tag</th>
+                        <th></th>
                     </tr>
-                    { tagPatterns.map(tp => 
-                        <tr key={tp.pattern}>
-                            
-                            <td>{ tp.pattern }</td>
+                    { props.patterns.map(tp => editing === tp.id
+                        ?
+                        <TagPatternForm key={tp.id} pattern={tp} onSave={save(tp.id)} onCancel={() => setEditing(undefined)} />
+                        :
+                        <tr key={tp.id}>
+
+                            <td>{ [tp.pattern, tp.rule && JSON.stringify(tp.rule)].filter(Boolean).join(" and ") }</td>
+                            <td>{ tp.match_kind }</td>
                             <td>{ tp.tags.join(", ")}</td>
+                            <td>
+                                <button onClick={() => setEditing(tp.id)}>Edit</button>
+                                <button onClick={() => props.onDelete && props.onDelete(tp.id)}>Delete</button>
+                            </td>
                         </tr>
                     )}
-                </tbody>      
-            </table>    
+                    { editing === "new"
+                        ?
+                        <TagPatternForm pattern={NEW_PATTERN} onSave={save(undefined)} onCancel={() => setEditing(undefined)} />
+                        :
+                        <tr>
+                            <td><button onClick={() => setEditing("new")}>New pattern</button></td>
+                        </tr>
+                    }
+                </tbody>
+            </table>
         :
             <h3>Error loading page: can not reach data source</h3>
         }
     </div>)
 };
 
-export default TagPatternList;
\ No newline at end of file
+export default TagPatternList;
+export type { TagPatternJson, TagPatternDefinition };